@@ -36,6 +36,7 @@ use super::Com;
 use super::MachineProof;
 use super::PcsProverData;
 use super::Prover;
+use super::ShardComponent;
 use super::StarkGenericConfig;
 use super::Val;
 use super::VerificationError;
@@ -411,6 +412,42 @@ impl<SC: StarkGenericConfig, A: MachineAir<Val<SC>>> StarkMachine<SC, A> {
         }
     }
 
+    /// Checks a single component of the proof of shard `shard`, to find out which stage of its
+    /// verification fails.
+    ///
+    /// `challenger` must be in its initial state, as for [`Self::verify`]. Only the shard proof is
+    /// checked: neither the transitions between shards nor the cumulative sum are.
+    pub fn verify_shard_component(
+        &self,
+        vk: &StarkVerifyingKey<SC>,
+        proof: &MachineProof<SC>,
+        challenger: &mut SC::Challenger,
+        shard: usize,
+        component: ShardComponent,
+    ) -> Result<(), VerificationError<SC>>
+    where
+        A: for<'a> Air<VerifierConstraintFolder<'a, SC>>,
+    {
+        vk.observe_into(challenger);
+        proof.shard_proofs.iter().for_each(|proof| {
+            challenger.observe(proof.commitment.main_commit.clone());
+            challenger.observe_slice(&proof.public_values[0..self.num_pv_elts()]);
+        });
+
+        let shard_proof = &proof.shard_proofs[shard];
+        let chips = self
+            .shard_chips_ordered(&shard_proof.chip_ordering)
+            .collect::<Vec<_>>();
+        Verifier::verify_shard_component(
+            &self.config,
+            vk,
+            &chips,
+            challenger,
+            shard_proof,
+            component,
+        )
+    }
+
     pub fn debug_constraints(
         &self,
         pk: &StarkProvingKey<SC>,
@@ -568,6 +605,8 @@ impl<SC: StarkGenericConfig> std::error::Error for ProgramVerificationError<SC>
 #[allow(non_snake_case)]
 pub mod tests {
 
+    use p3_field::AbstractField;
+
     use crate::runtime::tests::fibonacci_program;
     use crate::runtime::tests::simple_memory_program;
     use crate::runtime::tests::simple_program;
@@ -575,9 +614,15 @@ pub mod tests {
     use crate::runtime::Instruction;
     use crate::runtime::Opcode;
     use crate::runtime::Program;
+    use crate::stark::MachineProof;
+    use crate::stark::RiscvAir;
+    use crate::stark::ShardComponent;
+    use crate::stark::StarkGenericConfig;
+    use crate::stark::VerificationError;
     use crate::utils;
     use crate::utils::run_test;
     use crate::utils::setup_logger;
+    use crate::utils::BabyBearBlake3;
 
     #[test]
     fn test_simple_prove() {
@@ -586,6 +631,60 @@ pub mod tests {
         run_test(program).unwrap();
     }
 
+    #[test]
+    fn test_invalid_log_degree_rejected() {
+        utils::setup_logger();
+        let program = simple_program();
+        let mut proof = run_test(program.clone()).unwrap();
+        proof.shard_proofs[0].opened_values.chips[0].log_degree = 64;
+
+        let machine = RiscvAir::machine(BabyBearBlake3::new());
+        let (_, vk) = machine.setup(&program);
+        let mut challenger = machine.config().challenger();
+        assert!(machine.verify(&vk, &proof, &mut challenger).is_err());
+    }
+
+    #[test]
+    fn test_verify_shard_component() {
+        utils::setup_logger();
+        let program = simple_program();
+        let proof = run_test(program.clone()).unwrap();
+        let machine = RiscvAir::machine(BabyBearBlake3::new());
+        let (_, vk) = machine.setup(&program);
+        let check = |proof: &MachineProof<BabyBearBlake3>, component| {
+            let mut challenger = machine.config().challenger();
+            machine.verify_shard_component(&vk, proof, &mut challenger, 0, component)
+        };
+
+        for component in [
+            ShardComponent::Shape,
+            ShardComponent::Openings,
+            ShardComponent::Constraints,
+        ] {
+            assert!(check(&proof, component).is_ok());
+        }
+
+        // A wrong quotient commitment is caught by the opening proof.
+        let mut wrong_commitment = proof.clone();
+        let commitment = &mut wrong_commitment.shard_proofs[0].commitment;
+        commitment.quotient_commit = commitment.main_commit.clone();
+        assert!(check(&wrong_commitment, ShardComponent::Shape).is_ok());
+        assert!(matches!(
+            check(&wrong_commitment, ShardComponent::Openings),
+            Err(VerificationError::InvalidopeningArgument(_))
+        ));
+
+        // A wrong quotient opening no longer matches the constraints at the out-of-domain point.
+        let mut wrong_quotient = proof;
+        wrong_quotient.shard_proofs[0].opened_values.chips[0].quotient[0][0] +=
+            <BabyBearBlake3 as StarkGenericConfig>::Challenge::one();
+        assert!(check(&wrong_quotient, ShardComponent::Shape).is_ok());
+        assert!(matches!(
+            check(&wrong_quotient, ShardComponent::Constraints),
+            Err(VerificationError::OodEvaluationMismatch(_))
+        ));
+    }
+
     #[test]
     fn test_shift_prove() {
         utils::setup_logger();
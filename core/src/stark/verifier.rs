@@ -35,6 +35,50 @@ impl<SC: StarkGenericConfig, A: MachineAir<Val<SC>>> Verifier<SC, A> {
         challenger: &mut SC::Challenger,
         proof: &ShardProof<SC>,
     ) -> Result<(), VerificationError<SC>>
+    where
+        A: for<'a> Air<VerifierConstraintFolder<'a, SC>>,
+    {
+        Self::verify_shard_components(
+            config,
+            vk,
+            chips,
+            challenger,
+            proof,
+            &[
+                ShardComponent::Shape,
+                ShardComponent::Openings,
+                ShardComponent::Constraints,
+            ],
+        )
+    }
+
+    /// Runs only the checks of `verify_shard` that make up `component`, to find out which stage
+    /// of the verification of a shard fails.
+    ///
+    /// The challenges are derived from the commitments in the same way as in `verify_shard`, so
+    /// `challenger` must be in the state it would be in before verifying the shard.
+    pub fn verify_shard_component(
+        config: &SC,
+        vk: &StarkVerifyingKey<SC>,
+        chips: &[&MachineChip<SC, A>],
+        challenger: &mut SC::Challenger,
+        proof: &ShardProof<SC>,
+        component: ShardComponent,
+    ) -> Result<(), VerificationError<SC>>
+    where
+        A: for<'a> Air<VerifierConstraintFolder<'a, SC>>,
+    {
+        Self::verify_shard_components(config, vk, chips, challenger, proof, &[component])
+    }
+
+    fn verify_shard_components(
+        config: &SC,
+        vk: &StarkVerifyingKey<SC>,
+        chips: &[&MachineChip<SC, A>],
+        challenger: &mut SC::Challenger,
+        proof: &ShardProof<SC>,
+        components: &[ShardComponent],
+    ) -> Result<(), VerificationError<SC>>
     where
         A: for<'a> Air<VerifierConstraintFolder<'a, SC>>,
     {
@@ -62,6 +106,23 @@ impl<SC: StarkGenericConfig, A: MachineAir<Val<SC>>> Verifier<SC, A> {
             .map(|chip| chip.log_quotient_degree())
             .collect::<Vec<_>>();
 
+        // The trace heights are claimed as logarithms, so they are always powers of two, but the
+        // domain sizes computed from them must not overflow.
+        for (chip, (log_degree, log_quotient_degree)) in chips
+            .iter()
+            .zip_eq(log_degrees.iter().zip_eq(log_quotient_degrees.iter()))
+        {
+            if log_degree + log_quotient_degree >= usize::BITS as usize {
+                return Err(VerificationError::OpeningShapeError(
+                    chip.name(),
+                    OpeningShapeError::LogDegreeTooLarge(
+                        usize::BITS as usize - 1 - log_quotient_degree,
+                        *log_degree,
+                    ),
+                ));
+            }
+        }
+
         let trace_domains = log_degrees
             .iter()
             .map(|log_degree| pcs.natural_domain_for_degree(1 << log_degree))
@@ -159,19 +220,37 @@ impl<SC: StarkGenericConfig, A: MachineAir<Val<SC>>> Verifier<SC, A> {
             })
             .collect::<Vec<_>>();
 
-        config
-            .pcs()
-            .verify(
-                vec![
-                    (vk.commit.clone(), preprocessed_domains_points_and_opens),
-                    (main_commit.clone(), main_domains_points_and_opens),
-                    (permutation_commit.clone(), perm_domains_points_and_opens),
-                    (quotient_commit.clone(), quotient_domains_points_and_opens),
-                ],
-                opening_proof,
-                challenger,
-            )
-            .map_err(|e| VerificationError::InvalidopeningArgument(e))?;
+        // Verify the shape of the opening arguments matches the expected values. The constraints
+        // can only be evaluated on openings of the right shape, so this is also checked before
+        // them.
+        if components.contains(&ShardComponent::Shape)
+            || components.contains(&ShardComponent::Constraints)
+        {
+            for (chip, values) in chips.iter().zip_eq(opened_values.chips.iter()) {
+                Self::verify_opening_shape(chip, values)
+                    .map_err(|e| VerificationError::OpeningShapeError(chip.name(), e))?;
+            }
+        }
+
+        if components.contains(&ShardComponent::Openings) {
+            config
+                .pcs()
+                .verify(
+                    vec![
+                        (vk.commit.clone(), preprocessed_domains_points_and_opens),
+                        (main_commit.clone(), main_domains_points_and_opens),
+                        (permutation_commit.clone(), perm_domains_points_and_opens),
+                        (quotient_commit.clone(), quotient_domains_points_and_opens),
+                    ],
+                    opening_proof,
+                    challenger,
+                )
+                .map_err(|e| VerificationError::InvalidopeningArgument(e))?;
+        }
+
+        if !components.contains(&ShardComponent::Constraints) {
+            return Ok(());
+        }
 
         // Verify the constrtaint evaluations.
 
@@ -181,9 +260,6 @@ impl<SC: StarkGenericConfig, A: MachineAir<Val<SC>>> Verifier<SC, A> {
             quotient_chunk_domains,
             opened_values.chips.iter(),
         ) {
-            // Verify the shape of the opening arguments matches the expected values.
-            Self::verify_opening_shape(chip, values)
-                .map_err(|e| VerificationError::OpeningShapeError(chip.name(), e))?;
             // Verify the constraint evaluation.
             Self::verify_constraints(
                 chip,
@@ -392,12 +468,27 @@ impl<SC: StarkGenericConfig, A: MachineAir<Val<SC>>> Verifier<SC, A> {
 
 pub struct OodEvaluationMismatch;
 
+/// A stage of the verification of a shard proof, which can be checked on its own with
+/// [`Verifier::verify_shard_component`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardComponent {
+    /// The dimensions of the opened values match the widths of the chips.
+    Shape,
+    /// The opening proof shows that the opened values are evaluations of the committed main,
+    /// permutation and quotient traces.
+    Openings,
+    /// The constraints evaluated on the opened values agree with the opened quotient at the
+    /// out-of-domain point.
+    Constraints,
+}
+
 pub enum OpeningShapeError {
     PreprocessedWidthMismatch(usize, usize),
     MainWidthMismatch(usize, usize),
     PermutationWidthMismatch(usize, usize),
     QuotientWidthMismatch(usize, usize),
     QuotientChunkSizeMismatch(usize, usize),
+    LogDegreeTooLarge(usize, usize),
 }
 
 pub enum VerificationError<SC: StarkGenericConfig> {
@@ -449,6 +540,13 @@ impl Debug for OpeningShapeError {
                     expected, actual
                 )
             }
+            OpeningShapeError::LogDegreeTooLarge(max, actual) => {
+                write!(
+                    f,
+                    "Log degree too large: expected at most {}, got {}",
+                    max, actual
+                )
+            }
         }
     }
 }
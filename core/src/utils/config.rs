@@ -87,17 +87,89 @@ pub fn inner_fri_config() -> FriConfig<InnerChallengeMmcs> {
     }
 }
 
+/// The FRI config for inner recursion proofs that are cheap to verify in a recursion circuit.
+///
+/// The cost of verifying a proof in a circuit is dominated by the FRI queries, so this config
+/// trades a larger blowup, and so a slower prover, for fewer queries at a similar security.
+pub fn inner_compact_fri_config() -> FriConfig<InnerChallengeMmcs> {
+    let perm = inner_perm();
+    let hash = InnerHash::new(perm.clone());
+    let compress = InnerCompress::new(perm.clone());
+    let challenge_mmcs = InnerChallengeMmcs::new(InnerValMmcs::new(hash, compress));
+    let num_queries = match std::env::var("FRI_QUERIES") {
+        Ok(value) => value.parse().unwrap(),
+        Err(_) => 33,
+    };
+    FriConfig {
+        log_blowup: 3,
+        num_queries,
+        proof_of_work_bits: 16,
+        mmcs: challenge_mmcs,
+    }
+}
+
+/// An error returned by [`check_fri_security`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FriSecurityError {
+    /// The queries and the proof of work do not reach the target security.
+    TooFewQueries { required: usize, actual: usize },
+    /// The challenge field is too small for the target security at the given trace height.
+    ChallengeFieldTooSmall {
+        challenge_bits: usize,
+        log_degree: usize,
+    },
+}
+
+/// Checks that `fri_config` gives at least `security_bits` bits of conjectured security when
+/// committing to traces of height at most `2^log_trace_height`.
+///
+/// Every query brings `log_blowup` bits and the proof of work brings `proof_of_work_bits`, so
+/// at least `(security_bits - proof_of_work_bits) / log_blowup` queries are needed. The
+/// probability that an out-of-domain sample hits a root of a low-degree polynomial grows with
+/// its degree, so the challenge field of `challenge_bits` bits must also leave `security_bits`
+/// bits once the degree of the low-degree extension is accounted for.
+pub fn check_fri_security<M>(
+    fri_config: &FriConfig<M>,
+    challenge_bits: usize,
+    log_trace_height: usize,
+    security_bits: usize,
+) -> Result<(), FriSecurityError> {
+    let query_bits = security_bits.saturating_sub(fri_config.proof_of_work_bits);
+    let required = (query_bits + fri_config.log_blowup - 1) / fri_config.log_blowup.max(1);
+    if fri_config.log_blowup == 0 || fri_config.num_queries < required {
+        return Err(FriSecurityError::TooFewQueries {
+            required,
+            actual: fri_config.num_queries,
+        });
+    }
+
+    let log_degree = log_trace_height + fri_config.log_blowup;
+    if challenge_bits < security_bits + log_degree {
+        return Err(FriSecurityError::ChallengeFieldTooSmall {
+            challenge_bits,
+            log_degree,
+        });
+    }
+    Ok(())
+}
+
 /// The recursion config used for recursive reduce circuit.
 #[derive(Deserialize)]
 #[serde(from = "std::marker::PhantomData<BabyBearPoseidon2Inner>")]
 pub struct BabyBearPoseidon2Inner {
     pub perm: InnerPerm,
     pub pcs: InnerPcs,
+    /// Whether the PCS uses [`inner_compact_fri_config`] rather than [`inner_fri_config`].
+    compact: bool,
 }
 
 impl Clone for BabyBearPoseidon2Inner {
     fn clone(&self) -> Self {
-        Self::new()
+        if self.compact {
+            Self::compact()
+        } else {
+            Self::new()
+        }
     }
 }
 
@@ -125,7 +197,29 @@ impl BabyBearPoseidon2Inner {
         let dft = InnerDft {};
         let fri_config = inner_fri_config();
         let pcs = InnerPcs::new(27, dft, val_mmcs, fri_config);
-        Self { pcs, perm }
+        Self {
+            pcs,
+            perm,
+            compact: false,
+        }
+    }
+
+    /// A config whose proofs have fewer FRI queries, see [`inner_compact_fri_config`].
+    ///
+    /// The mode is not serialized, so a deserialized config is always the default one.
+    pub fn compact() -> Self {
+        let perm = inner_perm();
+        let hash = InnerHash::new(perm.clone());
+        let compress = InnerCompress::new(perm.clone());
+        let val_mmcs = InnerValMmcs::new(hash, compress);
+        let dft = InnerDft {};
+        let fri_config = inner_compact_fri_config();
+        let pcs = InnerPcs::new(27, dft, val_mmcs, fri_config);
+        Self {
+            pcs,
+            perm,
+            compact: true,
+        }
     }
 }
 
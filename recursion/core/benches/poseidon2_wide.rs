@@ -0,0 +1,124 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use p3_baby_bear::BabyBear;
+use p3_field::{AbstractField, Field, PackedValue};
+use sp1_recursion_core::poseidon2::Poseidon2Event;
+use sp1_recursion_core::poseidon2_wide::external::{flatten_rows, NUM_POSEIDON2_WIDE_COLS, WIDTH};
+use sp1_recursion_core::poseidon2_wide::{
+    permute_in_place, permute_with_constants, round_constants, unpack_states, Poseidon2WideChip,
+};
+
+/// Returns `num_states` distinct states to permute.
+fn states(num_states: usize) -> Vec<[BabyBear; WIDTH]> {
+    (0..num_states)
+        .map(|i| core::array::from_fn(|j| BabyBear::from_canonical_usize(i * WIDTH + j)))
+        .collect()
+}
+
+pub fn permute_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("permute");
+    let inputs = states(1 << 12);
+    let rc = round_constants::<BabyBear>();
+
+    group.bench_function("converting constants", |b| {
+        b.iter_batched_ref(
+            || inputs.clone(),
+            |states| {
+                for state in states.iter_mut() {
+                    permute_in_place(state);
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("precomputed constants", |b| {
+        b.iter_batched_ref(
+            || inputs.clone(),
+            |states| {
+                for state in states.iter_mut() {
+                    permute_with_constants(state, black_box(&rc));
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+pub fn flatten_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("flatten rows");
+    let events = states(1 << 10)
+        .into_iter()
+        .map(Poseidon2Event::dummy_from_input)
+        .collect::<Vec<_>>();
+    let trace = Poseidon2WideChip::<3>::default().generate_rows(&events);
+    let rows = trace
+        .values
+        .chunks_exact(NUM_POSEIDON2_WIDE_COLS)
+        .map(|row| <[BabyBear; NUM_POSEIDON2_WIDE_COLS]>::try_from(row).unwrap())
+        .collect::<Vec<_>>();
+
+    group.bench_function("iterator", |b| {
+        b.iter(|| {
+            black_box(&rows)
+                .iter()
+                .flatten()
+                .copied()
+                .collect::<Vec<_>>()
+        })
+    });
+    group.bench_function("copies", |b| b.iter(|| flatten_rows(black_box(&rows))));
+    group.finish();
+}
+
+pub fn unpack_benchmark(c: &mut Criterion) {
+    type Packing = <BabyBear as Field>::Packing;
+
+    let mut group = c.benchmark_group("unpack states");
+    let packed = states(1 << 10)
+        .chunks_exact(Packing::WIDTH)
+        .map(|chunk| {
+            core::array::from_fn::<Packing, WIDTH, _>(|i| Packing::from_fn(|j| chunk[j][i]))
+        })
+        .collect::<Vec<_>>();
+    let num_states = packed.len() * Packing::WIDTH;
+
+    group.bench_function("gathering every entry", |b| {
+        b.iter_batched_ref(
+            || vec![[BabyBear::zero(); WIDTH]; num_states],
+            |states| {
+                for (state, chunk) in black_box(&packed)
+                    .iter()
+                    .zip(states.chunks_exact_mut(Packing::WIDTH))
+                {
+                    for (j, row) in chunk.iter_mut().enumerate() {
+                        *row = core::array::from_fn(|i| state[i].as_slice()[j]);
+                    }
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("transposing by lane", |b| {
+        b.iter_batched_ref(
+            || vec![[BabyBear::zero(); WIDTH]; num_states],
+            |states| {
+                for (state, chunk) in black_box(&packed)
+                    .iter()
+                    .zip(states.chunks_exact_mut(Packing::WIDTH))
+                {
+                    unpack_states(state, chunk);
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    permute_benchmark,
+    flatten_benchmark,
+    unpack_benchmark
+);
+criterion_main!(benches);
@@ -2,6 +2,8 @@ pub mod air;
 pub mod cpu;
 pub mod fri_fold;
 pub mod memory;
+#[cfg(feature = "otel")]
+pub mod otel;
 pub mod poseidon2;
 pub mod poseidon2_wide;
 pub mod range_check;
@@ -0,0 +1,80 @@
+//! Export of the tracing spans of trace generation and proving to OpenTelemetry.
+//!
+//! The chips already emit `tracing` spans, such as `generate poseidon2 wide trace` and
+//! `prove poseidon2 wide`, with the number of events and the shard as attributes. This module
+//! forwards them to an OpenTelemetry tracer, which also records the time spent in every span, so
+//! that a cluster of provers can be observed in a tracing backend.
+
+use opentelemetry::trace::Tracer;
+use tracing::Subscriber;
+use tracing_opentelemetry::PreSampledTracer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// Returns a subscriber that exports every span to `tracer`.
+///
+/// It can be installed globally with `tracing::subscriber::set_global_default`, or for a single
+/// scope with `tracing::subscriber::with_default`.
+pub fn otel_subscriber<T>(tracer: T) -> impl Subscriber + Send + Sync
+where
+    T: Tracer + PreSampledTracer + Send + Sync + 'static,
+{
+    Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::Value;
+    use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use sp1_core::utils::BabyBearPoseidon2Inner;
+
+    use super::otel_subscriber;
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::Poseidon2WideChip;
+    use crate::runtime::{ExecutionRecord, PERMUTATION_WIDTH};
+
+    #[test]
+    fn spans_exported() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = otel_subscriber(provider.tracer("sp1-recursion-core"));
+
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..3 {
+            let input = [BabyBear::from_canonical_u32(i); PERMUTATION_WIDTH];
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+        let chip = Poseidon2WideChip::<3>::default();
+        let config = BabyBearPoseidon2Inner::new();
+        tracing::subscriber::with_default(subscriber, || {
+            chip.prove(&config, &record).unwrap();
+        });
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        for name in ["generate poseidon2 wide trace", "prove poseidon2 wide"] {
+            let span = spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("no span named {}", name));
+            let attribute = |key: &str| {
+                span.attributes
+                    .iter()
+                    .find(|attribute| attribute.key.as_str() == key)
+                    .map(|attribute| attribute.value.clone())
+            };
+            assert_eq!(attribute("num_events"), Some(Value::I64(3)));
+            assert_eq!(attribute("shard"), Some(Value::I64(0)));
+            assert!(attribute("busy_ns").is_some());
+            assert!(span.end_time >= span.start_time);
+        }
+    }
+}
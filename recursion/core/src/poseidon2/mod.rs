@@ -4,6 +4,9 @@ use crate::poseidon2::external::WIDTH;
 mod external;
 use crate::air::Block;
 use crate::memory::MemoryRecord;
+use crate::poseidon2_wide::external::{NUM_INTERNAL_ROUNDS, NUM_ROUNDS};
+use crate::poseidon2_wide::snapshot::MemoryBackend;
+use crate::poseidon2_wide::{permute_with_internal_rounds, round_constants};
 use p3_field::PrimeField32;
 
 pub use external::Poseidon2Chip;
@@ -15,25 +18,144 @@ pub struct Poseidon2Event<F> {
     pub left: F,  // from b_val
     pub right: F, // from c_val
     pub input: [F; WIDTH],
+    /// The initialization vector added to `input` before the permutation is applied.
+    pub iv: [F; WIDTH],
     pub result_array: [F; WIDTH],
+    /// Whether `result_array` is the negation of the permutation of the input.
+    pub negate_output: bool,
+    /// The number of lanes the output is rotated by: lane `i` of `result_array` is lane
+    /// `i + output_rotation` of the permutation, modulo `WIDTH`.
+    pub output_rotation: usize,
+    /// The number of internal rounds of the permutation, at most `NUM_INTERNAL_ROUNDS`.
+    ///
+    /// Only the wide chip with `variable_internal_rounds` set proves events with fewer rounds.
+    pub num_internal_rounds: usize,
     pub input_records: [MemoryRecord<F>; WIDTH],
     pub result_records: [MemoryRecord<F>; WIDTH],
 }
 
 impl<F: PrimeField32> Poseidon2Event<F> {
+    /// Constructs the event of a permutation at timestamp `clk` over `memory`, like the
+    /// `Poseidon2Compress` opcode: the first `WIDTH / 2` input lanes are read from the addresses
+    /// starting at `left` and the others from those starting at `right`, `iv` is added to them,
+    /// and the permuted lanes are written at `clk + 1` to the `WIDTH` addresses starting at
+    /// `dst`.
+    ///
+    /// The memory records are the ones returned by `memory`, so they hold the previous value and
+    /// timestamp of every address, as the memory columns of the chips need them.
+    pub fn from_memory(
+        memory: &mut impl MemoryBackend<F>,
+        clk: F,
+        dst: usize,
+        left: usize,
+        right: usize,
+        iv: [F; WIDTH],
+    ) -> Self {
+        let input_records: [MemoryRecord<F>; WIDTH] = core::array::from_fn(|i| {
+            if i < WIDTH / 2 {
+                memory.read(left + i, clk)
+            } else {
+                memory.read(right + i - WIDTH / 2, clk)
+            }
+        });
+        let input = core::array::from_fn(|i| input_records[i].value.0[0]);
+
+        let mut result_array = core::array::from_fn(|i| input[i] + iv[i]);
+        permute_with_internal_rounds(&mut result_array, &round_constants(), NUM_INTERNAL_ROUNDS);
+        let result_records =
+            core::array::from_fn(|i| memory.write(dst + i, clk + F::one(), result_array[i]));
+
+        Self {
+            clk,
+            dst: F::from_canonical_usize(dst),
+            left: F::from_canonical_usize(left),
+            right: F::from_canonical_usize(right),
+            input,
+            iv,
+            result_array,
+            negate_output: false,
+            output_rotation: 0,
+            num_internal_rounds: NUM_INTERNAL_ROUNDS,
+            input_records,
+            result_records,
+        }
+    }
+
     /// A way to construct a dummy event from an input array, used for testing.
+    ///
+    /// The input is read at timestamp one and the permuted output is written at timestamp two,
+    /// so the memory records are consistent with the constraints of the wide chip.
     pub fn dummy_from_input(input: [F; WIDTH]) -> Self {
-        let dummy_record =
-            MemoryRecord::new_read(F::zero(), Block::from(F::zero()), F::zero(), F::zero());
+        Self::dummy_from_input_with_iv(input, [F::zero(); WIDTH])
+    }
+
+    /// A way to construct a dummy event from an input array and an initialization vector, used
+    /// for testing.
+    pub fn dummy_from_input_with_iv(input: [F; WIDTH], iv: [F; WIDTH]) -> Self {
+        Self::dummy_from_input_with_constants(input, iv, &round_constants())
+    }
+
+    /// A way to construct a dummy event whose output is permuted with the round constants `rc`,
+    /// used for testing.
+    pub fn dummy_from_input_with_constants(
+        input: [F; WIDTH],
+        iv: [F; WIDTH],
+        rc: &[[F; WIDTH]; NUM_ROUNDS],
+    ) -> Self {
+        Self::dummy_with_rounds(input, iv, rc, NUM_INTERNAL_ROUNDS)
+    }
+
+    /// A way to construct a dummy event whose output is permuted with only the first
+    /// `num_internal_rounds` internal rounds, used for testing.
+    pub fn dummy_with_internal_rounds(input: [F; WIDTH], num_internal_rounds: usize) -> Self {
+        Self::dummy_with_rounds(
+            input,
+            [F::zero(); WIDTH],
+            &round_constants(),
+            num_internal_rounds,
+        )
+    }
+
+    fn dummy_with_rounds(
+        input: [F; WIDTH],
+        iv: [F; WIDTH],
+        rc: &[[F; WIDTH]; NUM_ROUNDS],
+        num_internal_rounds: usize,
+    ) -> Self {
+        let mut result_array = core::array::from_fn(|i| input[i] + iv[i]);
+        permute_with_internal_rounds(&mut result_array, rc, num_internal_rounds);
+
+        let input_records = core::array::from_fn(|i| {
+            MemoryRecord::new_read(
+                F::from_canonical_usize(i),
+                Block::from(input[i]),
+                F::one(),
+                F::zero(),
+            )
+        });
+        let result_records = core::array::from_fn(|i| {
+            MemoryRecord::new_write(
+                F::from_canonical_usize(i),
+                Block::from(result_array[i]),
+                F::two(),
+                Block::from(F::zero()),
+                F::zero(),
+            )
+        });
+
         Self {
-            clk: F::zero(),
+            clk: F::one(),
             dst: F::zero(),
             left: F::zero(),
-            right: F::zero(),
+            right: F::from_canonical_usize(WIDTH / 2),
             input,
-            result_array: [F::zero(); WIDTH],
-            input_records: [dummy_record; WIDTH],
-            result_records: [dummy_record; WIDTH],
+            iv,
+            result_array,
+            negate_output: false,
+            output_rotation: 0,
+            num_internal_rounds,
+            input_records,
+            result_records,
         }
     }
 }
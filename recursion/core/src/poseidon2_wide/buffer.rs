@@ -0,0 +1,107 @@
+//! Generation of the trace of the wide Poseidon2 chip in chunks of bounded size.
+
+use p3_field::PrimeField32;
+use p3_matrix::dense::RowMajorMatrix;
+use sp1_core::utils::pad_to_power_of_two;
+
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::NUM_POSEIDON2_WIDE_COLS;
+use crate::poseidon2_wide::Poseidon2WideChip;
+
+/// A buffer of Poseidon2 events that is flushed into a chunk of trace rows whenever it holds
+/// `threshold` events, to bound the memory used while executing a long program.
+///
+/// Each chunk holds the unpadded rows of the events flushed into it, in order, so the chunks can
+/// be proven or persisted as they are produced, and [`merge_chunks`] reproduces the trace that
+/// the chip would have generated for all the events at once.
+pub struct EventBuffer<'a, F, const DEGREE: usize> {
+    chip: &'a Poseidon2WideChip<DEGREE>,
+    threshold: usize,
+    events: Vec<Poseidon2Event<F>>,
+}
+
+impl<'a, F: PrimeField32, const DEGREE: usize> EventBuffer<'a, F, DEGREE> {
+    /// Creates an empty buffer flushing every `threshold` events into rows of `chip`.
+    ///
+    /// Panics if `threshold` is zero.
+    pub fn new(chip: &'a Poseidon2WideChip<DEGREE>, threshold: usize) -> Self {
+        assert!(threshold > 0, "the flush threshold must be positive");
+        Self {
+            chip,
+            threshold,
+            events: Vec::with_capacity(threshold),
+        }
+    }
+
+    /// Adds `event` to the buffer, returning the chunk of rows of the buffered events if the
+    /// buffer reached its threshold and was flushed.
+    pub fn push(&mut self, event: Poseidon2Event<F>) -> Option<RowMajorMatrix<F>> {
+        self.events.push(event);
+        (self.events.len() >= self.threshold).then(|| self.flush())
+    }
+
+    /// Returns the chunk of rows of the events left in the buffer, if there are any.
+    pub fn finish(mut self) -> Option<RowMajorMatrix<F>> {
+        (!self.events.is_empty()).then(|| self.flush())
+    }
+
+    fn flush(&mut self) -> RowMajorMatrix<F> {
+        let chunk = self.chip.generate_rows(&self.events);
+        self.events.clear();
+        chunk
+    }
+}
+
+/// Concatenates chunks of rows produced by an [`EventBuffer`], in order, and pads the result to
+/// a power of two like the trace generated by the chip.
+pub fn merge_chunks<F: PrimeField32>(chunks: &[RowMajorMatrix<F>]) -> RowMajorMatrix<F> {
+    let mut values = Vec::with_capacity(chunks.iter().map(|chunk| chunk.values.len()).sum());
+    for chunk in chunks {
+        values.extend_from_slice(&chunk.values);
+    }
+    pad_to_power_of_two::<NUM_POSEIDON2_WIDE_COLS, F>(&mut values);
+    RowMajorMatrix::new(values, NUM_POSEIDON2_WIDE_COLS)
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+    use sp1_core::air::MachineAir;
+
+    use super::{merge_chunks, EventBuffer};
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::NUM_POSEIDON2_WIDE_COLS;
+    use crate::poseidon2_wide::Poseidon2WideChip;
+    use crate::runtime::ExecutionRecord;
+
+    #[test]
+    fn merged_chunks() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..100 {
+            let input = core::array::from_fn(|j| BabyBear::from_canonical_u32(i * 16 + j as u32));
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+
+        let mut buffer = EventBuffer::new(&chip, 7);
+        let mut chunks = record
+            .poseidon2_events
+            .iter()
+            .cloned()
+            .filter_map(|event| buffer.push(event))
+            .collect::<Vec<_>>();
+        chunks.extend(buffer.finish());
+        assert_eq!(chunks.len(), 15);
+        assert!(chunks[..14]
+            .iter()
+            .all(|chunk| chunk.values.len() == 7 * NUM_POSEIDON2_WIDE_COLS));
+
+        let expected: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        assert_eq!(merge_chunks(&chunks).values, expected.values);
+    }
+}
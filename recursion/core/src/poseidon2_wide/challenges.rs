@@ -0,0 +1,97 @@
+//! Derives Fiat-Shamir challenges from a digest with the Poseidon2 permutation.
+
+use p3_field::PrimeField32;
+
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::WIDTH;
+use crate::poseidon2_wide::snapshot::MemoryBackend;
+use crate::poseidon2_wide::{permute_state_in_memory, STATE_PERMUTATION_CYCLES};
+use crate::runtime::{DIGEST_SIZE, HASH_RATE};
+
+/// Squeezes `n` challenges from `digest`, recording the event of every permutation in `events`.
+///
+/// The state starts as the digest followed by a zero capacity. It is permuted once for every
+/// `HASH_RATE` challenges, which are read from the rate part of the permuted state, and every
+/// permutation starts from the output of the previous one. The challenges therefore only depend
+/// on the digest and their position, so asking for more of them extends the earlier ones.
+///
+/// The state is held in `memory` at the `WIDTH` addresses starting at `addr`, and the first
+/// permutation starts one cycle after the latest timestamp of `memory`.
+pub fn digest_to_challenges<F: PrimeField32>(
+    memory: &mut impl MemoryBackend<F>,
+    addr: usize,
+    digest: [F; DIGEST_SIZE],
+    n: usize,
+    events: &mut Vec<Poseidon2Event<F>>,
+) -> Vec<F> {
+    let mut state = [F::zero(); WIDTH];
+    state[..DIGEST_SIZE].copy_from_slice(&digest);
+
+    let mut clk = memory.latest_timestamp() + F::one();
+    let mut challenges = Vec::with_capacity(n);
+    while challenges.len() < n {
+        let event = permute_state_in_memory(memory, clk, addr, &state, [F::zero(); WIDTH]);
+        state = event.result_array;
+        events.push(event);
+        clk += F::from_canonical_usize(STATE_PERMUTATION_CYCLES);
+
+        let take = HASH_RATE.min(n - challenges.len());
+        challenges.extend_from_slice(&state[..take]);
+    }
+    challenges
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+
+    use super::digest_to_challenges;
+    use crate::runtime::{ExecutionRecord, MemoryEntry, HASH_RATE};
+
+    /// Squeezes challenges like [`digest_to_challenges`], in a fresh memory.
+    fn challenges(
+        digest: [BabyBear; 8],
+        n: usize,
+        events: &mut Vec<crate::poseidon2::Poseidon2Event<BabyBear>>,
+    ) -> Vec<BabyBear> {
+        let mut memory = HashMap::<usize, MemoryEntry<BabyBear>>::new();
+        digest_to_challenges(&mut memory, 0, digest, n, events)
+    }
+
+    #[test]
+    fn challenges_extend() {
+        let digest = core::array::from_fn(|i| BabyBear::from_canonical_usize(3 * i + 1));
+
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        let challenges = challenges(digest, 5, &mut record.poseidon2_events);
+        assert_eq!(challenges.len(), 5);
+        assert_eq!(record.poseidon2_events.len(), 1);
+        assert_eq!(
+            challenges,
+            challenges(digest, 5, &mut Vec::new()),
+            "the same digest should give the same challenges"
+        );
+
+        // Asking for more challenges runs more permutations and keeps the earlier challenges.
+        let mut events = Vec::new();
+        let more = challenges(digest, 3 * HASH_RATE + 1, &mut events);
+        assert_eq!(events.len(), 4);
+        assert_eq!(more[..5], challenges[..]);
+        assert_eq!(events[1].input, events[0].result_array);
+        assert_eq!(
+            more[HASH_RATE..2 * HASH_RATE],
+            events[1].result_array[..HASH_RATE]
+        );
+
+        // Another digest gives other challenges.
+        let mut other = digest;
+        other[0] += BabyBear::one();
+        assert_ne!(challenges(other, 5, &mut Vec::new()), challenges);
+
+        record.poseidon2_events = events;
+        record.validate().unwrap();
+    }
+}
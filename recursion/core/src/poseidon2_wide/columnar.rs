@@ -0,0 +1,137 @@
+//! Export of the wide Poseidon2 trace in the Arrow columnar format.
+//!
+//! Every column of the trace becomes a `UInt32` column of canonical values, named after
+//! [`Poseidon2WideChip::columns_layout`], so the trace can be loaded by tools like pandas.
+
+use std::io::{Read, Seek, Write};
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, UInt32Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use p3_field::PrimeField32;
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+
+use crate::poseidon2_wide::external::NUM_POSEIDON2_WIDE_COLS;
+use crate::poseidon2_wide::Poseidon2WideChip;
+
+/// Converts a trace of the chip into a record batch with one column per trace column.
+pub fn trace_to_record_batch<F: PrimeField32>(
+    trace: &RowMajorMatrix<F>,
+) -> Result<RecordBatch, ArrowError> {
+    let names = Poseidon2WideChip::<3>::columns_layout();
+    let schema = Schema::new(
+        names
+            .iter()
+            .map(|name| Field::new(name, DataType::UInt32, false))
+            .collect::<Vec<_>>(),
+    );
+    let columns = (0..trace.width())
+        .map(|col| {
+            let values = (0..trace.height())
+                .map(|row| trace.get(row, col).as_canonical_u32())
+                .collect::<Vec<_>>();
+            Arc::new(UInt32Array::from(values)) as ArrayRef
+        })
+        .collect::<Vec<_>>();
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// Converts a record batch produced by [`trace_to_record_batch`] back into a trace.
+pub fn record_batch_to_trace<F: PrimeField32>(
+    batch: &RecordBatch,
+) -> Result<RowMajorMatrix<F>, ArrowError> {
+    if batch.num_columns() != NUM_POSEIDON2_WIDE_COLS {
+        return Err(ArrowError::SchemaError(format!(
+            "expected {} columns, got {}",
+            NUM_POSEIDON2_WIDE_COLS,
+            batch.num_columns()
+        )));
+    }
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| {
+            column
+                .as_any()
+                .downcast_ref::<UInt32Array>()
+                .ok_or_else(|| ArrowError::CastError("expected a UInt32 column".to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut values = Vec::with_capacity(batch.num_rows() * NUM_POSEIDON2_WIDE_COLS);
+    for row in 0..batch.num_rows() {
+        values.extend(
+            columns
+                .iter()
+                .map(|column| F::from_canonical_u32(column.value(row))),
+        );
+    }
+    Ok(RowMajorMatrix::new(values, NUM_POSEIDON2_WIDE_COLS))
+}
+
+/// Writes a trace of the chip to `writer` in the Arrow IPC file format.
+pub fn write_trace<F: PrimeField32, W: Write>(
+    trace: &RowMajorMatrix<F>,
+    writer: W,
+) -> Result<(), ArrowError> {
+    let batch = trace_to_record_batch(trace)?;
+    let mut writer = FileWriter::try_new(writer, &batch.schema())?;
+    writer.write(&batch)?;
+    writer.finish()
+}
+
+/// Reads a trace written by [`write_trace`] from `reader`.
+pub fn read_trace<F: PrimeField32, R: Read + Seek>(
+    reader: R,
+) -> Result<RowMajorMatrix<F>, ArrowError> {
+    let mut reader = FileReader::try_new(reader, None)?;
+    let batch = reader
+        .next()
+        .ok_or_else(|| ArrowError::IpcError("the file holds no record batch".to_string()))??;
+    record_batch_to_trace(&batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_matrix::Matrix;
+    use sp1_core::air::MachineAir;
+
+    use super::{read_trace, trace_to_record_batch, write_trace};
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::Poseidon2WideChip;
+    use crate::runtime::ExecutionRecord;
+
+    #[test]
+    fn round_trip() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..3 {
+            let input = core::array::from_fn(|j| BabyBear::from_canonical_u32(i * 5 + j as u32));
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+
+        let batch = trace_to_record_batch(&trace).unwrap();
+        assert_eq!(batch.num_rows(), trace.height());
+        assert_eq!(batch.schema().field(0).name(), "memory.timestamp");
+
+        let mut bytes = Vec::new();
+        write_trace(&trace, &mut bytes).unwrap();
+        let decoded: RowMajorMatrix<BabyBear> = read_trace(Cursor::new(bytes)).unwrap();
+        assert_eq!(decoded.values, trace.values);
+        assert_eq!(decoded.width(), trace.width());
+    }
+}
@@ -0,0 +1,253 @@
+//! A preprocessed table of Poseidon2 round constants, shared by the chips that read from it.
+//!
+//! Committing to the constants once in a preprocessed trace lets several chips permuting with
+//! the same constants look them up instead of each baking them into their constraints.
+
+use core::borrow::{Borrow, BorrowMut};
+use core::mem::size_of;
+
+use p3_air::{Air, BaseAir, PairBuilder};
+use p3_field::{Field, PrimeField32};
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+use sp1_core::air::MachineAir;
+use sp1_derive::AlignedBorrow;
+use sp1_primitives::RC_16_30_U32;
+
+use crate::air::SP1RecursionAirBuilder;
+use crate::poseidon2_wide::external::{
+    NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS, NUM_ROUNDS, WIDTH,
+};
+use crate::poseidon2_wide::Poseidon2WideChip;
+use crate::runtime::{ExecutionRecord, RecursionProgram};
+
+/// The number of preprocessed columns for `RoundConstantsChip`.
+pub const NUM_ROUND_CONSTANTS_PREPROCESSED_COLS: usize =
+    size_of::<RoundConstantsPreprocessedCols<u8>>();
+
+/// The number of main trace columns for `RoundConstantsChip`.
+pub const NUM_ROUND_CONSTANTS_MULT_COLS: usize = size_of::<RoundConstantsMultCols<u8>>();
+
+/// The number of rows of the table, one per round rounded up to a power of two.
+pub const NUM_ROWS: usize = NUM_ROUNDS.next_power_of_two();
+
+#[derive(Debug, Clone, Copy, AlignedBorrow)]
+#[repr(C)]
+pub struct RoundConstantsPreprocessedCols<T> {
+    /// The index of the round. The padding rows continue the count past the last round, so
+    /// that no lookup can match them.
+    pub round: T,
+
+    /// The constants of the round. Only the 0th lane of an internal round has a constant added
+    /// to it, so the other lanes of the internal rounds are zero.
+    pub constants: [T; WIDTH],
+}
+
+#[derive(Debug, Clone, Copy, AlignedBorrow)]
+#[repr(C)]
+pub struct RoundConstantsMultCols<T> {
+    /// The number of times the round is looked up.
+    pub multiplicity: T,
+}
+
+/// A chip holding a table of round constants in its preprocessed trace, which other chips look
+/// up through the bus `bus`.
+///
+/// Every round is sent once for every Poseidon2 event of the record, so the Poseidon2 events
+/// must be proven by exactly one chip reading from the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundConstantsChip {
+    pub bus: u32,
+    pub table: [[u32; WIDTH]; NUM_ROUNDS],
+}
+
+impl RoundConstantsChip {
+    /// Creates a table of `RC_16_30_U32` sent on `bus`.
+    pub fn new(bus: u32) -> Self {
+        Self {
+            bus,
+            table: core::array::from_fn(|r| RC_16_30_U32[r]),
+        }
+    }
+
+    /// Creates the table that `chip` reads its round constants from.
+    ///
+    /// Panics if the chip does not read its round constants from a table.
+    pub fn for_chip<const DEGREE: usize>(chip: &Poseidon2WideChip<DEGREE>) -> Self {
+        Self {
+            bus: chip
+                .rc_bus
+                .expect("the chip does not read its round constants from a table"),
+            table: chip.round_constant_table(),
+        }
+    }
+}
+
+impl<F: PrimeField32> MachineAir<F> for RoundConstantsChip {
+    type Record = ExecutionRecord<F>;
+    type Program = RecursionProgram<F>;
+
+    fn name(&self) -> String {
+        "Poseidon2RoundConstants".to_string()
+    }
+
+    fn preprocessed_width(&self) -> usize {
+        NUM_ROUND_CONSTANTS_PREPROCESSED_COLS
+    }
+
+    fn generate_preprocessed_trace(&self, _program: &Self::Program) -> Option<RowMajorMatrix<F>> {
+        let mut trace = RowMajorMatrix::new(
+            vec![F::zero(); NUM_ROWS * NUM_ROUND_CONSTANTS_PREPROCESSED_COLS],
+            NUM_ROUND_CONSTANTS_PREPROCESSED_COLS,
+        );
+        for row in 0..NUM_ROWS {
+            let cols: &mut RoundConstantsPreprocessedCols<F> = trace.row_mut(row).borrow_mut();
+            cols.round = F::from_canonical_usize(row);
+            if row < NUM_ROUNDS {
+                let is_internal = (NUM_EXTERNAL_ROUNDS / 2
+                    ..NUM_EXTERNAL_ROUNDS / 2 + NUM_INTERNAL_ROUNDS)
+                    .contains(&row);
+                let lanes = if is_internal { 1 } else { WIDTH };
+                for i in 0..lanes {
+                    cols.constants[i] = F::from_wrapped_u32(self.table[row][i]);
+                }
+            }
+        }
+        Some(trace)
+    }
+
+    fn generate_dependencies(&self, _: &Self::Record, _: &mut Self::Record) {
+        // This is a no-op.
+    }
+
+    fn generate_trace(
+        &self,
+        input: &ExecutionRecord<F>,
+        _output: &mut ExecutionRecord<F>,
+    ) -> RowMajorMatrix<F> {
+        let mut trace = RowMajorMatrix::new(
+            vec![F::zero(); NUM_ROWS * NUM_ROUND_CONSTANTS_MULT_COLS],
+            NUM_ROUND_CONSTANTS_MULT_COLS,
+        );
+        let multiplicity = F::from_canonical_usize(input.poseidon2_events.len());
+        for row in 0..NUM_ROUNDS {
+            let cols: &mut RoundConstantsMultCols<F> = trace.row_mut(row).borrow_mut();
+            cols.multiplicity = multiplicity;
+        }
+        trace
+    }
+
+    fn included(&self, shard: &Self::Record) -> bool {
+        !shard.poseidon2_events.is_empty()
+    }
+}
+
+impl<F: Field> BaseAir<F> for RoundConstantsChip {
+    fn width(&self) -> usize {
+        NUM_ROUND_CONSTANTS_MULT_COLS
+    }
+}
+
+impl<AB: SP1RecursionAirBuilder + PairBuilder> Air<AB> for RoundConstantsChip {
+    fn eval(&self, builder: &mut AB) {
+        let main = builder.main();
+        let local_mult = main.row_slice(0);
+        let local_mult: &RoundConstantsMultCols<AB::Var> = (*local_mult).borrow();
+
+        let prep = builder.preprocessed();
+        let prep = prep.row_slice(0);
+        let local: &RoundConstantsPreprocessedCols<AB::Var> = (*prep).borrow();
+
+        let mut values = vec![local.round];
+        values.extend_from_slice(&local.constants);
+        builder.send_table(
+            AB::F::from_canonical_u32(self.bus),
+            &values,
+            local_mult.multiplicity,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_matrix::Matrix;
+    use sp1_core::air::MachineAir;
+    use sp1_core::lookup::InteractionKind;
+    use sp1_core::stark::Chip;
+
+    use super::RoundConstantsChip;
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::{NUM_POSEIDON2_WIDE_COLS, WIDTH};
+    use crate::poseidon2_wide::{Poseidon2WideChip, Poseidon2WideOptions};
+    use crate::runtime::{ExecutionRecord, RecursionProgram};
+    use crate::stark::utils::{
+        accumulate_interactions, accumulate_interactions_with_preprocessed, check_constraints,
+    };
+
+    const BUS: u32 = 1 << 20;
+
+    #[test]
+    fn lookup_round_constants() {
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record.poseidon2_events = (0..5)
+            .map(|i| {
+                let input = core::array::from_fn(|j| BabyBear::from_canonical_usize(i * WIDTH + j));
+                Poseidon2Event::dummy_from_input(input)
+            })
+            .collect();
+
+        let inlined = Poseidon2WideChip::<3>::default();
+        let lookup = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            rc_bus: Some(BUS),
+            ..Default::default()
+        });
+        let inlined_trace: RowMajorMatrix<BabyBear> =
+            inlined.generate_trace(&record, &mut ExecutionRecord::default());
+        let lookup_trace: RowMajorMatrix<BabyBear> =
+            lookup.generate_trace(&record, &mut ExecutionRecord::default());
+        check_constraints(&lookup, &lookup_trace, &[]).expect("expected constraints to hold");
+
+        // Reading the constants from the table proves the same permutations.
+        assert_eq!(lookup_trace.height(), inlined_trace.height());
+        for row in 0..inlined_trace.height() {
+            assert_eq!(
+                lookup_trace.row_slice(row)[..NUM_POSEIDON2_WIDE_COLS],
+                *inlined_trace.row_slice(row)
+            );
+        }
+
+        // The constants received by the chip are the ones sent by the table.
+        let table = RoundConstantsChip::for_chip(&lookup);
+        let preprocessed = MachineAir::<BabyBear>::generate_preprocessed_trace(
+            &table,
+            &RecursionProgram::default(),
+        )
+        .unwrap();
+        let table_trace = table.generate_trace(&record, &mut ExecutionRecord::default());
+        let table = Chip::<BabyBear, _>::new(table);
+        let lookup = Chip::<BabyBear, _>::new(lookup);
+        let mut balance = BTreeMap::new();
+        accumulate_interactions_with_preprocessed(
+            &table,
+            Some(&preprocessed),
+            &table_trace,
+            InteractionKind::Syscall,
+            &mut balance,
+        );
+        accumulate_interactions(
+            &lookup,
+            &lookup_trace,
+            InteractionKind::Syscall,
+            &mut balance,
+        );
+        // Only look at the bus, the CPU table is not part of this test.
+        balance.retain(|key, _| key[0] == BUS);
+        assert!(!balance.is_empty());
+        assert!(balance.values().all(|net| *net == BabyBear::zero()));
+    }
+}
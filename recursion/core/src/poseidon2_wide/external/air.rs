@@ -0,0 +1,1593 @@
+use crate::runtime::{Opcode, DIGEST_SIZE};
+use core::borrow::Borrow;
+use core::iter::once;
+use core::ops::Range;
+use p3_air::{Air, BaseAir};
+use p3_field::{AbstractField, Field};
+use p3_matrix::Matrix;
+use p3_uni_stark::SymbolicAirBuilder;
+use sp1_core::air::{BaseAirBuilder, SP1AirBuilder};
+
+use super::columns::{
+    Poseidon2MemCols, Poseidon2WideByteInputCols, Poseidon2WideCols, Poseidon2WideIvCols,
+    Poseidon2WideNegationCols, Poseidon2WideRotationCols, Poseidon2WideRoundConstantCols,
+    Poseidon2WideVariableRoundCols, NUM_BYTE_INPUT_COLS, NUM_IV_COLS, NUM_NEGATION_COLS,
+    NUM_ROTATION_COLS, NUM_ROUND_CONSTANT_COLS, NUM_VARIABLE_ROUND_COLS,
+};
+use super::trace::external_round_index;
+use super::{
+    Poseidon2WideChip, DEFAULT_CONSTRAINT_BUDGET, NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS,
+    NUM_POSEIDON2_WIDE_COLS, NUM_ROUNDS, WIDTH,
+};
+use crate::air::SP1RecursionAirBuilder;
+use crate::memory::MemoryCols;
+use crate::poseidon2_wide::{
+    external_linear_layer, internal_linear_layer, permute_linear_with_constants,
+    permute_with_constants,
+};
+use crate::range_check::RangeCheckOpcode;
+use crate::stark::utils::assert_constraint_budget;
+
+impl<F, const DEGREE: usize> BaseAir<F> for Poseidon2WideChip<DEGREE> {
+    fn width(&self) -> usize {
+        let mut width = NUM_POSEIDON2_WIDE_COLS;
+        if self.options.rc_bus.is_some() {
+            width += NUM_ROUND_CONSTANT_COLS;
+        }
+        if self.options.variable_internal_rounds {
+            width += NUM_VARIABLE_ROUND_COLS;
+        }
+        if self.options.byte_input {
+            width += NUM_BYTE_INPUT_COLS;
+        }
+        if self.options.initialization_vector {
+            width += NUM_IV_COLS;
+        }
+        if self.options.negated_output {
+            width += NUM_NEGATION_COLS;
+        }
+        if self.options.rotated_output {
+            width += NUM_ROTATION_COLS;
+        }
+        width
+    }
+}
+
+/// The columns transforming the output of the permutation, when the chip appends them.
+#[derive(Clone, Copy)]
+struct OutputTransformCols<'a, T> {
+    negation: Option<&'a Poseidon2WideNegationCols<T>>,
+    rotation: Option<&'a Poseidon2WideRotationCols<T>>,
+}
+
+fn eval_external_round<AB: SP1AirBuilder>(
+    builder: &mut AB,
+    cols: &Poseidon2WideCols<AB::Var>,
+    output_transform: OutputTransformCols<AB::Var>,
+    output_lanes: Range<usize>,
+    r: usize,
+    rc: &[[AB::Expr; WIDTH]; NUM_ROUNDS],
+    linear_only: bool,
+) {
+    let round_cols = cols.external_rounds[r];
+
+    // Add the round constants, which are already gated by `is_real`.
+    let round = external_round_index(r);
+    let add_rc: [AB::Expr; WIDTH] =
+        core::array::from_fn(|i| round_cols.state[i].into() + rc[round][i].clone());
+
+    // Apply the sboxes.
+    // See `populate_external_round` for why we don't have columns for the sbox output here.
+    // Without the sboxes, the unused sbox columns are pinned to zero.
+    let mut sbox_deg_7: [AB::Expr; WIDTH] = core::array::from_fn(|_| AB::Expr::zero());
+    for i in 0..WIDTH {
+        if linear_only {
+            builder.assert_zero(round_cols.sbox_deg_3[i]);
+            sbox_deg_7[i] = add_rc[i].clone();
+            continue;
+        }
+        let sbox_deg_3 = add_rc[i].clone() * add_rc[i].clone() * add_rc[i].clone();
+        builder.assert_eq(round_cols.sbox_deg_3[i], sbox_deg_3);
+
+        let sbox_deg_3 = round_cols.sbox_deg_3[i];
+        sbox_deg_7[i] = sbox_deg_3 * sbox_deg_3 * add_rc[i].clone();
+    }
+
+    // Apply the linear layer.
+    let mut state = sbox_deg_7;
+    external_linear_layer(&mut state);
+
+    // The output of the last round is constrained lane by lane against the output memory values.
+    // Every lane of `output_lanes` is written back to memory in `eval_mem`, so every consumed
+    // lane is constrained, and the other lanes of a compression carry no constraint.
+    //
+    // A negated output is multiplied by `1 - 2 * negate_output`, which is its own inverse for a
+    // boolean flag, rather than negating the state, so that the degree stays at most 3.
+    //
+    // Output lane `i` of a rotation by `k` is lane `i + k` of the state, so lane `j` of the state
+    // is selected among the output lanes by the one-hot rotation columns, falling back to lane
+    // `j` when none of them is set.
+    let next_state: [AB::Expr; WIDTH] = if r == NUM_EXTERNAL_ROUNDS / 2 - 1 {
+        core::array::from_fn(|i| cols.internal_rounds.state[i].into())
+    } else if r == NUM_EXTERNAL_ROUNDS - 1 {
+        let output = |i: usize| *cols.memory.output[i % WIDTH].value();
+        let lanes: [AB::Expr; WIDTH] = match output_transform.rotation {
+            Some(rotation) => {
+                let not_rotated = AB::Expr::one()
+                    - rotation
+                        .output_rotation
+                        .iter()
+                        .map(|&selector| selector.into())
+                        .sum::<AB::Expr>();
+                core::array::from_fn(|j| {
+                    let rotated = (1..WIDTH)
+                        .map(|k| rotation.output_rotation[k - 1] * output(j + WIDTH - k))
+                        .sum::<AB::Expr>();
+                    rotated + not_rotated.clone() * output(j)
+                })
+            }
+            None => core::array::from_fn(|j| output(j).into()),
+        };
+        match output_transform.negation {
+            Some(negation) => {
+                let sign = AB::Expr::one() - negation.negate_output.into() * AB::F::two();
+                lanes.map(|lane| sign.clone() * lane)
+            }
+            None => lanes,
+        }
+    } else {
+        core::array::from_fn(|i| cols.external_rounds[r + 1].state[i].into())
+    };
+    let constrained = if r == NUM_EXTERNAL_ROUNDS - 1 {
+        output_lanes
+    } else {
+        0..WIDTH
+    };
+    for (i, (next, state)) in next_state.into_iter().zip(state).enumerate() {
+        if constrained.contains(&i) {
+            builder.assert_eq(next, state);
+        }
+    }
+}
+
+/// Constrains the internal rounds, and the input of the second half of the external rounds to be
+/// the state after all of them, or after the rounds selected by `variable` when it is given.
+fn eval_internal_rounds<AB: SP1AirBuilder>(
+    builder: &mut AB,
+    cols: &Poseidon2WideCols<AB::Var>,
+    variable: Option<&Poseidon2WideVariableRoundCols<AB::Var>>,
+    rc: &[[AB::Expr; WIDTH]; NUM_ROUNDS],
+    linear_only: bool,
+) {
+    let round_cols = &cols.internal_rounds;
+    let mut state: [AB::Expr; WIDTH] = core::array::from_fn(|i| round_cols.state[i].into());
+    let mut states = vec![state.clone()];
+    for r in 0..NUM_INTERNAL_ROUNDS {
+        // Add the round constant.
+        let round = r + NUM_EXTERNAL_ROUNDS / 2;
+        let add_rc = if r == 0 {
+            state[0].clone()
+        } else {
+            round_cols.s0[r - 1].into()
+        } + rc[round][0].clone();
+
+        // See `populate_internal_rounds` for why we don't have columns for the sbox output here.
+        let sbox_deg_7 = if linear_only {
+            builder.assert_zero(round_cols.sbox_deg_3[r]);
+            add_rc.clone()
+        } else {
+            let sbox_deg_3 = add_rc.clone() * add_rc.clone() * add_rc.clone();
+            builder.assert_eq(round_cols.sbox_deg_3[r], sbox_deg_3);
+            round_cols.sbox_deg_3[r].into() * round_cols.sbox_deg_3[r].into() * add_rc.clone()
+        };
+
+        // Apply the linear layer.
+        // See `populate_internal_rounds` for why we don't have columns for the new state here.
+        //
+        // When the rounds are selected, the sbox output is read from its column instead, so
+        // that the states stay linear and can be selected within degree 3.
+        state[0] = match variable {
+            Some(variable) => {
+                builder.assert_eq(variable.sbox_deg_7[r], sbox_deg_7);
+                variable.sbox_deg_7[r].into()
+            }
+            None => sbox_deg_7.clone(),
+        };
+        internal_linear_layer(&mut state);
+
+        if r < NUM_INTERNAL_ROUNDS - 1 {
+            builder.assert_eq(round_cols.s0[r], state[0].clone());
+        }
+        states.push(state.clone());
+    }
+
+    // The selectors are a prefix of ones, so the state after `k` rounds is picked by the
+    // difference between the selectors of rounds `k - 1` and `k`.
+    let output = match variable {
+        Some(variable) => {
+            let selectors = &variable.round_selectors;
+            let mut num_rounds = AB::Expr::zero();
+            for r in 0..NUM_INTERNAL_ROUNDS {
+                builder.assert_bool(selectors[r]);
+                if r > 0 {
+                    builder.when(selectors[r]).assert_one(selectors[r - 1]);
+                }
+                num_rounds += selectors[r].into();
+            }
+            builder.assert_eq(variable.num_internal_rounds, num_rounds);
+
+            let selector = |k: usize| -> AB::Expr {
+                match k {
+                    0 => AB::Expr::one(),
+                    k if k > NUM_INTERNAL_ROUNDS => AB::Expr::zero(),
+                    k => selectors[k - 1].into(),
+                }
+            };
+            core::array::from_fn(|i| {
+                states
+                    .iter()
+                    .enumerate()
+                    .map(|(k, state)| (selector(k) - selector(k + 1)) * state[i].clone())
+                    .sum::<AB::Expr>()
+            })
+        }
+        None => state,
+    };
+    for i in 0..WIDTH {
+        builder.assert_eq(
+            cols.external_rounds[NUM_EXTERNAL_ROUNDS / 2].state[i],
+            output[i].clone(),
+        )
+    }
+}
+
+/// Constrains every input lane of a real row to be the little-endian packing of its bytes, and
+/// range checks the bytes.
+fn eval_byte_input<AB: SP1RecursionAirBuilder>(
+    builder: &mut AB,
+    memory: &Poseidon2MemCols<AB::Var>,
+    byte_cols: &Poseidon2WideByteInputCols<AB::Var>,
+) {
+    for (input, bytes) in memory.input.iter().zip(byte_cols.input_bytes.iter()) {
+        let packed = bytes.iter().rev().fold(AB::Expr::zero(), |packed, &byte| {
+            packed * AB::F::from_canonical_u32(256) + byte
+        });
+        builder
+            .when(memory.is_real)
+            .assert_eq(*input.value(), packed);
+        for &byte in bytes {
+            builder.send_range_check(
+                AB::Expr::from_canonical_u8(RangeCheckOpcode::U8 as u8),
+                byte,
+                memory.is_real,
+            );
+        }
+    }
+}
+
+fn eval_mem<AB: SP1RecursionAirBuilder>(
+    builder: &mut AB,
+    local: &Poseidon2MemCols<AB::Var>,
+    input_bus: Option<u32>,
+    output_bus: Option<u32>,
+    output_lanes: Range<usize>,
+) {
+    // Evaluate all of the memory.
+    for i in 0..WIDTH {
+        // The input lanes are only read from memory if they are not received from a producer bus.
+        if input_bus.is_none() {
+            let input_addr = if i < WIDTH / 2 {
+                local.left + AB::F::from_canonical_usize(i)
+            } else {
+                local.right + AB::F::from_canonical_usize(i - WIDTH / 2)
+            };
+
+            builder.recursion_eval_memory_access_single(
+                local.timestamp,
+                input_addr,
+                &local.input[i],
+                local.is_real,
+            );
+        }
+
+        if output_lanes.contains(&i) {
+            let output_addr = local.dst + AB::F::from_canonical_usize(i);
+            builder.recursion_eval_memory_access_single(
+                local.timestamp + AB::F::from_canonical_usize(1),
+                output_addr,
+                &local.output[i],
+                local.is_real,
+            );
+        }
+    }
+
+    // Receive the input lanes, tagged with the timestamp, from the producer bus.
+    if let Some(bus) = input_bus {
+        let values = once(local.timestamp.into())
+            .chain(local.input.iter().map(|access| (*access.value()).into()))
+            .collect::<Vec<AB::Expr>>();
+        builder.receive_table(AB::F::from_canonical_u32(bus), &values, local.is_real);
+    }
+
+    // Send the output lanes, tagged with the timestamp they are written at, on the consumer bus.
+    if let Some(bus) = output_bus {
+        let values = once(local.timestamp + AB::F::one())
+            .chain(
+                local.output[output_lanes]
+                    .iter()
+                    .map(|access| (*access.value()).into()),
+            )
+            .collect::<Vec<AB::Expr>>();
+        builder.send_table(AB::F::from_canonical_u32(bus), &values, local.is_real);
+    }
+
+    // Constraint that the operands are sent from the CPU table.
+    let operands: [AB::Expr; 4] = [
+        local.timestamp.into(),
+        local.dst.into(),
+        local.left.into(),
+        local.right.into(),
+    ];
+    builder.receive_table(
+        Opcode::Poseidon2Compress.as_field::<AB::F>(),
+        &operands,
+        local.is_real,
+    );
+}
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Asserts that `eval` asserts at most `constraint_budget` constraints over `F`.
+    pub fn assert_constraint_budget<F: Field>(&self)
+    where
+        Self: Air<SymbolicAirBuilder<F>>,
+    {
+        assert_constraint_budget(
+            self,
+            self.num_public_values(),
+            self.options
+                .constraint_budget
+                .unwrap_or(DEFAULT_CONSTRAINT_BUDGET),
+        );
+    }
+
+    /// Returns the round constants of a row, multiplied by `is_real`, indexed by round.
+    ///
+    /// When the chip reads them from a table, they are the round constant columns of `row`,
+    /// which are pinned to zero on padding rows and received from the table on real rows.
+    fn eval_round_constants<AB: SP1RecursionAirBuilder>(
+        &self,
+        builder: &mut AB,
+        is_real: AB::Var,
+        row: &[AB::Var],
+    ) -> [[AB::Expr; WIDTH]; NUM_ROUNDS] {
+        let Some(bus) = self.options.rc_bus else {
+            let rc = self.round_constants::<AB::F>();
+            return core::array::from_fn(|r| core::array::from_fn(|i| is_real * rc[r][i]));
+        };
+
+        let constant_cols: &Poseidon2WideRoundConstantCols<AB::Var> = row
+            [NUM_POSEIDON2_WIDE_COLS..NUM_POSEIDON2_WIDE_COLS + NUM_ROUND_CONSTANT_COLS]
+            .borrow();
+        let mut rc: [[AB::Expr; WIDTH]; NUM_ROUNDS] =
+            core::array::from_fn(|_| core::array::from_fn(|_| AB::Expr::zero()));
+        for r in 0..NUM_EXTERNAL_ROUNDS {
+            rc[external_round_index(r)] =
+                core::array::from_fn(|i| constant_cols.external[r][i].into());
+        }
+        for r in 0..NUM_INTERNAL_ROUNDS {
+            rc[r + NUM_EXTERNAL_ROUNDS / 2][0] = constant_cols.internal[r].into();
+        }
+
+        let is_padding = AB::Expr::one() - is_real.into();
+        for &constant in constant_cols.external.iter().flatten() {
+            builder.when(is_padding.clone()).assert_zero(constant);
+        }
+        for &constant in constant_cols.internal.iter() {
+            builder.when(is_padding.clone()).assert_zero(constant);
+        }
+        for (round, constants) in rc.iter().enumerate() {
+            let values = once(AB::Expr::from_canonical_usize(round))
+                .chain(constants.iter().cloned())
+                .collect::<Vec<AB::Expr>>();
+            builder.receive_table(AB::F::from_canonical_u32(bus), &values, is_real);
+        }
+        rc
+    }
+}
+
+impl<AB, const DEGREE: usize> Air<AB> for Poseidon2WideChip<DEGREE>
+where
+    AB: SP1RecursionAirBuilder,
+{
+    fn eval(&self, builder: &mut AB) {
+        let main = builder.main();
+        let (local, next) = (main.row_slice(0), main.row_slice(1));
+        self.eval_rows(builder, &local, &next);
+    }
+}
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Asserts the constraints of the chip on the rows `local` and `next`, laid out in the current
+    /// layout.
+    pub(super) fn eval_rows<AB: SP1RecursionAirBuilder>(
+        &self,
+        builder: &mut AB,
+        local: &[AB::Var],
+        next: &[AB::Var],
+    ) {
+        let cols: &Poseidon2WideCols<AB::Var> = local[..NUM_POSEIDON2_WIDE_COLS].borrow();
+
+        assert!(
+            !self.options.compress
+                || !(self.options.rotated_output || self.options.export_boundary),
+            "a compression consumes only the digest lanes, which cannot be rotated or exported"
+        );
+        assert!(
+            !self.options.compress
+                || !self.options.expose_digest
+                || self.options.squeeze_tap.lanes().end <= DIGEST_SIZE,
+            "a compression only exposes a digest within its digest lanes"
+        );
+        let output_lanes = self.output_lanes();
+        eval_mem(
+            builder,
+            &cols.memory,
+            self.options.input_bus,
+            self.options.output_bus,
+            output_lanes.clone(),
+        );
+        if self.options.byte_input {
+            let offset = self.byte_input_cols_offset();
+            let byte_cols: &Poseidon2WideByteInputCols<AB::Var> =
+                local[offset..offset + NUM_BYTE_INPUT_COLS].borrow();
+            eval_byte_input(builder, &cols.memory, byte_cols);
+        }
+        assert!(
+            self.options.num_aux_public == 0 || self.options.initialization_vector,
+            "the auxiliary public values are bound to the initialization vector columns"
+        );
+        let iv_cols: Option<&Poseidon2WideIvCols<AB::Var>> =
+            self.options.initialization_vector.then(|| {
+                let offset = self.iv_cols_offset();
+                local[offset..offset + NUM_IV_COLS].borrow()
+            });
+        let output_transform = OutputTransformCols {
+            negation: self.options.negated_output.then(|| {
+                let offset = self.negation_cols_offset();
+                local[offset..offset + NUM_NEGATION_COLS].borrow()
+            }),
+            rotation: self.options.rotated_output.then(|| {
+                let offset = self.rotation_cols_offset();
+                local[offset..offset + NUM_ROTATION_COLS].borrow()
+            }),
+        };
+        if let Some(negation) = output_transform.negation {
+            builder.assert_bool(negation.negate_output);
+        }
+        if let Some(rotation) = output_transform.rotation {
+            let mut num_rotations = AB::Expr::zero();
+            for &selector in rotation.output_rotation.iter() {
+                builder.assert_bool(selector);
+                num_rotations += selector.into();
+            }
+            builder.assert_bool(num_rotations);
+        }
+
+        let rc = self.eval_round_constants(builder, cols.memory.is_real, local);
+
+        // Dummy constraints to normalize to DEGREE.
+        let lhs = (0..DEGREE)
+            .map(|_| cols.memory.is_real.into())
+            .product::<AB::Expr>();
+        let rhs = (0..DEGREE)
+            .map(|_| cols.memory.is_real.into())
+            .product::<AB::Expr>();
+        builder.assert_eq(lhs, rhs);
+
+        // Apply the initial round, after adding the initialization vector to the input.
+        let initial_round_output = {
+            let mut initial_round_output: [AB::Expr; WIDTH] = core::array::from_fn(|i| {
+                let input = *cols.memory.input[i].value();
+                match iv_cols {
+                    Some(iv_cols) => input + iv_cols.iv[i],
+                    None => input.into(),
+                }
+            });
+            external_linear_layer(&mut initial_round_output);
+            initial_round_output
+        };
+        builder
+            .when(cols.memory.is_real)
+            .assert_all_eq(cols.external_rounds[0].state, initial_round_output);
+
+        // Bind the auxiliary public values to the initialization vector.
+        let aux_public = builder.public_values()[..self.options.num_aux_public]
+            .iter()
+            .map(|&value| value.into())
+            .collect::<Vec<AB::Expr>>();
+        if let Some(iv_cols) = iv_cols {
+            for (iv, aux) in iv_cols.iv.iter().zip(aux_public) {
+                builder.when(cols.memory.is_real).assert_eq(*iv, aux);
+            }
+        }
+
+        // Bind the digest, if it is exposed, to the tapped lanes of the output of the first
+        // permutation.
+        let tap = self.options.squeeze_tap.lanes();
+        if self.options.expose_digest {
+            let digest = builder.public_values()
+                [self.options.num_aux_public..self.options.num_aux_public + tap.len()]
+                .iter()
+                .map(|&value| value.into())
+                .collect::<Vec<AB::Expr>>();
+            for (output, digest) in cols.memory.output[tap.clone()].iter().zip(digest) {
+                builder.when_first_row().assert_eq(*output.value(), digest);
+            }
+        }
+
+        // Bind the input, if it is public, and the imported boundary to the input of the first
+        // permutation.
+        let mut offset = self.options.num_aux_public
+            + if self.options.expose_digest {
+                tap.len()
+            } else {
+                0
+            };
+        for bound in [self.options.public_input, self.options.import_boundary] {
+            if !bound {
+                continue;
+            }
+            let public_input = builder.public_values()[offset..offset + WIDTH]
+                .iter()
+                .map(|&value| value.into())
+                .collect::<Vec<AB::Expr>>();
+            builder.when_first_row().assert_one(cols.memory.is_real);
+            for (input, public) in cols.memory.input.iter().zip(public_input) {
+                builder.when_first_row().assert_eq(*input.value(), public);
+            }
+            offset += WIDTH;
+        }
+
+        // Bind the exported boundary to the output of the last real permutation: the real row
+        // followed by a padding row, or the last row if it is real. The next row of the last row
+        // wraps around to the first row, which is real whenever the trace has a real row.
+        if self.options.export_boundary {
+            let next: &Poseidon2WideCols<AB::Var> = next[..NUM_POSEIDON2_WIDE_COLS].borrow();
+            let boundary = builder.public_values()[offset..offset + WIDTH]
+                .iter()
+                .map(|&value| value.into())
+                .collect::<Vec<AB::Expr>>();
+            let is_last_real = cols.memory.is_real * (AB::Expr::one() - next.memory.is_real);
+            for (output, boundary) in cols.memory.output.iter().zip(boundary) {
+                let difference = *output.value() - boundary;
+                builder.assert_zero(is_last_real.clone() * difference.clone());
+                builder
+                    .when_last_row()
+                    .assert_zero(cols.memory.is_real * difference);
+            }
+        }
+
+        // Pin the input and output of padding rows, so that they cannot carry any data. The
+        // round constants are gated by `is_real`, so the output of a padding row is the
+        // permutation of its input without them, and pinning it leaves every round column of the
+        // row with a single possible value.
+        let padding_input = AB::F::from_canonical_u32(self.options.padding_sentinel.unwrap_or(0));
+        let mut padding_output = [padding_input; WIDTH];
+        let zero_rc = [[AB::F::zero(); WIDTH]; NUM_ROUNDS];
+        if self.options.linear_only {
+            permute_linear_with_constants(&mut padding_output, &zero_rc);
+        } else {
+            permute_with_constants(&mut padding_output, &zero_rc);
+        }
+        builder.assert_bool(cols.memory.is_real);
+        let is_padding = AB::Expr::one() - cols.memory.is_real.into();
+        for i in 0..WIDTH {
+            builder
+                .when(is_padding.clone())
+                .assert_eq(*cols.memory.input[i].value(), padding_input);
+        }
+        for i in output_lanes.clone() {
+            builder
+                .when(is_padding.clone())
+                .assert_eq(*cols.memory.output[i].value(), padding_output[i]);
+        }
+
+        // Apply the first half of external rounds.
+        for r in 0..NUM_EXTERNAL_ROUNDS / 2 {
+            eval_external_round(
+                builder,
+                cols,
+                output_transform,
+                output_lanes.clone(),
+                r,
+                &rc,
+                self.options.linear_only,
+            );
+        }
+
+        // Apply the internal rounds.
+        let variable: Option<&Poseidon2WideVariableRoundCols<AB::Var>> =
+            self.options.variable_internal_rounds.then(|| {
+                let offset = self.variable_round_cols_offset();
+                local[offset..offset + NUM_VARIABLE_ROUND_COLS].borrow()
+            });
+        eval_internal_rounds(builder, cols, variable, &rc, self.options.linear_only);
+
+        // Apply the second half of external rounds.
+        for r in NUM_EXTERNAL_ROUNDS / 2..NUM_EXTERNAL_ROUNDS {
+            eval_external_round(
+                builder,
+                cols,
+                output_transform,
+                output_lanes.clone(),
+                r,
+                &rc,
+                self.options.linear_only,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::borrow::{Borrow, BorrowMut};
+    use core::iter::once;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::air::{Block, SP1RecursionAirBuilder};
+
+    use crate::memory::MemoryCols;
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::tests::{prove_and_verify, test_record};
+    use crate::poseidon2_wide::external::{
+        pack_input_bytes, Poseidon2MemCols, Poseidon2WideChip, Poseidon2WideCols,
+        Poseidon2WideOptions, Poseidon2WideVariableRoundCols, BYTES_PER_LANE,
+        DEFAULT_CONSTRAINT_BUDGET, NUM_BYTE_INPUT_COLS, NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS,
+        NUM_IV_COLS, NUM_POSEIDON2_WIDE_COLS, NUM_ROUNDS, NUM_VARIABLE_ROUND_COLS, WIDTH,
+    };
+    use crate::poseidon2_wide::{permute_linear_with_constants, round_constants};
+    use crate::range_check::{RangeCheckChip, RangeCheckOpcode};
+    use crate::runtime::{ExecutionRecord, RecursionProgram, DIGEST_SIZE};
+    use crate::stark::utils::{
+        accumulate_interactions, accumulate_interactions_with_preprocessed,
+        assert_constraint_budget, check_constraints, find_failure_cause, main_columns,
+        write_constraints,
+    };
+    use itertools::Itertools;
+    use p3_air::{Air, BaseAir};
+    use p3_baby_bear::BabyBear;
+    use p3_field::{AbstractField, Field, PrimeField32};
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_matrix::Matrix;
+    use p3_uni_stark::{get_max_constraint_degree, get_symbolic_constraints};
+    use sp1_core::air::MachineAir;
+    use sp1_core::lookup::InteractionKind;
+    use sp1_core::stark::{Chip, StarkGenericConfig};
+    use sp1_core::utils::{
+        pad_to_power_of_two, uni_stark_prove, uni_stark_verify, BabyBearPoseidon2Inner,
+    };
+    use sp1_primitives::RC_16_30_U32;
+
+    const NUM_STUB_PRODUCER_COLS: usize = WIDTH + 2;
+
+    /// A stub chip that sends the permutation inputs on a bus, standing in for a producer such as
+    /// an ALU chip. Each row holds the timestamp, the input lanes and `is_real`.
+    struct StubProducerChip {
+        bus: u32,
+    }
+
+    impl<F: PrimeField32> MachineAir<F> for StubProducerChip {
+        type Record = ExecutionRecord<F>;
+
+        type Program = RecursionProgram<F>;
+
+        fn name(&self) -> String {
+            "StubProducer".to_string()
+        }
+
+        fn generate_trace(
+            &self,
+            input: &ExecutionRecord<F>,
+            _: &mut ExecutionRecord<F>,
+        ) -> RowMajorMatrix<F> {
+            let mut values = input
+                .poseidon2_events
+                .iter()
+                .flat_map(|event| once(event.clk).chain(event.input).chain(once(F::one())))
+                .collect::<Vec<_>>();
+            pad_to_power_of_two::<NUM_STUB_PRODUCER_COLS, F>(&mut values);
+            RowMajorMatrix::new(values, NUM_STUB_PRODUCER_COLS)
+        }
+
+        fn included(&self, record: &Self::Record) -> bool {
+            !record.poseidon2_events.is_empty()
+        }
+    }
+
+    impl<F> BaseAir<F> for StubProducerChip {
+        fn width(&self) -> usize {
+            NUM_STUB_PRODUCER_COLS
+        }
+    }
+
+    impl<AB: SP1RecursionAirBuilder> Air<AB> for StubProducerChip {
+        fn eval(&self, builder: &mut AB) {
+            let main = builder.main();
+            let local = main.row_slice(0);
+            let local: &[AB::Var] = &local;
+            builder.send_table(
+                AB::F::from_canonical_u32(self.bus),
+                &local[..WIDTH + 1],
+                local[WIDTH + 1],
+            );
+        }
+    }
+
+    /// The ways in which `corrupt_trace` can tamper with a trace.
+    #[derive(Debug, Clone, Copy)]
+    enum Corruption {
+        /// Changes one cell of the SBox of an external round.
+        SboxCell,
+        /// Changes the state passed from the first external rounds to the internal rounds.
+        Transition,
+        /// Changes an input value without updating the state derived from it.
+        Input,
+        /// Marks a padding row as real.
+        PaddingIsReal,
+    }
+
+    impl Corruption {
+        const ALL: [Corruption; 4] = [
+            Corruption::SboxCell,
+            Corruption::Transition,
+            Corruption::Input,
+            Corruption::PaddingIsReal,
+        ];
+    }
+
+    /// Tampers with `trace` according to `kind`. All but `PaddingIsReal` act on the first row.
+    fn corrupt_trace(trace: &mut RowMajorMatrix<BabyBear>, kind: Corruption) {
+        let row = match kind {
+            Corruption::PaddingIsReal => trace.height() - 1,
+            _ => 0,
+        };
+        let cols: &mut Poseidon2WideCols<BabyBear> = trace.values
+            [row * NUM_POSEIDON2_WIDE_COLS..(row + 1) * NUM_POSEIDON2_WIDE_COLS]
+            .borrow_mut();
+
+        match kind {
+            Corruption::SboxCell => cols.external_rounds[0].sbox_deg_3[0] += BabyBear::one(),
+            Corruption::Transition => cols.internal_rounds.state[0] += BabyBear::one(),
+            Corruption::Input => *cols.memory.input[0].value_mut() += BabyBear::one(),
+            Corruption::PaddingIsReal => {
+                assert_eq!(cols.memory.is_real, BabyBear::zero());
+                cols.memory.is_real = BabyBear::one();
+            }
+        }
+    }
+
+    #[test]
+    fn failure_cause_of_corrupted_cell() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let mut trace: RowMajorMatrix<BabyBear> = chip.generate_trace(
+            &test_record(16),
+            &mut ExecutionRecord::<BabyBear>::default(),
+        );
+        assert_eq!(find_failure_cause(&chip, &trace, &[]), None);
+
+        let column = Poseidon2WideChip::<3>::columns_layout()
+            .iter()
+            .position(|name| name == "memory.output[3].value")
+            .unwrap();
+        trace.values[11 * NUM_POSEIDON2_WIDE_COLS + column] += BabyBear::one();
+
+        let cause = find_failure_cause(&chip, &trace, &[]).unwrap();
+        assert_eq!(cause.row, 11);
+        assert_eq!(cause.column, Some(column));
+        assert_eq!(cause.constraints.len(), 1);
+    }
+
+    /// A test checking that corrupting any output lane is rejected, since every lane is consumed.
+    #[test]
+    fn corrupt_output_lane() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&test_record(4), &mut ExecutionRecord::<BabyBear>::default());
+        assert_eq!(check_constraints(&chip, &trace, &[]), Ok(()));
+
+        for lane in 0..WIDTH {
+            let mut corrupted = trace.clone();
+            let cols: &mut Poseidon2WideCols<BabyBear> =
+                corrupted.values[..NUM_POSEIDON2_WIDE_COLS].borrow_mut();
+            *cols.memory.output[lane].value_mut() += BabyBear::one();
+            assert!(check_constraints(&chip, &corrupted, &[]).is_err());
+        }
+    }
+
+    /// A test checking that a compression only constrains its digest lanes: corrupting an unused
+    /// output lane still verifies and leaves the interactions unchanged, while corrupting a
+    /// digest lane is rejected.
+    #[test]
+    fn compress_output_lanes() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            compress: true,
+            ..Default::default()
+        });
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&test_record(4), &mut ExecutionRecord::<BabyBear>::default());
+        let corrupt = |lane: usize| {
+            let mut corrupted = trace.clone();
+            let cols: &mut Poseidon2WideCols<BabyBear> =
+                corrupted.values[..NUM_POSEIDON2_WIDE_COLS].borrow_mut();
+            *cols.memory.output[lane].value_mut() += BabyBear::one();
+            corrupted
+        };
+        let interactions = |trace: &RowMajorMatrix<BabyBear>| {
+            let mut balance = BTreeMap::new();
+            accumulate_interactions(
+                &Chip::<BabyBear, _>::new(chip.clone()),
+                trace,
+                InteractionKind::Memory,
+                &mut balance,
+            );
+            balance
+        };
+
+        let config = BabyBearPoseidon2Inner::new();
+        for lane in DIGEST_SIZE..WIDTH {
+            let corrupted = corrupt(lane);
+            assert_eq!(interactions(&corrupted), interactions(&trace));
+            let proof = uni_stark_prove(&config, &chip, &mut config.challenger(), corrupted);
+            uni_stark_verify(&config, &chip, &mut config.challenger(), &proof)
+                .expect("expected an unused lane to be unconstrained");
+        }
+        for lane in 0..DIGEST_SIZE {
+            assert!(check_constraints(&chip, &corrupt(lane), &[]).is_err());
+        }
+
+        // Without the option, the same lanes are consumed and constrained.
+        let full = Poseidon2WideChip::<3>::default();
+        assert!(check_constraints(&full, &corrupt(DIGEST_SIZE), &[]).is_err());
+    }
+
+    /// A test checking that a trace cannot claim that two different inputs map to the same
+    /// output, however the row of the second input is tampered with. The permutation is a
+    /// bijection, so the constraints must tie every output to exactly one input.
+    #[test]
+    fn collision_rejected() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(2);
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        let [first, second] = [0, 1].map(|row| {
+            let cols: &Poseidon2WideCols<BabyBear> = (*trace.row_slice(row)).borrow();
+            *cols
+        });
+        assert_ne!(
+            record.poseidon2_events[0].input,
+            record.poseidon2_events[1].input
+        );
+
+        let with_output_of_first = |mut cols: Poseidon2WideCols<BabyBear>| {
+            for i in 0..WIDTH {
+                *cols.memory.output[i].value_mut() = *first.memory.output[i].value();
+            }
+            cols
+        };
+        let collisions = [
+            // The output of the first row, with the rounds of the second one.
+            with_output_of_first(second),
+            // The whole first row, with the input of the second one.
+            Poseidon2WideCols {
+                memory: Poseidon2MemCols {
+                    input: second.memory.input,
+                    ..first.memory
+                },
+                ..first
+            },
+            // The rounds and output of the first row, with the memory of the second one.
+            with_output_of_first(Poseidon2WideCols {
+                memory: second.memory,
+                ..first
+            }),
+        ];
+        for (i, collision) in collisions.into_iter().enumerate() {
+            let mut tampered = trace.clone();
+            let cols: &mut Poseidon2WideCols<BabyBear> =
+                tampered.values[NUM_POSEIDON2_WIDE_COLS..2 * NUM_POSEIDON2_WIDE_COLS].borrow_mut();
+            *cols = collision;
+            assert!(
+                check_constraints(&chip, &tampered, &[]).is_err(),
+                "collision {} was accepted",
+                i
+            );
+        }
+    }
+
+    /// A test checking that every kind of corruption is rejected by the constraints.
+    #[test]
+    fn corrupt_trace_rejected() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&test_record(4), &mut ExecutionRecord::<BabyBear>::default());
+
+        for kind in Corruption::ALL {
+            let mut corrupted = trace.clone();
+            corrupt_trace(&mut corrupted, kind);
+            assert!(
+                check_constraints(&chip, &corrupted, &[]).is_err(),
+                "{:?} was not rejected",
+                kind
+            );
+        }
+    }
+
+    /// A test wiring a stub producer chip to the permutation chip through a bus and checking that
+    /// the lookup balances, and that it no longer does once the producer sends a different input.
+    #[test]
+    fn input_from_producer_bus() {
+        const BUS: u32 = 100;
+        let record = test_record(5);
+
+        let producer = Chip::<BabyBear, _>::new(StubProducerChip { bus: BUS });
+        let consumer =
+            Chip::<BabyBear, _>::new(Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+                input_bus: Some(BUS),
+                ..Default::default()
+            }));
+        let producer_trace =
+            producer.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        let consumer_trace =
+            consumer.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+
+        let bus_balance = |producer_trace: &RowMajorMatrix<BabyBear>| {
+            let mut balance = BTreeMap::new();
+            accumulate_interactions(
+                &producer,
+                producer_trace,
+                InteractionKind::Syscall,
+                &mut balance,
+            );
+            accumulate_interactions(
+                &consumer,
+                &consumer_trace,
+                InteractionKind::Syscall,
+                &mut balance,
+            );
+            // Only look at the bus, the CPU table is not part of this test.
+            balance.retain(|key, _| key[0] == BUS);
+            balance
+        };
+
+        let balance = bus_balance(&producer_trace);
+        assert_eq!(balance.len(), 5);
+        assert!(balance.values().all(|count| count.is_zero()));
+
+        let mut corrupted = producer_trace.clone();
+        corrupted.values[1] += BabyBear::one();
+        let balance = bus_balance(&corrupted);
+        assert!(!balance.values().all(|count| count.is_zero()));
+    }
+
+    /// A test checking that the bus balances when two producers, standing in for a hashing chip
+    /// and a challenger chip, send on it, including a permutation that both of them request.
+    #[test]
+    fn input_from_shared_bus() {
+        const BUS: u32 = 100;
+        let events = test_record(4).poseidon2_events;
+
+        let mut hash_record = ExecutionRecord::<BabyBear>::default();
+        hash_record.poseidon2_events = events[..3].to_vec();
+        let mut challenger_record = ExecutionRecord::<BabyBear>::default();
+        challenger_record.poseidon2_events = events[2..].to_vec();
+
+        let producer = Chip::<BabyBear, _>::new(StubProducerChip { bus: BUS });
+        let consumer =
+            Chip::<BabyBear, _>::new(Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+                input_bus: Some(BUS),
+                ..Default::default()
+            }));
+
+        let bus_balance = |consumer_record: &ExecutionRecord<BabyBear>| {
+            let mut balance = BTreeMap::new();
+            for record in [&hash_record, &challenger_record] {
+                let trace = producer.generate_trace(record, &mut ExecutionRecord::default());
+                accumulate_interactions(&producer, &trace, InteractionKind::Syscall, &mut balance);
+            }
+            let trace = consumer.generate_trace(consumer_record, &mut ExecutionRecord::default());
+            accumulate_interactions(&consumer, &trace, InteractionKind::Syscall, &mut balance);
+            // Only look at the bus, the CPU table is not part of this test.
+            balance.retain(|key, _| key[0] == BUS);
+            balance
+        };
+
+        // The permutation requested by both producers is sent twice, so it must be received twice.
+        let mut combined = ExecutionRecord::<BabyBear>::default();
+        combined.poseidon2_events = hash_record
+            .poseidon2_events
+            .iter()
+            .chain(&challenger_record.poseidon2_events)
+            .cloned()
+            .collect();
+        let balance = bus_balance(&combined);
+        assert_eq!(balance.len(), 4);
+        assert!(balance.values().all(|count| count.is_zero()));
+
+        // Receiving the shared permutation only once leaves it unbalanced.
+        let balance = bus_balance(&test_record(4));
+        assert!(!balance.values().all(|count| count.is_zero()));
+    }
+
+    /// A test dumping the constraints to a file and checking that there is one line per constraint.
+    #[test]
+    fn dump_constraints() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let path = std::env::temp_dir().join("poseidon2_wide_constraints.txt");
+        write_constraints::<BabyBear, _>(&chip, 0, &path).unwrap();
+        let dump = std::fs::read_to_string(&path).unwrap();
+
+        // The timestamp checks of the input and output memory accesses.
+        let memory = 2 * WIDTH;
+        // The constraint normalizing the degree.
+        let degree = 1;
+        // The initial linear layer.
+        let initial = WIDTH;
+        // The sbox and the next state of every external round.
+        let external = NUM_EXTERNAL_ROUNDS * 2 * WIDTH;
+        // The sbox of every internal round, the intermediate 0th state elements and the state at
+        // the end of the internal rounds.
+        let internal = NUM_INTERNAL_ROUNDS + (NUM_INTERNAL_ROUNDS - 1) + WIDTH;
+
+        assert_eq!(
+            dump.lines().count(),
+            memory + degree + initial + external + internal
+        );
+        assert!(dump.lines().all(|line| line.starts_with('(')));
+    }
+
+    #[test]
+    fn constraint_budget() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            num_aux_public: 2,
+            expose_digest: true,
+            public_input: true,
+            export_boundary: true,
+            rc_bus: Some(1 << 20),
+            variable_internal_rounds: true,
+            initialization_vector: true,
+            negated_output: true,
+            rotated_output: true,
+            padding_sentinel: Some(7),
+            ..Default::default()
+        });
+        let aux_public = [BabyBear::one(); 2];
+        assert_eq!(
+            chip.num_public_values(),
+            chip.public_values(&test_record(3), &aux_public).len()
+        );
+
+        // The chip fits in the default budget with most of its options set.
+        chip.assert_constraint_budget::<BabyBear>();
+    }
+
+    /// An AIR asserting the constraints of the chip once per lane, like an `eval` that
+    /// accidentally repeats its rounds in a loop over the lanes.
+    struct RepeatedEval(Poseidon2WideChip<3>);
+
+    impl<F> BaseAir<F> for RepeatedEval {
+        fn width(&self) -> usize {
+            <Poseidon2WideChip<3> as BaseAir<F>>::width(&self.0)
+        }
+    }
+
+    impl<AB: SP1RecursionAirBuilder> Air<AB> for RepeatedEval {
+        fn eval(&self, builder: &mut AB) {
+            for _ in 0..WIDTH {
+                self.0.eval(builder);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "exceed the constraint budget")]
+    fn constraint_budget_exceeded() {
+        let air = RepeatedEval(Poseidon2WideChip::<3>::default());
+        assert_constraint_budget::<BabyBear, _>(&air, 0, DEFAULT_CONSTRAINT_BUDGET);
+    }
+
+    #[test]
+    #[should_panic(expected = "exceed the constraint budget")]
+    fn constraint_budget_of_chip_exceeded() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            constraint_budget: Some(WIDTH),
+            ..Default::default()
+        });
+        chip.assert_constraint_budget::<BabyBear>();
+    }
+
+    /// A test checking that the constraints pin the order of the rounds, by rejecting traces
+    /// generated with the round constants of the rounds in another order.
+    ///
+    /// Every constraint of a round uses the constants of the round at its position in the
+    /// schedule, so a trace computed with the rounds in another order only satisfies them if
+    /// the reordered rounds happen to have the same constants.
+    #[test]
+    fn reordered_rounds_rejected() {
+        let half = NUM_EXTERNAL_ROUNDS / 2;
+        let reorderings: [(&str, fn(usize) -> usize); 2] = [
+            // The two halves of the external rounds are swapped.
+            ("external halves", |r| {
+                if r < half {
+                    r + NUM_INTERNAL_ROUNDS + half
+                } else if r >= half + NUM_INTERNAL_ROUNDS {
+                    r - NUM_INTERNAL_ROUNDS - half
+                } else {
+                    r
+                }
+            }),
+            // The first external rounds are run after the first internal rounds.
+            ("internal and external blocks", |r| {
+                if r < half {
+                    r + half
+                } else if r < 2 * half {
+                    r - half
+                } else {
+                    r
+                }
+            }),
+        ];
+
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(4);
+        for (name, reorder) in reorderings {
+            let table = core::array::from_fn(|r| RC_16_30_U32[reorder(r)]);
+            let reordered = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+                custom_round_constants: Some(table),
+                ..Default::default()
+            });
+            let rc = reordered.round_constants::<BabyBear>();
+            let mut reordered_record = ExecutionRecord::<BabyBear>::default();
+            reordered_record.poseidon2_events = record
+                .poseidon2_events
+                .iter()
+                .map(|event| {
+                    Poseidon2Event::dummy_from_input_with_constants(event.input, event.iv, &rc)
+                })
+                .collect();
+
+            let trace: RowMajorMatrix<BabyBear> = reordered.generate_trace(
+                &reordered_record,
+                &mut ExecutionRecord::<BabyBear>::default(),
+            );
+            assert!(check_constraints(&reordered, &trace, &[]).is_ok());
+            assert!(
+                check_constraints(&chip, &trace, &[]).is_err(),
+                "a trace with reordered {} was accepted",
+                name
+            );
+        }
+    }
+
+    /// A test checking that a nonzero initialization vector changes the output, and that the
+    /// initialization vector is bound by the constraints.
+    #[test]
+    fn initialization_vector() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            initialization_vector: true,
+            ..Default::default()
+        });
+        let input = [BabyBear::from_canonical_u32(7); WIDTH];
+        let iv = core::array::from_fn(|i| BabyBear::from_canonical_u32(i as u32 + 1));
+
+        let plain = Poseidon2Event::dummy_from_input(input);
+        let with_iv = Poseidon2Event::dummy_from_input_with_iv(input, iv);
+        assert_ne!(plain.result_array, with_iv.result_array);
+
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record.poseidon2_events.push(with_iv.clone());
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        let row = trace.row(0).collect_vec();
+        let cols: &Poseidon2WideCols<BabyBear> = row.as_slice().borrow();
+        for i in 0..WIDTH {
+            assert_eq!(with_iv.result_array[i], *cols.memory.output[i].value());
+        }
+
+        prove_and_verify(&chip, &record, &[]);
+
+        // Dropping the initialization vector from the trace is rejected.
+        let mut corrupted = trace.clone();
+        let offset = chip.iv_cols_offset();
+        corrupted.values[offset..offset + NUM_IV_COLS].fill(BabyBear::zero());
+        assert!(check_constraints(&chip, &corrupted, &[]).is_err());
+    }
+
+    /// A test checking that the input of a chip reading bytes is the packing of its byte
+    /// columns, and that the byte range checks it sends are the ones it records.
+    #[test]
+    fn byte_input() {
+        let byte_chip = || {
+            Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+                byte_input: true,
+                ..Default::default()
+            })
+        };
+        let chip = byte_chip();
+        let data = (0..5)
+            .map(|i| core::array::from_fn(|j| (i * 37 + j * 11) as u8))
+            .collect::<Vec<[u8; BYTES_PER_LANE * WIDTH]>>();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record.poseidon2_events = data
+            .iter()
+            .map(|bytes| Poseidon2Event::dummy_from_input(pack_input_bytes(bytes)))
+            .collect();
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        check_constraints(&chip, &trace, &[]).expect("expected constraints to hold");
+
+        // The byte columns of every real row hold the bytes the input was packed from.
+        let offset = trace.width() - NUM_BYTE_INPUT_COLS;
+        for (row, bytes) in data.iter().enumerate() {
+            assert_eq!(
+                trace.row_slice(row)[offset..],
+                bytes.map(BabyBear::from_canonical_u8)
+            );
+        }
+
+        // The range check table receives every byte that is sent.
+        let mut output = ExecutionRecord::default();
+        chip.generate_dependencies(&record, &mut output);
+        let range = RangeCheckChip::<BabyBear>::default();
+        let preprocessed = range
+            .generate_preprocessed_trace(&RecursionProgram::default())
+            .unwrap();
+        let range_trace = range.generate_trace(&output, &mut ExecutionRecord::default());
+        let mut balance = BTreeMap::new();
+        accumulate_interactions_with_preprocessed(
+            &Chip::new(range),
+            Some(&preprocessed),
+            &range_trace,
+            InteractionKind::Range,
+            &mut balance,
+        );
+        accumulate_interactions(
+            &Chip::new(byte_chip()),
+            &trace,
+            InteractionKind::Range,
+            &mut balance,
+        );
+        // Only look at the byte range checks, those of the memory timestamps are recorded by the
+        // runtime.
+        balance.retain(|key, _| key[0] == RangeCheckOpcode::U8 as u32);
+        assert!(!balance.is_empty());
+        assert!(balance.values().all(|net| net.is_zero()));
+
+        // A byte that is not the one packed into its lane is rejected.
+        let mut tampered = trace.clone();
+        tampered.row_mut(2)[offset + 4] += BabyBear::one();
+        assert!(check_constraints(&chip, &tampered, &[]).is_err());
+    }
+
+    /// A test checking that the constraints defining the sbox columns of the internal rounds only
+    /// read the 0th lane of the state, and that an sbox computed from another lane is rejected.
+    #[test]
+    fn internal_sbox_reads_state0() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let names = Poseidon2WideChip::<3>::columns_layout();
+        let column = |name: String| names.iter().position(|n| *n == name).unwrap();
+
+        // The linear layer after an sbox mixes it into every lane, so the constraints binding the
+        // outputs of the rounds read every lane. The one left for an sbox, which reads no output
+        // but that of the previous round, defines it.
+        let outputs = (0..NUM_INTERNAL_ROUNDS - 1)
+            .map(|r| column(format!("internal_rounds.s0[{}]", r)))
+            .chain((0..WIDTH).map(|i| {
+                column(format!(
+                    "external_rounds[{}].state[{}]",
+                    NUM_EXTERNAL_ROUNDS / 2,
+                    i
+                ))
+            }))
+            .collect::<BTreeSet<_>>();
+        let constraints = get_symbolic_constraints::<BabyBear, _>(&chip, 0, 0)
+            .iter()
+            .map(main_columns)
+            .collect::<Vec<_>>();
+        for r in 0..NUM_INTERNAL_ROUNDS {
+            let sbox = column(format!("internal_rounds.sbox_deg_3[{}]", r));
+            let input = if r == 0 {
+                column("internal_rounds.state[0]".to_string())
+            } else {
+                column(format!("internal_rounds.s0[{}]", r - 1))
+            };
+            let is_real = column("memory.is_real".to_string());
+            let defining = constraints
+                .iter()
+                .filter(|columns| columns.contains(&sbox))
+                .filter(|columns| columns.iter().all(|c| *c == input || !outputs.contains(c)))
+                .collect::<Vec<_>>();
+            assert_eq!(defining.len(), 1, "round {}", r);
+            assert!(
+                defining[0].is_subset(&BTreeSet::from([sbox, input, is_real])),
+                "the sbox of internal round {} reads {:?}",
+                r,
+                defining[0]
+            );
+        }
+
+        // An sbox computed from lane 1 rather than lane 0 is rejected.
+        let mut trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&test_record(4), &mut ExecutionRecord::default());
+        let rc = chip.round_constants::<BabyBear>();
+        {
+            let cols: &mut Poseidon2WideCols<BabyBear> = trace.row_mut(1).borrow_mut();
+            let add_rc = cols.internal_rounds.state[1] + rc[NUM_EXTERNAL_ROUNDS / 2][0];
+            cols.internal_rounds.sbox_deg_3[0] = add_rc.cube();
+        }
+        assert_eq!(
+            check_constraints(&chip, &trace, &[]).map_err(|failure| failure.row),
+            Err(1)
+        );
+    }
+
+    /// A test proving permutations with and without a negated output, and checking that the flag
+    /// has to agree with the output.
+    #[test]
+    fn negated_output() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            negated_output: true,
+            ..Default::default()
+        });
+
+        let mut record = test_record(6);
+        for event in record.poseidon2_events.iter_mut().step_by(2) {
+            event.negate_output = true;
+            event.result_array = event.result_array.map(|x| -x);
+            for (result_record, value) in event.result_records.iter_mut().zip(event.result_array) {
+                result_record.value = Block::from(value);
+            }
+        }
+        record.validate().unwrap();
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        check_constraints(&chip, &trace, &[]).expect("expected constraints to hold");
+        prove_and_verify(&chip, &record, &[]);
+
+        // The plain permutation is still proven when no event is negated.
+        let plain = test_record(6);
+        let plain_trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&plain, &mut ExecutionRecord::default());
+        check_constraints(&chip, &plain_trace, &[]).expect("expected constraints to hold");
+
+        // Flipping the flag of a row without negating its output breaks the constraints.
+        let column = chip.negation_cols_offset();
+        for row in [0, 1] {
+            let mut corrupted = trace.clone();
+            let cell = &mut corrupted.values[row * trace.width() + column];
+            *cell = BabyBear::one() - *cell;
+            assert!(check_constraints(&chip, &corrupted, &[]).is_err());
+        }
+    }
+
+    /// A test chaining two permutations through a bus, the output of the first being the input of
+    /// the second, and checking that the chain no longer balances once it is broken.
+    #[test]
+    fn chained_permutations() {
+        const BUS: u32 = 100;
+        let chip = Chip::<BabyBear, _>::new(Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            input_bus: Some(BUS),
+            output_bus: Some(BUS),
+            ..Default::default()
+        }));
+        let producer = Chip::<BabyBear, _>::new(StubProducerChip { bus: BUS });
+
+        let first = test_record(1).poseidon2_events.remove(0);
+        let mut second = Poseidon2Event::dummy_from_input(first.result_array);
+        second.clk = first.clk + BabyBear::one();
+
+        let bus_balance = |second: &Poseidon2Event<BabyBear>| {
+            let mut record = ExecutionRecord::<BabyBear>::default();
+            record.poseidon2_events = vec![first.clone(), second.clone()];
+            let mut producer_record = ExecutionRecord::<BabyBear>::default();
+            producer_record.poseidon2_events = vec![first.clone()];
+
+            let mut balance = BTreeMap::new();
+            let trace = producer.generate_trace(&producer_record, &mut ExecutionRecord::default());
+            accumulate_interactions(&producer, &trace, InteractionKind::Syscall, &mut balance);
+            let trace = chip.generate_trace(&record, &mut ExecutionRecord::default());
+            accumulate_interactions(&chip, &trace, InteractionKind::Syscall, &mut balance);
+            // Only look at the bus, the CPU table is not part of this test.
+            balance.retain(|key, _| key[0] == BUS);
+            // The output of the second permutation is the end of the chain, and is not consumed.
+            let end = once(BUS)
+                .chain(once((second.clk + BabyBear::one()).as_canonical_u32()))
+                .chain(second.result_array.iter().map(|x| x.as_canonical_u32()))
+                .collect::<Vec<_>>();
+            assert_eq!(balance.remove(&end), Some(BabyBear::one()));
+            balance
+        };
+
+        let balance = bus_balance(&second);
+        assert!(balance.values().all(|count| count.is_zero()));
+
+        // A second permutation of another input breaks the chain.
+        let mut input = first.result_array;
+        input[0] += BabyBear::one();
+        let mut broken = Poseidon2Event::dummy_from_input(input);
+        broken.clk = second.clk;
+        let balance = bus_balance(&broken);
+        assert!(!balance.values().all(|count| count.is_zero()));
+    }
+
+    /// A test proving permutations whose outputs are rotated, and checking that the rotation is
+    /// bound by the constraints.
+    #[test]
+    fn rotated_output() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            rotated_output: true,
+            ..Default::default()
+        });
+
+        let mut record = test_record(4);
+        for (event, rotation) in record.poseidon2_events.iter_mut().zip([0, 1, 5, WIDTH - 1]) {
+            event.output_rotation = rotation;
+            event.result_array.rotate_left(rotation);
+            for (result_record, value) in event.result_records.iter_mut().zip(event.result_array) {
+                result_record.value = Block::from(value);
+            }
+        }
+        record.validate().unwrap();
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        check_constraints(&chip, &trace, &[]).expect("expected constraints to hold");
+        for (row, event) in record.poseidon2_events.iter().enumerate() {
+            let states = chip.round_states(&trace.row(row).collect_vec());
+            assert_eq!(
+                states[NUM_ROUNDS],
+                Poseidon2Event::dummy_from_input(event.input).result_array
+            );
+        }
+        prove_and_verify(&chip, &record, &[]);
+
+        // Claiming another rotation for the same output breaks the constraints, whether it moves
+        // the selector or clears it.
+        let column = |k: usize| chip.rotation_cols_offset() + k;
+        let row = trace.width();
+        for (from, to) in [(0, Some(1)), (0, None)] {
+            let mut corrupted = trace.clone();
+            corrupted.values[row + column(from)] = BabyBear::zero();
+            if let Some(to) = to {
+                corrupted.values[row + column(to)] = BabyBear::one();
+            }
+            assert!(check_constraints(&chip, &corrupted, &[]).is_err());
+        }
+    }
+
+    /// A test checking that padding rows cannot carry data in their input or output lanes.
+    #[test]
+    fn padding_rows_pinned() {
+        let record = test_record(5);
+        for chip in [
+            Poseidon2WideChip::<3>::default(),
+            Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+                padding_sentinel: Some(7),
+                ..Default::default()
+            }),
+        ] {
+            let trace: RowMajorMatrix<BabyBear> =
+                chip.generate_trace(&record, &mut ExecutionRecord::default());
+            check_constraints(&chip, &trace, &[]).expect("expected constraints to hold");
+
+            let names = Poseidon2WideChip::<3>::columns_layout();
+            for name in ["memory.input[3].value", "memory.output[0].value"] {
+                let column = names.iter().position(|n| n == name).unwrap();
+                let mut corrupted = trace.clone();
+                corrupted.values[6 * NUM_POSEIDON2_WIDE_COLS + column] += BabyBear::one();
+                assert_eq!(
+                    check_constraints(&chip, &corrupted, &[]).unwrap_err().row,
+                    6,
+                    "{} of a padding row was not rejected",
+                    name
+                );
+            }
+        }
+    }
+
+    /// A test proving the insecure linear-only mode, whose map is affine in the input.
+    #[test]
+    fn linear_only() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            linear_only: true,
+            ..Default::default()
+        });
+        let rc = round_constants::<BabyBear>();
+        let linear = |input: [BabyBear; WIDTH]| {
+            let mut state = input;
+            permute_linear_with_constants(&mut state, &rc);
+            state
+        };
+
+        let mut record = test_record(6);
+        for event in record.poseidon2_events.iter_mut() {
+            event.result_array = linear(event.input);
+            for (result_record, value) in event.result_records.iter_mut().zip(event.result_array) {
+                result_record.value = Block::from(value);
+            }
+        }
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        check_constraints(&chip, &trace, &[]).expect("expected constraints to hold");
+        prove_and_verify(&chip, &record, &[]);
+
+        // The map is affine, which is why it must not be used as a hash.
+        let (x, y) = (
+            record.poseidon2_events[1].input,
+            record.poseidon2_events[2].input,
+        );
+        let sum = core::array::from_fn(|i| x[i] + y[i]);
+        let expected: [BabyBear; WIDTH] = core::array::from_fn(|i| {
+            linear(x)[i] + linear(y)[i] - linear([BabyBear::zero(); WIDTH])[i]
+        });
+        assert_eq!(linear(sum), expected);
+
+        // The trace of the full permutation does not satisfy the linear constraints, and the
+        // other way around.
+        let full = Poseidon2WideChip::<3>::default();
+        let full_trace: RowMajorMatrix<BabyBear> =
+            full.generate_trace(&test_record(6), &mut ExecutionRecord::default());
+        assert!(check_constraints(&chip, &full_trace, &[]).is_err());
+        assert!(check_constraints(&full, &trace, &[]).is_err());
+    }
+
+    /// A test checking that events with fewer internal rounds than the maximum are proven with
+    /// their own number of rounds.
+    #[test]
+    fn variable_internal_rounds() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            variable_internal_rounds: true,
+            ..Default::default()
+        });
+        assert_eq!(get_max_constraint_degree::<BabyBear, _>(&chip, 0, 0), 3);
+
+        let counts = [NUM_INTERNAL_ROUNDS, 5, 0, 9, NUM_INTERNAL_ROUNDS];
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record.poseidon2_events = counts
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| {
+                let input = core::array::from_fn(|j| BabyBear::from_canonical_usize(i * WIDTH + j));
+                Poseidon2Event::dummy_with_internal_rounds(input, count)
+            })
+            .collect();
+        record.validate().unwrap();
+        let full = Poseidon2Event::dummy_from_input(record.poseidon2_events[1].input);
+        assert_ne!(record.poseidon2_events[1].result_array, full.result_array);
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        assert_eq!(
+            trace.width(),
+            NUM_POSEIDON2_WIDE_COLS + NUM_VARIABLE_ROUND_COLS
+        );
+        check_constraints(&chip, &trace, &[]).expect("expected constraints to hold");
+        prove_and_verify(&chip, &record, &[]);
+
+        // Selecting one more round than the event applied does not give its output.
+        let mut tampered = trace.clone();
+        let round_cols: &mut Poseidon2WideVariableRoundCols<BabyBear> =
+            tampered.row_mut(1)[NUM_POSEIDON2_WIDE_COLS..].borrow_mut();
+        round_cols.num_internal_rounds += BabyBear::one();
+        round_cols.round_selectors[counts[1]] = BabyBear::one();
+        assert!(check_constraints(&chip, &tampered, &[]).is_err());
+
+        // The count must agree with the selectors.
+        let mut tampered = trace;
+        let round_cols: &mut Poseidon2WideVariableRoundCols<BabyBear> =
+            tampered.row_mut(2)[NUM_POSEIDON2_WIDE_COLS..].borrow_mut();
+        round_cols.num_internal_rounds = BabyBear::one();
+        assert!(check_constraints(&chip, &tampered, &[]).is_err());
+    }
+}
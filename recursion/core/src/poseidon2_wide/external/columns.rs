@@ -0,0 +1,267 @@
+use core::iter::once;
+use core::mem::size_of;
+use sp1_derive::AlignedBorrow;
+
+use super::{
+    Poseidon2WideChip, NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS, NUM_POSEIDON2_WIDE_COLS, WIDTH,
+};
+use crate::memory::{MemoryReadSingleCols, MemoryReadWriteSingleCols};
+
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub struct Poseidon2MemCols<T> {
+    pub timestamp: T,
+    pub dst: T,
+    pub left: T,
+    pub right: T,
+    pub input: [MemoryReadSingleCols<T>; WIDTH],
+    pub output: [MemoryReadWriteSingleCols<T>; WIDTH],
+    pub is_real: T,
+}
+
+/// The column layout for the chip.
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub struct Poseidon2WideCols<T> {
+    pub(super) memory: Poseidon2MemCols<T>,
+    pub(super) external_rounds: [Poseidon2WideExternalRoundCols<T>; NUM_EXTERNAL_ROUNDS],
+    pub(super) internal_rounds: Poseidon2WideInternalRoundsCols<T>,
+}
+
+/// The number of columns holding the round constants when they are read from a table.
+pub const NUM_ROUND_CONSTANT_COLS: usize = size_of::<Poseidon2WideRoundConstantCols<u8>>();
+
+/// The round constants of a row, appended to [`Poseidon2WideCols`] when the chip reads them from
+/// a table.
+///
+/// Only the 0th lane of an internal round has a constant added to it, so the internal rounds get
+/// a single column each.
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub struct Poseidon2WideRoundConstantCols<T> {
+    pub external: [[T; WIDTH]; NUM_EXTERNAL_ROUNDS],
+    pub internal: [T; NUM_INTERNAL_ROUNDS],
+}
+
+/// The number of columns selecting the internal rounds when their number varies by event.
+pub const NUM_VARIABLE_ROUND_COLS: usize = size_of::<Poseidon2WideVariableRoundCols<u8>>();
+
+/// The number of internal rounds of a row, appended to [`Poseidon2WideCols`] when the chip lets
+/// it vary by event.
+///
+/// The selectors are a prefix of ones, `round_selectors[r]` being set when round `r` is applied.
+/// The output of the sbox of every internal round gets its own column, so that the state after
+/// every round stays linear in the columns and selecting one of them stays within degree 3.
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub struct Poseidon2WideVariableRoundCols<T> {
+    pub num_internal_rounds: T,
+    pub round_selectors: [T; NUM_INTERNAL_ROUNDS],
+    pub sbox_deg_7: [T; NUM_INTERNAL_ROUNDS],
+}
+
+/// The number of bytes packed into an input lane when the input encodes bytes.
+///
+/// Three bytes always fit in a canonical element of a 31-bit field, so every packing of them
+/// gives a distinct lane.
+pub const BYTES_PER_LANE: usize = 3;
+
+/// The number of columns holding the bytes of the input when it encodes bytes.
+pub const NUM_BYTE_INPUT_COLS: usize = size_of::<Poseidon2WideByteInputCols<u8>>();
+
+/// The bytes of the input of a row, appended to [`Poseidon2WideCols`] when the input encodes
+/// bytes. The bytes of every lane are stored in little-endian order.
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub struct Poseidon2WideByteInputCols<T> {
+    pub input_bytes: [[T; BYTES_PER_LANE]; WIDTH],
+}
+
+/// The number of columns holding the initialization vector when the chip adds one to the input.
+pub const NUM_IV_COLS: usize = size_of::<Poseidon2WideIvCols<u8>>();
+
+/// The initialization vector of a row, appended to [`Poseidon2WideCols`] when the chip adds one
+/// to the input before the initial linear layer.
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub struct Poseidon2WideIvCols<T> {
+    pub iv: [T; WIDTH],
+}
+
+/// The number of columns flagging a negated output when the chip may negate it.
+pub const NUM_NEGATION_COLS: usize = size_of::<Poseidon2WideNegationCols<u8>>();
+
+/// Whether the output of a row is negated, appended to [`Poseidon2WideCols`] when the chip may
+/// negate it.
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub struct Poseidon2WideNegationCols<T> {
+    /// Whether the output is the negation of the permutation, as a boolean.
+    pub negate_output: T,
+}
+
+/// The number of columns selecting the rotation of the output when the chip may rotate it.
+pub const NUM_ROTATION_COLS: usize = size_of::<Poseidon2WideRotationCols<u8>>();
+
+/// The rotation of the output lanes of a row, appended to [`Poseidon2WideCols`] when the chip may
+/// rotate them.
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub struct Poseidon2WideRotationCols<T> {
+    /// The rotation, one-hot encoded: `output_rotation[k - 1]` is set for a rotation by `k`, and
+    /// none of them is set for no rotation.
+    pub output_rotation: [T; WIDTH - 1],
+}
+
+/// A grouping of columns for a single external round.
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub(super) struct Poseidon2WideExternalRoundCols<T> {
+    pub(super) state: [T; WIDTH],
+    pub(super) sbox_deg_3: [T; WIDTH],
+}
+
+/// A grouping of columns for all of the internal rounds.
+/// As an optimization, we can represent all of the internal rounds without columns for intermediate
+/// states except for the 0th element. This is because the linear layer that comes after the sbox is
+/// degree 1, so all state elements at the end can be expressed as a degree-3 polynomial of:
+/// 1) the 0th state element at rounds prior to the current round
+/// 2) the rest of the state elements at the beginning of the internal rounds
+#[derive(AlignedBorrow, Clone, Copy)]
+#[repr(C)]
+pub(super) struct Poseidon2WideInternalRoundsCols<T> {
+    pub(super) state: [T; WIDTH],
+    pub(super) s0: [T; NUM_INTERNAL_ROUNDS - 1],
+    pub(super) sbox_deg_3: [T; NUM_INTERNAL_ROUNDS],
+}
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Returns the offset in a row of the internal round columns appended when their number
+    /// varies by event.
+    pub(super) fn variable_round_cols_offset(&self) -> usize {
+        if self.options.rc_bus.is_some() {
+            NUM_POSEIDON2_WIDE_COLS + NUM_ROUND_CONSTANT_COLS
+        } else {
+            NUM_POSEIDON2_WIDE_COLS
+        }
+    }
+
+    /// Returns the offset in a row of the byte columns appended when the input encodes bytes.
+    pub(super) fn byte_input_cols_offset(&self) -> usize {
+        if self.options.variable_internal_rounds {
+            self.variable_round_cols_offset() + NUM_VARIABLE_ROUND_COLS
+        } else {
+            self.variable_round_cols_offset()
+        }
+    }
+
+    /// Returns the offset in a row of the initialization vector columns appended when the events
+    /// may have one.
+    pub(super) fn iv_cols_offset(&self) -> usize {
+        if self.options.byte_input {
+            self.byte_input_cols_offset() + NUM_BYTE_INPUT_COLS
+        } else {
+            self.byte_input_cols_offset()
+        }
+    }
+
+    /// Returns the offset in a row of the negation column appended when the events may negate
+    /// their output.
+    pub(super) fn negation_cols_offset(&self) -> usize {
+        if self.options.initialization_vector {
+            self.iv_cols_offset() + NUM_IV_COLS
+        } else {
+            self.iv_cols_offset()
+        }
+    }
+
+    /// Returns the offset in a row of the rotation columns appended when the events may rotate
+    /// their output.
+    pub(super) fn rotation_cols_offset(&self) -> usize {
+        if self.options.negated_output {
+            self.negation_cols_offset() + NUM_NEGATION_COLS
+        } else {
+            self.negation_cols_offset()
+        }
+    }
+
+    /// Returns the names of the columns of [`Poseidon2WideCols`], which start every row of the
+    /// trace, in order.
+    ///
+    /// A column of an array field is named after the field and its index, such as
+    /// `external_rounds[2].sbox_deg_3[5]`.
+    pub fn columns_layout() -> Vec<String> {
+        let read_access = [
+            "value",
+            "prev_timestamp",
+            "diff_16bit_limb",
+            "diff_12bit_limb",
+        ];
+        let mut names = ["timestamp", "dst", "left", "right"]
+            .map(|name| format!("memory.{}", name))
+            .to_vec();
+        for i in 0..WIDTH {
+            names.extend(
+                read_access
+                    .iter()
+                    .map(|name| format!("memory.input[{}].{}", i, name)),
+            );
+        }
+        for i in 0..WIDTH {
+            names.extend(
+                once("prev_value")
+                    .chain(read_access)
+                    .map(|name| format!("memory.output[{}].{}", i, name)),
+            );
+        }
+        names.push("memory.is_real".to_string());
+        for r in 0..NUM_EXTERNAL_ROUNDS {
+            for field in ["state", "sbox_deg_3"] {
+                names
+                    .extend((0..WIDTH).map(|i| format!("external_rounds[{}].{}[{}]", r, field, i)));
+            }
+        }
+        names.extend((0..WIDTH).map(|i| format!("internal_rounds.state[{}]", i)));
+        names.extend((0..NUM_INTERNAL_ROUNDS - 1).map(|r| format!("internal_rounds.s0[{}]", r)));
+        names
+            .extend((0..NUM_INTERNAL_ROUNDS).map(|r| format!("internal_rounds.sbox_deg_3[{}]", r)));
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::borrow::BorrowMut;
+
+    use crate::memory::MemoryCols;
+    use crate::poseidon2_wide::external::{
+        Poseidon2WideChip, Poseidon2WideCols, NUM_POSEIDON2_WIDE_COLS,
+    };
+    use itertools::Itertools;
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+
+    #[test]
+    fn columns_layout() {
+        let names = Poseidon2WideChip::<3>::columns_layout();
+        assert_eq!(names.len(), NUM_POSEIDON2_WIDE_COLS);
+        assert_eq!(names.iter().unique().count(), NUM_POSEIDON2_WIDE_COLS);
+
+        // Spot check the names against the offsets of the columns.
+        let mut row = [BabyBear::zero(); NUM_POSEIDON2_WIDE_COLS];
+        let cols: &mut Poseidon2WideCols<BabyBear> = row.as_mut_slice().borrow_mut();
+        cols.memory.is_real = BabyBear::one();
+        cols.external_rounds[3].sbox_deg_3[7] = BabyBear::two();
+        *cols.memory.output[5].value_mut() = BabyBear::from_canonical_u32(3);
+        cols.internal_rounds.s0[11] = BabyBear::from_canonical_u32(4);
+        for (name, value) in [
+            ("memory.is_real", 1),
+            ("external_rounds[3].sbox_deg_3[7]", 2),
+            ("memory.output[5].value", 3),
+            ("internal_rounds.s0[11]", 4),
+        ] {
+            let index = names.iter().position(|n| n == name).unwrap();
+            assert_eq!(row[index], BabyBear::from_canonical_u32(value), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use core::mem::size_of;
+use p3_air::{Air, BaseAir};
+use p3_field::PrimeField32;
+use p3_matrix::Matrix;
+use p3_uni_stark::{Proof, VerificationError};
+use sp1_core::stark::{StarkGenericConfig, UniConfig, Val};
+
+use super::columns::Poseidon2MemCols;
+use super::{Poseidon2WideChip, Poseidon2WideOptions, NUM_POSEIDON2_WIDE_COLS};
+use crate::air::SP1RecursionAirBuilder;
+use crate::runtime::ExecutionRecord;
+
+/// The version of the column layout of [`Poseidon2WideCols`](super::Poseidon2WideCols).
+///
+/// This must be bumped whenever the layout or the constraints change, so that proofs of an older
+/// layout can be told apart from, and verified separately to, proofs of the current one.
+///
+/// Layouts 1 to 3 held the initialization vector right after the memory columns, followed by the
+/// negation flag from layout 2 on and by the rotation selectors from layout 3 on. Layout 4 moved
+/// them into optional groups appended after the other columns.
+pub const LAYOUT_VERSION: u32 = 4;
+
+/// The number of memory columns, which start every row in every layout.
+const NUM_MEMORY_COLS: usize = size_of::<Poseidon2MemCols<u8>>();
+
+/// The constraints of a proof of an older layout, asserted by reordering its columns into the
+/// current layout and evaluating the chip on them.
+///
+/// The groups held by every row of an older layout are enabled on the chip, along with the ones
+/// it already enables, so the reordered columns are those of the chip in the current layout.
+struct LegacyLayoutAir<const DEGREE: usize> {
+    chip: Poseidon2WideChip<DEGREE>,
+    /// The index in a row of the older layout of every column of the current layout.
+    column_order: Vec<usize>,
+}
+
+impl<const DEGREE: usize> LegacyLayoutAir<DEGREE> {
+    /// Returns the constraints of `chip` in the layout `version`, or `None` if `version` is not
+    /// an older layout.
+    fn new(chip: &Poseidon2WideChip<DEGREE>, version: u32) -> Option<Self> {
+        if !(1..LAYOUT_VERSION).contains(&version) {
+            return None;
+        }
+        let chip = Poseidon2WideChip::new(Poseidon2WideOptions {
+            initialization_vector: true,
+            negated_output: version >= 2,
+            rotated_output: version >= 3,
+            ..chip.options.clone()
+        });
+
+        // The inline groups precede the rounds in the older layout and follow the other appended
+        // groups in the current one, in the same order.
+        let num_inline_cols =
+            <Poseidon2WideChip<DEGREE> as BaseAir<u8>>::width(&chip) - chip.iv_cols_offset();
+        let num_appended_cols = chip.iv_cols_offset() - NUM_POSEIDON2_WIDE_COLS;
+        let rounds = NUM_MEMORY_COLS + num_inline_cols..NUM_POSEIDON2_WIDE_COLS + num_inline_cols;
+        let column_order = (0..NUM_MEMORY_COLS)
+            .chain(rounds.clone())
+            .chain(rounds.end..rounds.end + num_appended_cols)
+            .chain(NUM_MEMORY_COLS..NUM_MEMORY_COLS + num_inline_cols)
+            .collect();
+        Some(Self { chip, column_order })
+    }
+}
+
+impl<F, const DEGREE: usize> BaseAir<F> for LegacyLayoutAir<DEGREE> {
+    fn width(&self) -> usize {
+        self.column_order.len()
+    }
+}
+
+impl<AB, const DEGREE: usize> Air<AB> for LegacyLayoutAir<DEGREE>
+where
+    AB: SP1RecursionAirBuilder,
+{
+    fn eval(&self, builder: &mut AB) {
+        let main = builder.main();
+        let (local, next) = (main.row_slice(0), main.row_slice(1));
+        let reorder = |row: &[AB::Var]| {
+            self.column_order
+                .iter()
+                .map(|&i| row[i])
+                .collect::<Vec<_>>()
+        };
+        let (local, next) = (reorder(&local), reorder(&next));
+        self.chip.eval_rows(builder, &local, &next);
+    }
+}
+
+/// A proof of the chip, tagged with the version of the column layout it was generated with.
+pub struct VersionedProof<SC: StarkGenericConfig> {
+    pub layout_version: u32,
+    pub proof: Proof<UniConfig<SC>>,
+}
+
+/// An error returned when verifying a [`VersionedProof`].
+#[derive(Debug)]
+pub enum LayoutVerificationError {
+    /// The proof was generated with a layout that this verifier does not support.
+    UnsupportedLayout(u32),
+    /// The proof is invalid for its layout.
+    Verification(VerificationError),
+}
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Proves the permutations of `record` like [`Self::prove`], tagging the proof with the
+    /// current [`LAYOUT_VERSION`].
+    pub fn prove_versioned<SC>(
+        &self,
+        config: &SC,
+        record: &ExecutionRecord<SC::Val>,
+    ) -> Option<VersionedProof<SC>>
+    where
+        SC: StarkGenericConfig,
+        SC::Val: PrimeField32,
+    {
+        self.prove(config, record).map(|proof| VersionedProof {
+            layout_version: LAYOUT_VERSION,
+            proof,
+        })
+    }
+
+    /// Verifies a versioned proof with the constraints of the layout it was generated with.
+    ///
+    /// A proof of an older layout is checked against the constraints of the chip with its columns
+    /// reordered into the current layout, so it must have been generated with the same options.
+    /// The public values are laid out in the same way in every layout.
+    pub fn verify_versioned<SC: StarkGenericConfig>(
+        &self,
+        config: &SC,
+        proof: &VersionedProof<SC>,
+        public_values: &[Val<SC>],
+    ) -> Result<(), LayoutVerificationError> {
+        if proof.layout_version == LAYOUT_VERSION {
+            return self
+                .verify_only(config, &proof.proof, public_values)
+                .map_err(LayoutVerificationError::Verification);
+        }
+
+        let air = LegacyLayoutAir::new(self, proof.layout_version).ok_or(
+            LayoutVerificationError::UnsupportedLayout(proof.layout_version),
+        )?;
+        if public_values.len() != self.num_public_values() {
+            return Err(LayoutVerificationError::Verification(
+                VerificationError::InvalidProofShape,
+            ));
+        }
+        p3_uni_stark::verify(
+            &UniConfig(config.clone()),
+            &air,
+            &mut config.challenger(),
+            &proof.proof,
+            &public_values.to_vec(),
+        )
+        .map_err(LayoutVerificationError::Verification)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LegacyLayoutAir;
+    use crate::poseidon2_wide::external::tests::test_record;
+    use crate::poseidon2_wide::external::{
+        LayoutVerificationError, Poseidon2WideChip, VersionedProof, LAYOUT_VERSION,
+    };
+    use crate::runtime::ExecutionRecord;
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_matrix::Matrix;
+    use sp1_core::air::MachineAir;
+    use sp1_core::stark::{StarkGenericConfig, UniConfig};
+    use sp1_core::utils::BabyBearPoseidon2Inner;
+
+    #[test]
+    fn versioned_proof() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let config = BabyBearPoseidon2Inner::new();
+
+        let mut proof = chip.prove_versioned(&config, &test_record(4)).unwrap();
+        assert_eq!(proof.layout_version, LAYOUT_VERSION);
+        chip.verify_versioned(&config, &proof, &[])
+            .expect("expected proof to be valid");
+
+        // The same proof tagged with a layout the verifier does not know about is rejected.
+        proof.layout_version = LAYOUT_VERSION + 1;
+        assert!(matches!(
+            chip.verify_versioned(&config, &proof, &[]),
+            Err(LayoutVerificationError::UnsupportedLayout(v)) if v == LAYOUT_VERSION + 1
+        ));
+    }
+
+    /// Proves `record` in the older layout `version`, as a prover of that layout did: the trace
+    /// is generated in the current layout and its columns are moved to where that layout held
+    /// them.
+    fn prove_legacy(
+        chip: &Poseidon2WideChip<3>,
+        config: &BabyBearPoseidon2Inner,
+        record: &ExecutionRecord<BabyBear>,
+        version: u32,
+    ) -> VersionedProof<BabyBearPoseidon2Inner> {
+        let air = LegacyLayoutAir::new(chip, version).unwrap();
+        let trace: RowMajorMatrix<BabyBear> = air
+            .chip
+            .generate_trace(record, &mut ExecutionRecord::default());
+        let width = trace.width();
+        let mut values = vec![BabyBear::zero(); trace.values.len()];
+        for (legacy, row) in values
+            .chunks_exact_mut(width)
+            .zip(trace.values.chunks_exact(width))
+        {
+            for (&i, &value) in air.column_order.iter().zip(row) {
+                legacy[i] = value;
+            }
+        }
+
+        let proof = p3_uni_stark::prove(
+            &UniConfig(config.clone()),
+            &air,
+            &mut config.challenger(),
+            RowMajorMatrix::new(values, width),
+            &vec![],
+        );
+        VersionedProof {
+            layout_version: version,
+            proof,
+        }
+    }
+
+    #[test]
+    fn legacy_layout_proof() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let config = BabyBearPoseidon2Inner::new();
+
+        let mut proof = prove_legacy(&chip, &config, &test_record(4), 1);
+        chip.verify_versioned(&config, &proof, &[])
+            .expect("expected proof to be valid in its layout");
+
+        // The same proof is rejected under the constraints of any other layout.
+        for version in [2, 3, LAYOUT_VERSION] {
+            proof.layout_version = version;
+            assert!(matches!(
+                chip.verify_versioned(&config, &proof, &[]),
+                Err(LayoutVerificationError::Verification(_))
+            ));
+        }
+        proof.layout_version = 0;
+        assert!(matches!(
+            chip.verify_versioned(&config, &proof, &[]),
+            Err(LayoutVerificationError::UnsupportedLayout(0))
+        ));
+    }
+}
@@ -0,0 +1,672 @@
+pub mod air;
+pub mod columns;
+pub mod layout;
+pub mod prove;
+pub mod public_values;
+pub mod round_constants;
+pub mod trace;
+
+pub use columns::{
+    Poseidon2MemCols, Poseidon2WideByteInputCols, Poseidon2WideCols, Poseidon2WideIvCols,
+    Poseidon2WideNegationCols, Poseidon2WideRotationCols, Poseidon2WideRoundConstantCols,
+    Poseidon2WideVariableRoundCols, BYTES_PER_LANE, NUM_BYTE_INPUT_COLS, NUM_IV_COLS,
+    NUM_NEGATION_COLS, NUM_ROTATION_COLS, NUM_ROUND_CONSTANT_COLS, NUM_VARIABLE_ROUND_COLS,
+};
+pub use layout::{LayoutVerificationError, VersionedProof, LAYOUT_VERSION};
+pub use public_values::{
+    proof_commitment, trace_digest, DigestVerificationError, SqueezeTap, TraceDigestError,
+};
+pub use round_constants::{
+    schedules_compatible, RoundConstantSavings, RoundConstantsError, RoundSchedule,
+};
+pub use trace::{flatten_rows, pack_input_bytes};
+
+use core::mem::size_of;
+use core::ops::Range;
+use p3_air::BaseAir;
+use p3_field::{Field, PrimeField32};
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+use sp1_core::air::MachineAir;
+use sp1_core::stark::MachineRecord;
+use std::borrow::BorrowMut;
+use tracing::instrument;
+
+use self::trace::lane_bytes;
+use crate::poseidon2::Poseidon2Event;
+use crate::range_check::{RangeCheckEvent, RangeCheckOpcode};
+
+use crate::poseidon2_wide::pass::{EventRowPopulator, Poseidon2EventRows};
+use crate::runtime::{ExecutionRecord, RecursionProgram, DIGEST_SIZE};
+
+/// The number of main trace columns for `AddChip`.
+pub const NUM_POSEIDON2_WIDE_COLS: usize = size_of::<Poseidon2WideCols<u8>>();
+
+/// The width of the permutation.
+pub const WIDTH: usize = 16;
+
+pub const NUM_EXTERNAL_ROUNDS: usize = 8;
+
+pub const NUM_INTERNAL_ROUNDS: usize = 13;
+pub const NUM_ROUNDS: usize = NUM_EXTERNAL_ROUNDS + NUM_INTERNAL_ROUNDS;
+
+/// The options of a [`Poseidon2WideChip`], all of which are unset by default.
+#[derive(Clone, Default)]
+pub struct Poseidon2WideOptions {
+    /// The bus the input lanes are received from when they are produced by another chip, such as
+    /// an ALU chip, rather than read from memory.
+    ///
+    /// The bus id is the first value of a table interaction, so it must not collide with the
+    /// opcodes that are also received through `receive_table`.
+    ///
+    /// Several chips, such as a hashing chip and a challenger chip, may send on the same bus.
+    /// Their multiplicities add up, so the record must hold one event for every permutation sent
+    /// by any of them, including the permutations that more than one of them requests.
+    pub input_bus: Option<u32>,
+
+    /// The bus the output lanes are sent on, tagged with the timestamp the output is written at,
+    /// for a consumer such as the next permutation of a sponge.
+    ///
+    /// Setting it to the same bus as `input_bus` chains the permutations of the chip: the output
+    /// of a permutation at timestamp `t` is the input of the permutation at timestamp `t + 1`.
+    pub output_bus: Option<u32>,
+
+    /// Whether to warn about suspicious records during trace generation, such as records in
+    /// which most permutations have all-equal input lanes.
+    pub strict: bool,
+
+    /// The number of auxiliary public values, such as a block number, that the proof binds to
+    /// the permutations.
+    ///
+    /// They are absorbed into the initial state by constraining the first `num_aux_public` lanes
+    /// of the initialization vector of every permutation to be equal to the public values, so
+    /// they require `initialization_vector` to be set.
+    pub num_aux_public: usize,
+
+    /// Whether the proof exposes a digest in its public values, after the auxiliary ones.
+    ///
+    /// The digest is read from the lanes of the output of the first permutation of the trace
+    /// selected by `squeeze_tap`.
+    pub expose_digest: bool,
+
+    /// The lanes of the output read as the digest when it is exposed.
+    pub squeeze_tap: SqueezeTap,
+
+    /// Whether the input of the first permutation of the trace is a public value, after the
+    /// auxiliary ones and the digest.
+    ///
+    /// This lets a verifier who knows the input, such as the data behind a hash it is checking,
+    /// supply it publicly. The first row must then be real, so the proof attests the output of
+    /// the permutation of that input.
+    pub public_input: bool,
+
+    /// Whether the input of the first permutation of the trace is imported from a boundary
+    /// value exported by the previous shard, as a public value after the public input.
+    pub import_boundary: bool,
+
+    /// Whether the output of the last real permutation of the trace is exported as a boundary
+    /// value for the next shard, as a public value after the imported boundary.
+    ///
+    /// A sponge split across shards is continued by proving the next shard with
+    /// `import_boundary` against the same boundary value, which the verifier checks the two
+    /// proofs agree on.
+    pub export_boundary: bool,
+
+    /// Whether the proof carries the digest of its trace, as computed by [`trace_digest`], in
+    /// its last `DIGEST_SIZE` public values.
+    ///
+    /// The public values are observed by the challenger, so the digest is bound to the proof,
+    /// but a trace cannot constrain its own commitment, so the constraints do not check it. A
+    /// verifier checking it with [`Poseidon2WideChip::verify_with_trace_digest`] learns which
+    /// trace the prover claims to have proven, as made by
+    /// [`Poseidon2WideChip::prove_with_trace_digest`].
+    pub expose_trace_digest: bool,
+
+    /// A table of round constants, indexed by round, to use instead of `RC_16_30_U32`.
+    ///
+    /// This is meant for experimenting with other parameter sets. The constants are used both
+    /// to generate the trace and in the constraints, so a proof only verifies against a chip
+    /// with the same table.
+    pub custom_round_constants: Option<[[u32; WIDTH]; NUM_ROUNDS]>,
+
+    /// A value to fill the input lanes of the padding rows with, instead of zero.
+    ///
+    /// This is a debugging aid which makes the padding rows stand out in trace dumps. The rest of
+    /// a padding row is the permutation of the sentinel without round constants, since those are
+    /// gated by `is_real`, so the constraints still hold.
+    ///
+    /// The constraints pin the input and output of padding rows to those of the sentinel, so a
+    /// proof only verifies against a chip with the same sentinel.
+    pub padding_sentinel: Option<u32>,
+
+    /// Whether the chip proves only the linear layers and round constants of the permutation,
+    /// without the sboxes, as computed by [`permute_linear_with_constants`](crate::poseidon2_wide::permute_linear_with_constants).
+    ///
+    /// This is INSECURE: the resulting map is affine, so it is neither collision resistant nor
+    /// one-way, and it must never be used for hashing, commitments or challenges. It is only
+    /// meant as a fast provable checksum during development. The sbox columns are constrained to
+    /// zero in this mode.
+    pub linear_only: bool,
+
+    /// Whether to only fill the columns of the interactions of every row, leaving the columns of
+    /// the rounds at zero.
+    ///
+    /// This is a debugging aid for testing the memory and bus interactions of the chip on their
+    /// own. The resulting trace does not satisfy the constraints of the permutation, so it must
+    /// never be proven.
+    pub interactions_only: bool,
+
+    /// A predicate on the timestamp of every event selecting the events the chip proves, such as
+    /// the ones of a range of timestamps when the permutations of a record are split across
+    /// shards.
+    ///
+    /// The events it rejects get no row, so their memory accesses and bus interactions must be
+    /// proven elsewhere, and the chip does not give a row to every event for the one-pass
+    /// generation of `pass`. Without it, every event of the record is proven.
+    pub clk_filter: Option<fn(u32) -> bool>,
+
+    /// The bus the round constants are received from, when they are read from a shared
+    /// [`RoundConstantsChip`](crate::poseidon2_wide::constants::RoundConstantsChip) rather than
+    /// baked into the constraints.
+    ///
+    /// The constants of every real row are then held in [`Poseidon2WideRoundConstantCols`],
+    /// appended to the columns of the chip, and each round of them is received from the bus.
+    /// The table must hold the same constants as [`Poseidon2WideChip::round_constant_table`] for
+    /// the traces to balance.
+    pub rc_bus: Option<u32>,
+
+    /// Whether every event may apply fewer internal rounds than `NUM_INTERNAL_ROUNDS`, as set by
+    /// its `num_internal_rounds`.
+    ///
+    /// The number of rounds of every row is then held in [`Poseidon2WideVariableRoundCols`],
+    /// appended after the round constant columns. Every round is still computed, and the state
+    /// after the last selected one is the input of the second half of the external rounds.
+    ///
+    /// The number of rounds is not part of the memory or bus interactions, so whoever consumes
+    /// the outputs must constrain it separately. Without this set, every event must apply all of
+    /// the internal rounds.
+    pub variable_internal_rounds: bool,
+
+    /// Whether every input lane is the packing of `BYTES_PER_LANE` bytes, so that the proof
+    /// attests that the input encodes `BYTES_PER_LANE * WIDTH` bytes, as packed by
+    /// [`pack_input_bytes`].
+    ///
+    /// The bytes of every row are then held in [`Poseidon2WideByteInputCols`], appended after
+    /// the internal round columns, and each of them is range checked as a `U8`. The range checks
+    /// are recorded by `generate_dependencies`.
+    pub byte_input: bool,
+
+    /// Whether every event may add its initialization vector to the input before the initial
+    /// linear layer.
+    ///
+    /// The vector of every row is then held in [`Poseidon2WideIvCols`], appended after the byte
+    /// columns. It is required by `num_aux_public`, whose public values are bound to it. Without
+    /// this set, the vector of every event must be zero.
+    pub initialization_vector: bool,
+
+    /// Whether every event may negate its output, as set by its `negate_output`.
+    ///
+    /// The flag of every row is then held in [`Poseidon2WideNegationCols`], appended after the
+    /// initialization vector columns. Without this set, no event may negate its output.
+    pub negated_output: bool,
+
+    /// Whether every event may rotate its output lanes, as set by its `output_rotation`.
+    ///
+    /// The rotation of every row is then held in [`Poseidon2WideRotationCols`], appended after
+    /// the negation column. Without this set, no event may rotate its output.
+    pub rotated_output: bool,
+
+    /// Whether the chip proves a compression, of which only the first `DIGEST_SIZE` output lanes
+    /// are consumed.
+    ///
+    /// Only those lanes are then constrained against the permutation, written to memory and
+    /// sent on `output_bus`, and the rest of the output columns carry no constraint. This is for
+    /// a runtime that only writes the digest half of the output, and it cannot be combined with
+    /// `rotated_output` or `export_boundary`, which read every lane.
+    pub compress: bool,
+
+    /// The maximum number of constraints `eval` may assert, checked by
+    /// [`Poseidon2WideChip::assert_constraint_budget`], or [`DEFAULT_CONSTRAINT_BUDGET`] if unset.
+    pub constraint_budget: Option<usize>,
+}
+
+/// A chip that implements addition for the opcode ADD.
+#[derive(Clone, Default)]
+pub struct Poseidon2WideChip<const DEGREE: usize> {
+    /// The options the chip proves the permutations with.
+    pub options: Poseidon2WideOptions,
+}
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Builds a chip proving the permutations with `options`.
+    pub const fn new(options: Poseidon2WideOptions) -> Self {
+        Self { options }
+    }
+}
+
+/// The default maximum number of constraints of the chip.
+///
+/// `eval` asserts about a constraint per lane of every round, plus a few per lane for the
+/// memory accesses and the public values, so this leaves room for a few times as many
+/// constraints while catching any that grow quadratically.
+pub const DEFAULT_CONSTRAINT_BUDGET: usize = 4 * NUM_ROUNDS * WIDTH;
+
+/// The dimensions of a trace, computed without generating it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceInfo {
+    /// The number of rows holding a permutation.
+    pub real_rows: usize,
+    /// The number of rows after padding to a power of two.
+    pub padded_rows: usize,
+    /// The number of cells in the padded trace.
+    pub total_cells: usize,
+    /// The estimated size of the padded trace in bytes.
+    pub est_bytes: usize,
+}
+
+/// An error returned by [`Poseidon2WideChip::dry_run`], pointing at the first event the chip
+/// cannot prove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsupportedEventError {
+    /// The event applies more than `NUM_INTERNAL_ROUNDS` internal rounds.
+    TooManyInternalRounds { event: usize },
+    /// The event applies fewer internal rounds, and the chip does not set
+    /// `variable_internal_rounds`.
+    FewerInternalRounds { event: usize },
+    /// The event has an initialization vector, and the chip does not set
+    /// `initialization_vector`.
+    InitializationVector { event: usize },
+    /// The event negates its output, and the chip does not set `negated_output`.
+    NegatedOutput { event: usize },
+    /// The event rotates its output by `WIDTH` lanes or more.
+    RotationOutOfRange { event: usize },
+    /// The event rotates its output, and the chip does not set `rotated_output`.
+    RotatedOutput { event: usize },
+}
+
+impl<F: PrimeField32, const DEGREE: usize> MachineAir<F> for Poseidon2WideChip<DEGREE> {
+    type Record = ExecutionRecord<F>;
+
+    type Program = RecursionProgram<F>;
+
+    fn name(&self) -> String {
+        format!("Poseidon2Wide {}", DEGREE)
+    }
+
+    fn generate_dependencies(&self, input: &Self::Record, output: &mut Self::Record) {
+        if !self.options.byte_input {
+            return;
+        }
+
+        let range_checks = self
+            .real_events(input)
+            .iter()
+            .flat_map(|event| event.input)
+            .flat_map(lane_bytes)
+            .map(|byte| RangeCheckEvent::new(RangeCheckOpcode::U8, byte as u16))
+            .collect::<Vec<_>>();
+        output.add_range_check_events(&range_checks);
+    }
+
+    #[instrument(
+        name = "generate poseidon2 wide trace",
+        level = "debug",
+        skip_all,
+        fields(num_events = input.poseidon2_events.len(), shard = input.index())
+    )]
+    fn generate_trace(
+        &self,
+        input: &ExecutionRecord<F>,
+        _: &mut ExecutionRecord<F>,
+    ) -> RowMajorMatrix<F> {
+        tracing::debug!("Nb poseidon2 events: {:?}", input.poseidon2_events.len());
+
+        if let Some(uniform) = self.degenerate_inputs(input) {
+            tracing::warn!(
+                "{} of {} poseidon2 events have all-equal input lanes",
+                uniform,
+                input.poseidon2_events.len()
+            );
+        }
+
+        let events = self.real_events(input);
+        let trace = self.finish_trace(self.generate_rows(&events), &events);
+
+        tracing::debug!(
+            "poseidon2 wide trace dims is width: {:?}, height: {:?}",
+            trace.width(),
+            trace.height()
+        );
+
+        trace
+    }
+
+    fn included(&self, record: &Self::Record) -> bool {
+        !self.real_events(record).is_empty()
+    }
+}
+
+impl<F: PrimeField32, const DEGREE: usize> Poseidon2EventRows<F> for Poseidon2WideChip<DEGREE> {
+    fn event_row_width(&self) -> usize {
+        NUM_POSEIDON2_WIDE_COLS
+    }
+
+    fn event_row_populator(&self) -> EventRowPopulator<'_, F> {
+        let rc = self.round_constants::<F>();
+        Box::new(move |event, row| self.populate_row(event, &rc, row.borrow_mut()))
+    }
+
+    fn trace_from_event_rows(
+        &self,
+        rows: RowMajorMatrix<F>,
+        events: &[Poseidon2Event<F>],
+    ) -> RowMajorMatrix<F> {
+        self.finish_trace(rows, events)
+    }
+}
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Returns the output lanes of every permutation that the chip constrains and writes back:
+    /// the digest lanes if it proves a compression, or all of them.
+    pub fn output_lanes(&self) -> Range<usize> {
+        if self.options.compress {
+            0..DIGEST_SIZE
+        } else {
+            0..WIDTH
+        }
+    }
+
+    /// Returns the number of events with all-equal input lanes if the chip is strict and they
+    /// make up more than half of the events in `record`.
+    ///
+    /// Inputs like `[1; WIDTH]` are usually the artifact of a bug, or of a test, rather than real
+    /// data, so this is only meant as a debugging aid.
+    pub fn degenerate_inputs<F: PrimeField32>(&self, record: &ExecutionRecord<F>) -> Option<usize> {
+        if !self.options.strict {
+            return None;
+        }
+        let uniform = record.uniform_poseidon2_inputs();
+        (2 * uniform > record.poseidon2_events.len()).then_some(uniform)
+    }
+
+    /// Computes the dimensions of the trace that `generate_trace` would produce for `record`,
+    /// without building it, after checking that the chip can prove every event of the trace.
+    ///
+    /// Trace generation does not check the events, so a record rejected here gives a trace that
+    /// does not verify rather than a panic.
+    pub fn dry_run<F: PrimeField32>(
+        &self,
+        record: &ExecutionRecord<F>,
+    ) -> Result<TraceInfo, UnsupportedEventError> {
+        let events = self.real_events(record);
+        for (i, event) in events.iter().enumerate() {
+            self.check_event(i, event)?;
+        }
+
+        let real_rows = events.len();
+        let padded_rows = padded_height(real_rows);
+        let total_cells = padded_rows * <Self as BaseAir<F>>::width(self);
+
+        Ok(TraceInfo {
+            real_rows,
+            padded_rows,
+            total_cells,
+            est_bytes: total_cells * size_of::<F>(),
+        })
+    }
+
+    /// Checks that the chip can prove `event`, the event `index` of the trace.
+    fn check_event<F: PrimeField32>(
+        &self,
+        index: usize,
+        event: &Poseidon2Event<F>,
+    ) -> Result<(), UnsupportedEventError> {
+        if event.num_internal_rounds > NUM_INTERNAL_ROUNDS {
+            return Err(UnsupportedEventError::TooManyInternalRounds { event: index });
+        }
+        if !self.options.variable_internal_rounds
+            && event.num_internal_rounds != NUM_INTERNAL_ROUNDS
+        {
+            return Err(UnsupportedEventError::FewerInternalRounds { event: index });
+        }
+        if !self.options.initialization_vector && !event.iv.iter().all(F::is_zero) {
+            return Err(UnsupportedEventError::InitializationVector { event: index });
+        }
+        if !self.options.negated_output && event.negate_output {
+            return Err(UnsupportedEventError::NegatedOutput { event: index });
+        }
+        if event.output_rotation >= WIDTH {
+            return Err(UnsupportedEventError::RotationOutOfRange { event: index });
+        }
+        if !self.options.rotated_output && event.output_rotation != 0 {
+            return Err(UnsupportedEventError::RotatedOutput { event: index });
+        }
+        Ok(())
+    }
+}
+
+/// Returns the fraction of the cells of `trace` that are zero, or zero for an empty trace.
+///
+/// This is a diagnostic for the cost of committing to a trace: padding rows and the unused
+/// auxiliary columns are mostly zero, and a high density would favour a sparse commitment scheme.
+pub fn zero_density<F: Field>(trace: &RowMajorMatrix<F>) -> f64 {
+    if trace.values.is_empty() {
+        return 0.0;
+    }
+    let zeros = trace.values.iter().filter(|value| value.is_zero()).count();
+    zeros as f64 / trace.values.len() as f64
+}
+
+/// Returns the height of the trace of `real_rows` rows, once padded by `pad_to_power_of_two`.
+fn padded_height(real_rows: usize) -> usize {
+    if real_rows <= 1 {
+        8
+    } else {
+        real_rows.next_power_of_two()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use core::borrow::Borrow;
+
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::{
+        Poseidon2WideChip, Poseidon2WideCols, Poseidon2WideOptions, WIDTH,
+    };
+    use crate::runtime::ExecutionRecord;
+    use crate::stark::RecursionAirWideDeg3;
+    use itertools::Itertools;
+    use p3_baby_bear::BabyBear;
+    use p3_field::{AbstractField, PrimeField32};
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_matrix::Matrix;
+    use p3_uni_stark::Proof;
+    use sp1_core::air::MachineAir;
+    use sp1_core::stark::UniConfig;
+    use sp1_core::utils::{BabyBearPoseidon2, BabyBearPoseidon2Inner};
+
+    /// Builds a record with `num_events` permutations of distinct inputs.
+    pub(crate) fn test_record(num_events: u32) -> ExecutionRecord<BabyBear> {
+        let mut input_exec = ExecutionRecord::<BabyBear>::default();
+        for i in 0..num_events {
+            let input =
+                core::array::from_fn(|j| BabyBear::from_canonical_u32(i * WIDTH as u32 + j as u32));
+            input_exec
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+        input_exec
+    }
+
+    /// Proves the permutations of `record` against `public_values` and checks that the proof
+    /// verifies, returning it for further checks.
+    pub(crate) fn prove_and_verify(
+        chip: &Poseidon2WideChip<3>,
+        record: &ExecutionRecord<BabyBear>,
+        public_values: &[BabyBear],
+    ) -> Proof<UniConfig<BabyBearPoseidon2Inner>> {
+        let config = BabyBearPoseidon2Inner::new();
+        let proof = chip
+            .prove_with_public_values(&config, record, public_values)
+            .expect("expected the record to have events");
+        chip.verify_only(&config, &proof, public_values)
+            .expect("expected proof to be valid");
+        proof
+    }
+
+    /// A test checking that the chip is included exactly when its trace has real rows.
+    #[test]
+    fn included_matches_trace() {
+        let chip = Poseidon2WideChip::<3>::default();
+        for num_events in [0, 1, 5] {
+            let record = test_record(num_events);
+            let trace: RowMajorMatrix<BabyBear> =
+                chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+            let num_real_rows = trace
+                .rows()
+                .map(|row| row.collect_vec())
+                .filter(|row| {
+                    let cols: &Poseidon2WideCols<BabyBear> = row.as_slice().borrow();
+                    cols.memory.is_real == BabyBear::one()
+                })
+                .count();
+
+            assert_eq!(num_real_rows, chip.real_events(&record).len());
+            assert_eq!(
+                <Poseidon2WideChip<3> as MachineAir<BabyBear>>::included(&chip, &record),
+                num_real_rows > 0
+            );
+        }
+    }
+
+    /// A test checking that a chip whose filter rejects every event of a record is left out of
+    /// the shard, in agreement with its trace holding only padding rows.
+    #[test]
+    fn included_with_all_events_filtered() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            clk_filter: Some(|_| false),
+            ..Default::default()
+        });
+        let record = test_record(5);
+        assert!(chip.real_events(&record).is_empty());
+        assert!(!<Poseidon2WideChip<3> as MachineAir<BabyBear>>::included(
+            &chip, &record
+        ));
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        assert!(trace.rows().all(|row| {
+            let row = row.collect_vec();
+            let cols: &Poseidon2WideCols<BabyBear> = row.as_slice().borrow();
+            cols.memory.is_real == BabyBear::zero()
+        }));
+
+        // Keeping the events of odd timestamps gives a row to those alone.
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            clk_filter: Some(|clk| clk % 2 == 1),
+            ..Default::default()
+        });
+        let mut record = test_record(5);
+        for (i, event) in record.poseidon2_events.iter_mut().enumerate() {
+            event.clk = BabyBear::from_canonical_usize(i);
+        }
+        let clks = chip
+            .real_events(&record)
+            .iter()
+            .map(|event| event.clk.as_canonical_u32())
+            .collect_vec();
+        assert_eq!(clks, [1, 3]);
+        assert!(<Poseidon2WideChip<3> as MachineAir<BabyBear>>::included(
+            &chip, &record
+        ));
+    }
+
+    /// A test checking that the dry run dimensions match the generated trace.
+    #[test]
+    fn dry_run() {
+        let chip = Poseidon2WideChip::<3>::default();
+
+        for num_events in [0, 1, 5, 8, 100] {
+            let mut input_exec = ExecutionRecord::<BabyBear>::default();
+            for i in 0..num_events {
+                input_exec
+                    .poseidon2_events
+                    .push(Poseidon2Event::dummy_from_input(
+                        [BabyBear::from_canonical_u32(i); WIDTH],
+                    ));
+            }
+
+            let info = chip
+                .dry_run(&input_exec)
+                .expect("expected the events to be supported");
+            let trace: RowMajorMatrix<BabyBear> =
+                chip.generate_trace(&input_exec, &mut ExecutionRecord::<BabyBear>::default());
+
+            assert_eq!(info.real_rows, num_events as usize);
+            assert_eq!(info.padded_rows, trace.height());
+            assert_eq!(info.total_cells, trace.values.len());
+            assert_eq!(
+                info.est_bytes,
+                trace.values.len() * core::mem::size_of::<BabyBear>()
+            );
+        }
+    }
+
+    /// A test checking that a record without events is skipped, both when proving the chip on its
+    /// own and by the recursion machine.
+    #[test]
+    fn empty_record_skipped() {
+        let config = BabyBearPoseidon2Inner::new();
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = ExecutionRecord::<BabyBear>::default();
+
+        assert!(chip.prove(&config, &record).is_none());
+
+        let machine = RecursionAirWideDeg3::machine(BabyBearPoseidon2::default());
+        let name = MachineAir::<BabyBear>::name(&chip);
+        assert!(machine.shard_chips(&record).all(|chip| chip.name() != name));
+    }
+
+    #[test]
+    fn degenerate_inputs() {
+        let strict = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            strict: true,
+            ..Default::default()
+        });
+
+        let mut ones = ExecutionRecord::<BabyBear>::default();
+        for _ in 0..8 {
+            ones.poseidon2_events
+                .push(Poseidon2Event::dummy_from_input([BabyBear::one(); WIDTH]));
+        }
+        assert_eq!(strict.degenerate_inputs(&ones), Some(8));
+        assert_eq!(
+            Poseidon2WideChip::<3>::default().degenerate_inputs(&ones),
+            None
+        );
+
+        assert_eq!(strict.degenerate_inputs(&test_record(8)), None);
+    }
+
+    #[test]
+    fn zero_density() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record.poseidon2_events =
+            vec![Poseidon2Event::dummy_from_input([BabyBear::one(); WIDTH]); 108173];
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+
+        // The padding rows, about a sixth of the trace, permute zero without round constants and
+        // are all zero. Of the real rows, about a fifth of the cells are zero: the previous
+        // values and timestamps of the memory accesses, while the round states are not.
+        let density = super::zero_density(&trace);
+        assert!((0.3..0.5).contains(&density), "density {density}");
+
+        assert_eq!(
+            super::zero_density(&RowMajorMatrix::<BabyBear>::new(vec![], 1)),
+            0.0
+        );
+    }
+}
@@ -0,0 +1,662 @@
+use core::mem::size_of;
+use p3_field::{AbstractField, Field, PrimeField32};
+use p3_fri::FriConfig;
+use p3_matrix::dense::RowMajorMatrix;
+use p3_uni_stark::{Proof, VerificationError};
+use sp1_core::air::MachineAir;
+use sp1_core::stark::{MachineRecord, StarkGenericConfig, UniConfig, Val};
+use sp1_core::utils::{check_fri_security, FriSecurityError};
+use tracing::instrument;
+
+use super::{padded_height, Poseidon2WideChip, NUM_POSEIDON2_WIDE_COLS};
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::probe_record;
+use crate::runtime::{ExecutionRecord, DIGEST_SIZE};
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Estimates the memory in bytes needed to prove `num_events` permutations with a FRI
+    /// blowup of `2^log_blowup`.
+    ///
+    /// The estimate adds up the buffers alive at the peak of proving:
+    ///
+    /// - the padded trace, of `height × width` base field elements;
+    /// - its low degree extension, `2^log_blowup` times larger, and the Merkle tree committing to
+    ///   it, of about two digests per leaf;
+    /// - the quotient polynomial, evaluated over a domain `DEGREE - 1` (rounded up to a power of
+    ///   two) times larger than the trace, in the extension field, along with its low degree
+    ///   extension and Merkle tree;
+    /// - the FRI layers, whose folded codewords add up to about twice the first one, each with a
+    ///   Merkle tree.
+    ///
+    /// Allocator overhead and short-lived temporaries are not accounted for, so the peak resident
+    /// memory of a prover is only expected to be within a small factor of the estimate.
+    pub fn estimate_prove_memory<SC: StarkGenericConfig>(
+        num_events: usize,
+        log_blowup: usize,
+    ) -> usize {
+        let val = size_of::<Val<SC>>();
+        let challenge = size_of::<SC::Challenge>();
+        let digest = DIGEST_SIZE * val;
+
+        let height = padded_height(num_events);
+        let lde_height = height << log_blowup;
+        let quotient_degree = (DEGREE - 1).next_power_of_two();
+
+        let trace = height * NUM_POSEIDON2_WIDE_COLS * val;
+        let trace_commitment = lde_height * NUM_POSEIDON2_WIDE_COLS * val + 2 * lde_height * digest;
+        let quotient = height * quotient_degree * challenge
+            + quotient_degree * lde_height * challenge
+            + 2 * lde_height * digest;
+        let fri = 2 * lde_height * challenge + 2 * lde_height * digest;
+
+        trace + trace_commitment + quotient + fri
+    }
+
+    /// Returns the number of Merkle openings the verifier checks in a proof of a trace of
+    /// `trace_height` rows under the FRI parameters `fri_config`, for estimating the cost of
+    /// verifying the proof in a recursion circuit.
+    ///
+    /// Every query opens the two committed batches, the trace and the quotient chunks, at the
+    /// queried index, and then one sibling in each FRI layer. The codeword of
+    /// `trace_height << log_blowup` evaluations is folded in half until it is `2^log_blowup` long,
+    /// so there are `log2(trace_height)` layers.
+    ///
+    /// Panics if `trace_height` is not a power of two.
+    pub fn fri_opening_count<M>(fri_config: &FriConfig<M>, trace_height: usize) -> usize {
+        assert!(
+            trace_height.is_power_of_two(),
+            "the trace height must be a power of two"
+        );
+        let num_batches = 2;
+        let num_layers = trace_height.trailing_zeros() as usize;
+        fri_config.num_queries * (num_batches + num_layers)
+    }
+
+    /// Proves the permutations of `record`, or returns `None` if it has none.
+    ///
+    /// A record without any Poseidon2 events is skipped rather than proven, in the same way that
+    /// the machine leaves the chip out of the shards for which `included` is false.
+    pub fn prove<SC>(
+        &self,
+        config: &SC,
+        record: &ExecutionRecord<SC::Val>,
+    ) -> Option<Proof<UniConfig<SC>>>
+    where
+        SC: StarkGenericConfig,
+        SC::Val: PrimeField32,
+    {
+        self.prove_with_public_values(config, record, &[])
+    }
+
+    /// Proves the permutations of `record` like [`Self::prove`], after checking that
+    /// `fri_config`, the FRI parameters of `config`, reaches `security_bits` bits of security for
+    /// the height of the trace.
+    ///
+    /// The parameters of the PCS cannot be read back from `config`, so the caller passes the
+    /// FRI configuration it was built with.
+    pub fn prove_checked<SC, M>(
+        &self,
+        config: &SC,
+        fri_config: &FriConfig<M>,
+        record: &ExecutionRecord<SC::Val>,
+        security_bits: usize,
+    ) -> Result<Option<Proof<UniConfig<SC>>>, FriSecurityError>
+    where
+        SC: StarkGenericConfig,
+        SC::Val: PrimeField32,
+    {
+        let log_trace_height =
+            padded_height(self.real_events(record).len()).trailing_zeros() as usize;
+        let challenge_bits = SC::Challenge::order().bits() as usize;
+        check_fri_security(fri_config, challenge_bits, log_trace_height, security_bits)?;
+        Ok(self.prove(config, record))
+    }
+
+    /// Proves the permutations of `record` like [`Self::prove`], against the auxiliary public
+    /// values `aux_public`.
+    pub fn prove_with_public_values<SC>(
+        &self,
+        config: &SC,
+        record: &ExecutionRecord<SC::Val>,
+        aux_public: &[Val<SC>],
+    ) -> Option<Proof<UniConfig<SC>>>
+    where
+        SC: StarkGenericConfig,
+        SC::Val: PrimeField32,
+    {
+        self.prove_with_challenger(config, record, aux_public, &mut config.challenger())
+    }
+
+    /// Generates the trace of `events` and proves it like [`Self::prove`], returning both so
+    /// that the caller can inspect the trace that was proven.
+    ///
+    /// Unlike [`Self::prove`], an empty slice of events is still proven, with a trace of padding
+    /// rows only.
+    pub fn prove_events<SC>(
+        &self,
+        config: &SC,
+        events: &[Poseidon2Event<SC::Val>],
+    ) -> (RowMajorMatrix<SC::Val>, Proof<UniConfig<SC>>)
+    where
+        SC: StarkGenericConfig,
+        SC::Val: PrimeField32,
+    {
+        let record = ExecutionRecord {
+            poseidon2_events: events.to_vec(),
+            ..Default::default()
+        };
+        let trace = <Self as MachineAir<SC::Val>>::generate_trace(
+            self,
+            &record,
+            &mut ExecutionRecord::default(),
+        );
+        let proof = p3_uni_stark::prove(
+            &UniConfig(config.clone()),
+            self,
+            &mut config.challenger(),
+            trace.clone(),
+            &Vec::new(),
+        );
+        (trace, proof)
+    }
+
+    /// Proves and discards a record holding a single permutation, so that the one-off costs of a
+    /// first proof, such as starting the worker threads and faulting in the memory of the prover,
+    /// are paid before the proofs that follow are timed.
+    ///
+    /// The round constants are converted once per trace rather than cached, so they need no
+    /// warming up of their own.
+    pub fn warm_up<SC>(&self, config: &SC)
+    where
+        SC: StarkGenericConfig,
+        SC::Val: PrimeField32,
+    {
+        let probe = probe_record::<Val<SC>>();
+        let aux_public = vec![Val::<SC>::zero(); self.options.num_aux_public];
+        let public_values = self.public_values(&probe, &aux_public);
+        let proof = self.prove_with_public_values(config, &probe, &public_values);
+        std::hint::black_box(proof);
+    }
+
+    /// Proves the permutations of `record` like [`Self::prove_with_public_values`], starting from
+    /// the state of `challenger`.
+    #[instrument(
+        name = "prove poseidon2 wide",
+        level = "debug",
+        skip_all,
+        fields(num_events = record.poseidon2_events.len(), shard = record.index())
+    )]
+    pub fn prove_with_challenger<SC>(
+        &self,
+        config: &SC,
+        record: &ExecutionRecord<SC::Val>,
+        aux_public: &[Val<SC>],
+        challenger: &mut SC::Challenger,
+    ) -> Option<Proof<UniConfig<SC>>>
+    where
+        SC: StarkGenericConfig,
+        SC::Val: PrimeField32,
+    {
+        if !<Self as MachineAir<SC::Val>>::included(self, record) {
+            return None;
+        }
+
+        let trace = <Self as MachineAir<SC::Val>>::generate_trace(
+            self,
+            record,
+            &mut ExecutionRecord::default(),
+        );
+        Some(p3_uni_stark::prove(
+            &UniConfig(config.clone()),
+            self,
+            challenger,
+            trace,
+            &aux_public.to_vec(),
+        ))
+    }
+
+    /// Verifies a proof of this chip from just the proof and its public values.
+    ///
+    /// The trace is only needed by the prover. The verifier starts from the initial challenger of
+    /// `config` and checks the constraints against the openings contained in the proof.
+    ///
+    /// The constraints index into the public values, so a proof whose public values are not
+    /// [`Self::num_public_values`] long is rejected as malformed before they are evaluated.
+    pub fn verify_only<SC: StarkGenericConfig>(
+        &self,
+        config: &SC,
+        proof: &Proof<UniConfig<SC>>,
+        public_values: &[Val<SC>],
+    ) -> Result<(), VerificationError> {
+        if public_values.len() != self.num_public_values() {
+            return Err(VerificationError::InvalidProofShape);
+        }
+        let mut challenger = config.challenger();
+        p3_uni_stark::verify(
+            &UniConfig(config.clone()),
+            self,
+            &mut challenger,
+            proof,
+            &public_values.to_vec(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::tests::{prove_and_verify, test_record};
+    use crate::poseidon2_wide::external::{Poseidon2WideChip, Poseidon2WideOptions, WIDTH};
+    use crate::poseidon2_wide::NativePoseidon2;
+    use crate::runtime::ExecutionRecord;
+    use itertools::Itertools;
+    use p3_baby_bear::BabyBear;
+    use p3_challenger::{CanObserve, DuplexChallenger, FieldChallenger};
+    use p3_commit::{Pcs, PolynomialSpace};
+    use p3_field::{AbstractExtensionField, AbstractField, Field};
+    use p3_fri::FriConfig;
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_matrix::Matrix;
+    use p3_symmetric::Permutation;
+    use sp1_core::air::MachineAir;
+    use sp1_core::stark::StarkGenericConfig;
+    use sp1_core::utils::{
+        check_fri_security, inner_compact_fri_config, inner_fri_config, inner_perm,
+        uni_stark_prove, uni_stark_verify, BabyBearPoseidon2, BabyBearPoseidon2Inner,
+        FriSecurityError, InnerChallenge, InnerChallenger, InnerPcs, InnerPcsProof,
+    };
+
+    /// A test proving 2^10 permuations
+    #[test]
+    fn poseidon2_wide_prove_babybear() {
+        let config = BabyBearPoseidon2Inner::new();
+        let mut challenger = config.challenger();
+
+        let chip = Poseidon2WideChip::<3>::default();
+
+        let test_inputs = (0..1000)
+            .map(|i| [BabyBear::from_canonical_u32(i); WIDTH])
+            .collect_vec();
+
+        let mut input_exec = ExecutionRecord::<BabyBear>::default();
+        for input in test_inputs {
+            input_exec
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&input_exec, &mut ExecutionRecord::<BabyBear>::default());
+
+        let proof = uni_stark_prove(&config, &chip, &mut challenger, trace);
+
+        let mut challenger = config.challenger();
+        uni_stark_verify(&config, &chip, &mut challenger, &proof)
+            .expect("expected proof to be valid");
+    }
+
+    /// A test checking that `prove_events` proves the trace that `generate_trace` gives.
+    #[test]
+    fn prove_events() {
+        let config = BabyBearPoseidon2Inner::new();
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(12);
+
+        let (trace, proof) = chip.prove_events(&config, &record.poseidon2_events);
+        let expected: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        assert_eq!(trace.values, expected.values);
+        assert_eq!(trace.width(), expected.width());
+        chip.verify_only(&config, &proof, &[])
+            .expect("expected proof to be valid");
+
+        let (trace, proof) = chip.prove_events(&config, &[]);
+        assert_eq!(trace.height(), 8);
+        chip.verify_only(&config, &proof, &[])
+            .expect("expected proof of padding rows to be valid");
+    }
+
+    /// A test proving permutations of inputs using all the lanes as data, with no capacity.
+    #[test]
+    fn full_width_input() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..8u32 {
+            // Every lane is a nonzero, unstructured value.
+            let input: [BabyBear; WIDTH] = core::array::from_fn(|j| {
+                BabyBear::from_canonical_u32(i * WIDTH as u32 + j as u32 + 1)
+                    * BabyBear::from_canonical_u32(0x1234_5679)
+            });
+            let event = Poseidon2Event::dummy_from_input(input);
+            assert_eq!(event.result_array, inner_perm().permute(input));
+            record.poseidon2_events.push(event);
+        }
+
+        prove_and_verify(&chip, &record, &[]);
+    }
+
+    /// A test comparing the memory estimate to the peak resident memory of a proof.
+    ///
+    /// Peak resident memory is tracked per process, so this is ignored by default and meant to be
+    /// run on its own with `cargo test --release -- --ignored prove_memory_estimate`.
+    #[test]
+    #[ignore]
+    #[cfg(target_os = "linux")]
+    fn prove_memory_estimate() {
+        fn status_kb(field: &str) -> usize {
+            let status = std::fs::read_to_string("/proc/self/status").unwrap();
+            let line = status.lines().find(|line| line.starts_with(field)).unwrap();
+            line.split_whitespace().nth(1).unwrap().parse().unwrap()
+        }
+
+        let num_events = 1 << 12;
+        let config = BabyBearPoseidon2Inner::new();
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(num_events as u32);
+
+        // Reset the peak resident memory to the current one.
+        std::fs::write("/proc/self/clear_refs", "5").unwrap();
+        let baseline = status_kb("VmRSS:");
+        let proof = chip.prove(&config, &record).unwrap();
+        let measured = (status_kb("VmHWM:") - baseline) * 1024;
+        drop(proof);
+
+        let estimate = Poseidon2WideChip::<3>::estimate_prove_memory::<BabyBearPoseidon2Inner>(
+            num_events,
+            inner_fri_config().log_blowup,
+        );
+        assert!(
+            measured <= 4 * estimate && estimate <= 4 * measured,
+            "estimated {estimate} bytes, measured {measured} bytes"
+        );
+    }
+
+    /// A test checking that warming up does not change the proofs that follow it.
+    #[test]
+    fn warm_up() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let config = BabyBearPoseidon2Inner::new();
+        let record = test_record(64);
+
+        let cold = chip.prove(&config, &record).unwrap();
+        chip.warm_up(&config);
+        let warm = chip.prove(&config, &record).unwrap();
+        chip.verify_only(&config, &warm, &[])
+            .expect("expected proof to be valid");
+        assert_eq!(
+            bincode::serialize(&cold).unwrap(),
+            bincode::serialize(&warm).unwrap()
+        );
+    }
+
+    /// A best-effort test that the first proof after `warm_up` is not slower than the ones after
+    /// it.
+    ///
+    /// The bound is loose, so that only a first proof paying for a sizeable initialization fails
+    /// it, but wall-clock time depends on the machine, so this is ignored by default and meant to
+    /// be run on its own with `cargo test --release -- --ignored warm_up_timing`.
+    #[test]
+    #[ignore]
+    fn warm_up_timing() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let config = BabyBearPoseidon2Inner::new();
+        let record = test_record(64);
+
+        chip.warm_up(&config);
+        let timings = (0..3)
+            .map(|_| {
+                let start = Instant::now();
+                std::hint::black_box(chip.prove(&config, &record));
+                start.elapsed()
+            })
+            .collect_vec();
+
+        let slowest_later = *timings[1..].iter().max().unwrap();
+        assert!(
+            timings[0] < 2 * slowest_later,
+            "the first proof after warming up is slower: {:?}",
+            timings
+        );
+    }
+
+    #[test]
+    fn prove_checked() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let config = BabyBearPoseidon2Inner::new();
+        let record = test_record(4);
+
+        // The default parameters: 100 queries at a blowup of 2, with 16 bits of proof of work.
+        let fri_config = inner_fri_config();
+        let proof = chip
+            .prove_checked(&config, &fri_config, &record, 100)
+            .unwrap()
+            .unwrap();
+        chip.verify_only(&config, &proof, &[])
+            .expect("expected proof to be valid");
+
+        let too_few = FriConfig {
+            num_queries: 10,
+            ..inner_fri_config()
+        };
+        assert_eq!(
+            chip.prove_checked(&config, &too_few, &record, 100)
+                .unwrap_err(),
+            FriSecurityError::TooFewQueries {
+                required: 84,
+                actual: 10,
+            }
+        );
+
+        // The challenge field has 124 bits, which is not enough for 124 bits of security.
+        assert!(matches!(
+            chip.prove_checked(&config, &fri_config, &record, 124),
+            Err(FriSecurityError::TooFewQueries { .. })
+        ));
+        let many_queries = FriConfig {
+            num_queries: 124,
+            ..inner_fri_config()
+        };
+        assert_eq!(
+            chip.prove_checked(&config, &many_queries, &record, 124)
+                .unwrap_err(),
+            FriSecurityError::ChallengeFieldTooSmall {
+                challenge_bits: 124,
+                log_degree: 4,
+            }
+        );
+    }
+
+    /// A test checking that the chip proves under a configuration sampling its challenges from
+    /// the degree 4 extension of BabyBear, with a challenger built from the native permutation.
+    #[test]
+    fn extension_field_challenges() {
+        type Challenge = <BabyBearPoseidon2Inner as StarkGenericConfig>::Challenge;
+        assert_eq!(<Challenge as AbstractExtensionField<BabyBear>>::D, 4);
+
+        // A challenger over the native permutation samples the same challenges as the one of the
+        // configuration, and they do not lie in the base field.
+        let config = BabyBearPoseidon2Inner::new();
+        let mut challenger = config.challenger();
+        let mut native = DuplexChallenger::<BabyBear, NativePoseidon2, WIDTH>::new(NativePoseidon2);
+        let values = (0..20).map(BabyBear::from_canonical_u32).collect_vec();
+        challenger.observe_slice(&values);
+        native.observe_slice(&values);
+        for _ in 0..4 {
+            let challenge: Challenge = challenger.sample_ext_element();
+            assert_eq!(native.sample_ext_element::<Challenge>(), challenge);
+            assert!(challenge.as_base_slice()[1..].iter().any(|x| !x.is_zero()));
+        }
+
+        let chip = Poseidon2WideChip::<3>::default();
+        prove_and_verify(&chip, &test_record(16), &[]);
+    }
+
+    /// A test verifying a proof from just the proof and the public values, with the trace and the
+    /// record it was generated from dropped beforehand.
+    #[test]
+    fn poseidon2_wide_verify_only() {
+        let config = BabyBearPoseidon2Inner::new();
+        let mut challenger = config.challenger();
+
+        let chip = Poseidon2WideChip::<3>::default();
+
+        let mut input_exec = ExecutionRecord::<BabyBear>::default();
+        for i in 0..16 {
+            input_exec
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(
+                    [BabyBear::from_canonical_u32(i); WIDTH],
+                ));
+        }
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&input_exec, &mut ExecutionRecord::<BabyBear>::default());
+        let proof = uni_stark_prove(&config, &chip, &mut challenger, trace);
+        drop(input_exec);
+
+        chip.verify_only(&config, &proof, &[])
+            .expect("expected proof to be valid");
+
+        // The openings are checked against the constraints of the verifying chip, so a chip
+        // without the sboxes rejects the proof.
+        let linear = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            linear_only: true,
+            ..Default::default()
+        });
+        assert!(linear.verify_only(&config, &proof, &[]).is_err());
+    }
+
+    /// A test proving under the compact config, which is cheaper to verify in a recursion circuit,
+    /// and checking that its proofs have fewer FRI queries, and so fewer openings, than the
+    /// default ones at a similar security.
+    #[test]
+    fn compact_proof() {
+        let (default_fri, compact_fri) = (inner_fri_config(), inner_compact_fri_config());
+        assert!(compact_fri.num_queries < default_fri.num_queries);
+        for fri in [&default_fri, &compact_fri] {
+            check_fri_security(fri, 124, 20, 100).unwrap();
+        }
+
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(16);
+        let proof_size = |config: &BabyBearPoseidon2Inner| {
+            let proof = chip.prove(config, &record).unwrap();
+            chip.verify_only(config, &proof, &[])
+                .expect("expected proof to be valid");
+            bincode::serialize(&proof).unwrap().len()
+        };
+
+        // Every query opens the trace and the quotient along each FRI layer, so the query proofs
+        // make up most of a proof and the compact one is smaller.
+        assert!(
+            proof_size(&BabyBearPoseidon2Inner::compact())
+                < proof_size(&BabyBearPoseidon2Inner::new())
+        );
+    }
+
+    #[test]
+    fn fri_opening_count() {
+        let chip = Poseidon2WideChip::<3>::default();
+        for (config, fri) in [
+            (BabyBearPoseidon2Inner::new(), inner_fri_config()),
+            (
+                BabyBearPoseidon2Inner::compact(),
+                inner_compact_fri_config(),
+            ),
+        ] {
+            for num_events in [1, 16, 100] {
+                let record = test_record(num_events);
+                let trace: RowMajorMatrix<BabyBear> =
+                    chip.generate_trace(&record, &mut ExecutionRecord::default());
+                let height = trace.height();
+
+                // Open a trace and its quotient chunks as the prover does, through the PCS whose
+                // opening proof exposes its queries.
+                let domain =
+                    <InnerPcs as Pcs<InnerChallenge, InnerChallenger>>::natural_domain_for_degree(
+                        &config.pcs,
+                        height,
+                    );
+                let chunk_width = <InnerChallenge as AbstractExtensionField<BabyBear>>::D;
+                let quotient =
+                    RowMajorMatrix::new(vec![BabyBear::one(); height * chunk_width], chunk_width);
+                let (trace_commit, trace_data) =
+                    <InnerPcs as Pcs<InnerChallenge, InnerChallenger>>::commit(
+                        &config.pcs,
+                        vec![(domain, trace)],
+                    );
+                let (quotient_commit, quotient_data) =
+                    <InnerPcs as Pcs<InnerChallenge, InnerChallenger>>::commit(
+                        &config.pcs,
+                        vec![(domain, quotient)],
+                    );
+                let mut challenger = config.challenger();
+                challenger.observe(trace_commit);
+                challenger.observe(quotient_commit);
+                let zeta: InnerChallenge = challenger.sample_ext_element();
+                let zeta_next = domain.next_point(zeta).unwrap();
+                let (_, opening_proof): (_, InnerPcsProof) = config.pcs.open(
+                    vec![
+                        (&trace_data, vec![vec![zeta, zeta_next]]),
+                        (&quotient_data, vec![vec![zeta]]),
+                    ],
+                    &mut challenger,
+                );
+
+                let openings = opening_proof
+                    .fri_proof
+                    .query_proofs
+                    .iter()
+                    .map(|query| query.commit_phase_openings.len())
+                    .sum::<usize>()
+                    + opening_proof
+                        .query_openings
+                        .iter()
+                        .map(Vec::len)
+                        .sum::<usize>();
+                assert_eq!(
+                    Poseidon2WideChip::<3>::fri_opening_count(&fri, height),
+                    openings
+                );
+            }
+        }
+    }
+
+    /// Proves `record` under each of `a` and `b` and verifies each proof under the config it was
+    /// generated with, so that a chip which only works under one of them is caught.
+    fn assert_verifies_under_configs<SC1, SC2>(
+        chip: &Poseidon2WideChip<3>,
+        record: &ExecutionRecord<BabyBear>,
+        a: &SC1,
+        b: &SC2,
+    ) where
+        SC1: StarkGenericConfig<Val = BabyBear>,
+        SC2: StarkGenericConfig<Val = BabyBear>,
+    {
+        let proof = chip.prove(a, record).unwrap();
+        chip.verify_only(a, &proof, &[])
+            .expect("expected proof to be valid under the first config");
+        let proof = chip.prove(b, record).unwrap();
+        chip.verify_only(b, &proof, &[])
+            .expect("expected proof to be valid under the second config");
+    }
+
+    /// A test proving the same trace under configs with other permutations and FRI parameters.
+    #[test]
+    fn verifies_under_configs() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(16);
+        assert_verifies_under_configs(
+            &chip,
+            &record,
+            &BabyBearPoseidon2Inner::new(),
+            &BabyBearPoseidon2::new(),
+        );
+        assert_verifies_under_configs(
+            &chip,
+            &record,
+            &BabyBearPoseidon2Inner::new(),
+            &BabyBearPoseidon2Inner::compact(),
+        );
+    }
+}
@@ -0,0 +1,661 @@
+use core::iter::once;
+use core::ops::Range;
+use p3_commit::Pcs;
+use p3_field::PrimeField32;
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+use p3_uni_stark::{Proof, VerificationError};
+use sp1_core::air::MachineAir;
+use sp1_core::stark::{Com, StarkGenericConfig, UniConfig, Val};
+
+use super::{Poseidon2WideChip, WIDTH};
+use crate::poseidon2_wide::poseidon2_hash;
+use crate::runtime::{ExecutionRecord, DIGEST_SIZE, HASH_RATE};
+
+/// An error returned by [`Poseidon2WideChip::verify_and_extract`].
+#[derive(Debug)]
+pub enum DigestVerificationError {
+    /// The chip does not expose a digest of the expected length, or the public values are too
+    /// short to hold one.
+    NoDigest,
+    /// The proof is invalid.
+    Verification(VerificationError),
+}
+
+/// An error returned by [`Poseidon2WideChip::verify_with_trace_digest`].
+#[derive(Debug)]
+pub enum TraceDigestError {
+    /// The chip does not expose the digest of its trace, or the public values are not
+    /// [`Poseidon2WideChip::num_public_values`] long.
+    NoDigest,
+    /// The proof carries the digest of another trace than the expected one.
+    DigestMismatch,
+    /// The proof is invalid.
+    Verification(VerificationError),
+}
+
+/// The lanes of the output of a permutation that are read as its digest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SqueezeTap {
+    /// The first `DIGEST_SIZE` lanes, like the `Poseidon2Compress` opcode.
+    #[default]
+    Digest,
+    /// The first `HASH_RATE` lanes, which a sponge absorbs into and squeezes from, so that the
+    /// digest is the first block squeezed by a sponge over the permutation.
+    Rate,
+    /// The first `n` lanes, at most `WIDTH`, for a digest of another length such as the short
+    /// digests of [`poseidon2_hash_with_len`](crate::poseidon2_wide::poseidon2_hash_with_len).
+    Prefix(usize),
+}
+
+// The digest returned by `verify_and_extract` is `DIGEST_SIZE` lanes long for either of the
+// default and rate taps.
+const _: () = assert!(HASH_RATE == DIGEST_SIZE);
+
+impl SqueezeTap {
+    /// Returns the lanes of the output read as the digest.
+    pub fn lanes(self) -> Range<usize> {
+        match self {
+            SqueezeTap::Digest => 0..DIGEST_SIZE,
+            SqueezeTap::Rate => 0..HASH_RATE,
+            SqueezeTap::Prefix(n) => 0..n,
+        }
+    }
+}
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Returns the number of public values of a proof of the chip, as laid out by
+    /// [`Self::public_values`].
+    pub fn num_public_values(&self) -> usize {
+        let mut num_public_values = self.options.num_aux_public;
+        if self.options.expose_digest {
+            num_public_values += self.options.squeeze_tap.lanes().len();
+        }
+        for exposed in [
+            self.options.public_input,
+            self.options.import_boundary,
+            self.options.export_boundary,
+        ] {
+            if exposed {
+                num_public_values += WIDTH;
+            }
+        }
+        if self.options.expose_trace_digest {
+            num_public_values += DIGEST_SIZE;
+        }
+        num_public_values
+    }
+
+    /// Returns the public values of a proof of `record`: the auxiliary public values, followed by
+    /// the digest if the chip exposes one, the input if it is public and the boundaries the chip
+    /// imports and exports.
+    ///
+    /// The digest of the trace depends on the PCS, so it is left out here and appended by
+    /// [`Self::prove_with_trace_digest`].
+    pub fn public_values<F: PrimeField32>(
+        &self,
+        record: &ExecutionRecord<F>,
+        aux_public: &[F],
+    ) -> Vec<F> {
+        let events = self.real_events(record);
+        let mut public_values = aux_public.to_vec();
+        if let Some(event) = events.first() {
+            if self.options.expose_digest {
+                public_values
+                    .extend_from_slice(&event.result_array[self.options.squeeze_tap.lanes()]);
+            }
+            if self.options.public_input {
+                public_values.extend_from_slice(&event.input);
+            }
+            if self.options.import_boundary {
+                public_values.extend_from_slice(&event.input);
+            }
+        }
+        if let Some(event) = events.last() {
+            if self.options.export_boundary {
+                public_values.extend_from_slice(&event.result_array);
+            }
+        }
+        public_values
+    }
+
+    /// Verifies a proof of this chip like [`Self::verify_only`] and returns the digest it exposes.
+    pub fn verify_and_extract<SC: StarkGenericConfig>(
+        &self,
+        config: &SC,
+        proof: &Proof<UniConfig<SC>>,
+        public_values: &[Val<SC>],
+    ) -> Result<[Val<SC>; DIGEST_SIZE], DigestVerificationError> {
+        self.verify_and_extract_with_len(config, proof, public_values)
+    }
+
+    /// Verifies a proof of this chip like [`Self::verify_and_extract`], for a chip whose tap
+    /// exposes a digest of `DIGEST_LEN` lanes.
+    pub fn verify_and_extract_with_len<SC: StarkGenericConfig, const DIGEST_LEN: usize>(
+        &self,
+        config: &SC,
+        proof: &Proof<UniConfig<SC>>,
+        public_values: &[Val<SC>],
+    ) -> Result<[Val<SC>; DIGEST_LEN], DigestVerificationError> {
+        let digest = public_values
+            .get(
+                self.options.num_aux_public
+                    ..self.options.num_aux_public + self.options.squeeze_tap.lanes().len(),
+            )
+            .filter(|_| {
+                self.options.expose_digest && self.options.squeeze_tap.lanes().len() == DIGEST_LEN
+            })
+            .ok_or(DigestVerificationError::NoDigest)?;
+        self.verify_only(config, proof, public_values)
+            .map_err(DigestVerificationError::Verification)?;
+        Ok(core::array::from_fn(|i| digest[i]))
+    }
+
+    /// Proves the permutations of `record` like [`Self::prove_with_public_values`], appending the
+    /// digest of the trace to the public values for a chip with `expose_trace_digest` set.
+    ///
+    /// Returns the proof along with all of its public values, or `None` if the record has no
+    /// permutations.
+    pub fn prove_with_trace_digest<SC>(
+        &self,
+        config: &SC,
+        record: &ExecutionRecord<SC::Val>,
+        aux_public: &[Val<SC>],
+    ) -> Option<(Proof<UniConfig<SC>>, Vec<Val<SC>>)>
+    where
+        SC: StarkGenericConfig,
+        SC::Val: PrimeField32,
+        Com<SC>: Into<[Val<SC>; DIGEST_SIZE]>,
+    {
+        assert!(
+            self.options.expose_trace_digest,
+            "the chip does not expose the digest of its trace"
+        );
+        if !<Self as MachineAir<SC::Val>>::included(self, record) {
+            return None;
+        }
+
+        let trace = <Self as MachineAir<SC::Val>>::generate_trace(
+            self,
+            record,
+            &mut ExecutionRecord::default(),
+        );
+        let mut public_values = self.public_values(record, aux_public);
+        let digest: [Val<SC>; DIGEST_SIZE] = trace_digest(config, &trace).into();
+        public_values.extend_from_slice(&digest);
+
+        let proof = p3_uni_stark::prove(
+            &UniConfig(config.clone()),
+            self,
+            &mut config.challenger(),
+            trace,
+            &public_values,
+        );
+        Some((proof, public_values))
+    }
+
+    /// Verifies a proof of this chip like [`Self::verify_only`], after checking that the digest
+    /// of the trace in its public values is `expected`, as computed by [`trace_digest`].
+    ///
+    /// This lets a verifier who knows the digest of a trace, but not the trace itself, check that
+    /// the proof claims to be a proof of that trace. The digest is not constrained against the
+    /// trace commitment of the proof, as explained on `expose_trace_digest`.
+    pub fn verify_with_trace_digest<SC: StarkGenericConfig>(
+        &self,
+        config: &SC,
+        proof: &Proof<UniConfig<SC>>,
+        public_values: &[Val<SC>],
+        expected: &Com<SC>,
+    ) -> Result<(), TraceDigestError>
+    where
+        Com<SC>: Into<[Val<SC>; DIGEST_SIZE]>,
+    {
+        if !self.options.expose_trace_digest || public_values.len() != self.num_public_values() {
+            return Err(TraceDigestError::NoDigest);
+        }
+        let expected: [Val<SC>; DIGEST_SIZE] = expected.clone().into();
+        if public_values[public_values.len() - DIGEST_SIZE..] != expected {
+            return Err(TraceDigestError::DigestMismatch);
+        }
+        self.verify_only(config, proof, public_values)
+            .map_err(TraceDigestError::Verification)
+    }
+}
+
+/// Returns the commitment to `proof` that an outer recursion circuit absorbs: the
+/// [`poseidon2_hash`] of its serialized bytes.
+pub fn proof_commitment<SC>(proof: &Proof<UniConfig<SC>>) -> [Val<SC>; DIGEST_SIZE]
+where
+    SC: StarkGenericConfig,
+    Val<SC>: PrimeField32,
+{
+    let bytes = bincode::serialize(proof).expect("failed to serialize proof");
+    bytes_commitment(&bytes)
+}
+
+/// Returns the digest of `trace`: the commitment to it under the PCS of `config`, which is the
+/// trace commitment of every proof of it.
+///
+/// The commitment is observed by the challenger before any challenge is drawn, so a proof
+/// verifying against a digest is a proof of the trace of that digest.
+pub fn trace_digest<SC: StarkGenericConfig>(
+    config: &SC,
+    trace: &RowMajorMatrix<Val<SC>>,
+) -> Com<SC> {
+    let pcs = config.pcs();
+    let domain = pcs.natural_domain_for_degree(trace.height());
+    let (commitment, _) = pcs.commit(vec![(domain, trace.clone())]);
+    commitment
+}
+
+/// Hashes `bytes` with [`poseidon2_hash`], packing them three at a time into field elements.
+///
+/// Three bytes always fit in a canonical element of a 31-bit field. The number of bytes is
+/// absorbed first, so inputs that only differ by trailing zero bytes get different commitments.
+fn bytes_commitment<F: PrimeField32>(bytes: &[u8]) -> [F; DIGEST_SIZE] {
+    let input = once(F::from_canonical_usize(bytes.len()))
+        .chain(bytes.chunks(3).map(|chunk| {
+            let word = chunk
+                .iter()
+                .rev()
+                .fold(0u32, |word, &byte| (word << 8) | byte as u32);
+            F::from_canonical_u32(word)
+        }))
+        .collect::<Vec<_>>();
+    poseidon2_hash(&input)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::iter::once;
+
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::tests::{prove_and_verify, test_record};
+    use crate::poseidon2_wide::external::{
+        DigestVerificationError, Poseidon2WideChip, Poseidon2WideOptions, SqueezeTap,
+        TraceDigestError, WIDTH,
+    };
+    use crate::runtime::{ExecutionRecord, DIGEST_SIZE, HASH_RATE};
+    use crate::stark::utils::check_constraints;
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_symmetric::{CryptographicHasher, PaddingFreeSponge};
+    use p3_uni_stark::VerificationError;
+    use sp1_core::air::MachineAir;
+    use sp1_core::utils::{inner_perm, BabyBearPoseidon2Inner};
+
+    #[test]
+    fn aux_public_values() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            num_aux_public: 1,
+            initialization_vector: true,
+            ..Default::default()
+        });
+        let config = BabyBearPoseidon2Inner::new();
+
+        let block_number = BabyBear::from_canonical_u32(1234);
+        let mut iv = [BabyBear::zero(); WIDTH];
+        iv[0] = block_number;
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..4 {
+            let input = [BabyBear::from_canonical_u32(i); WIDTH];
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input_with_iv(input, iv));
+        }
+
+        let proof = prove_and_verify(&chip, &record, &[block_number]);
+        assert!(chip
+            .verify_only(&config, &proof, &[block_number + BabyBear::one()])
+            .is_err());
+
+        // A trace computed in a different context does not satisfy the constraints.
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        assert!(check_constraints(&chip, &trace, &[block_number]).is_ok());
+        assert!(check_constraints(&chip, &trace, &[block_number + BabyBear::one()]).is_err());
+    }
+
+    #[test]
+    fn proof_commitment() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let config = BabyBearPoseidon2Inner::new();
+        let record = test_record(4);
+        let proof = chip.prove(&config, &record).unwrap();
+
+        let commitment = super::proof_commitment(&proof);
+        assert_eq!(commitment, super::proof_commitment(&proof));
+        assert_eq!(
+            commitment,
+            chip.prove(&config, &record)
+                .map(|proof| super::proof_commitment(&proof))
+                .unwrap()
+        );
+
+        let bytes = bincode::serialize(&proof).unwrap();
+        assert_eq!(commitment, super::bytes_commitment::<BabyBear>(&bytes));
+        for i in (0..bytes.len()).step_by(97).chain(once(bytes.len() - 1)) {
+            let mut tampered = bytes.clone();
+            tampered[i] ^= 1;
+            assert_ne!(commitment, super::bytes_commitment::<BabyBear>(&tampered));
+        }
+        let mut extended = bytes;
+        extended.push(0);
+        assert_ne!(commitment, super::bytes_commitment::<BabyBear>(&extended));
+    }
+
+    /// A test checking that public values of the wrong length are rejected rather than indexed
+    /// out of bounds by the constraints.
+    #[test]
+    fn public_values_of_wrong_length() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            num_aux_public: 2,
+            initialization_vector: true,
+            expose_digest: true,
+            export_boundary: true,
+            ..Default::default()
+        });
+        let config = BabyBearPoseidon2Inner::new();
+        let record = test_record(4);
+        let aux_public = [BabyBear::zero(); 2];
+        let public_values = chip.public_values(&record, &aux_public);
+        assert_eq!(public_values.len(), chip.num_public_values());
+        let proof = prove_and_verify(&chip, &record, &public_values);
+
+        for len in [0, 1, public_values.len() - 1] {
+            assert!(matches!(
+                chip.verify_only(&config, &proof, &public_values[..len]),
+                Err(VerificationError::InvalidProofShape)
+            ));
+        }
+        let mut extended = public_values.clone();
+        extended.push(BabyBear::zero());
+        assert!(matches!(
+            chip.verify_only(&config, &proof, &extended),
+            Err(VerificationError::InvalidProofShape)
+        ));
+    }
+
+    #[test]
+    fn verify_and_extract() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            expose_digest: true,
+            ..Default::default()
+        });
+        let config = BabyBearPoseidon2Inner::new();
+        let record = test_record(4);
+
+        let public_values = chip.public_values(&record, &[]);
+        let proof = chip
+            .prove_with_public_values(&config, &record, &public_values)
+            .unwrap();
+        let digest = chip
+            .verify_and_extract(&config, &proof, &public_values)
+            .unwrap();
+        assert_eq!(digest[..], record.poseidon2_events[0].result_array[..8]);
+
+        // A corrupted digest is rejected.
+        let mut corrupted = public_values.clone();
+        corrupted[0] += BabyBear::one();
+        assert!(matches!(
+            chip.verify_and_extract(&config, &proof, &corrupted),
+            Err(DigestVerificationError::Verification(_))
+        ));
+
+        // A chip that does not expose a digest has none to extract.
+        let proof = Poseidon2WideChip::<3>::default()
+            .prove(&config, &record)
+            .unwrap();
+        assert!(matches!(
+            Poseidon2WideChip::<3>::default().verify_and_extract(&config, &proof, &[]),
+            Err(DigestVerificationError::NoDigest)
+        ));
+    }
+
+    #[test]
+    fn verify_with_trace_digest() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            expose_trace_digest: true,
+            ..Default::default()
+        });
+        let config = BabyBearPoseidon2Inner::new();
+        let record = test_record(4);
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        let digest = super::trace_digest(&config, &trace);
+
+        // The digest is the last public values of the proof.
+        let (proof, public_values) = chip.prove_with_trace_digest(&config, &record, &[]).unwrap();
+        let lanes: [BabyBear; DIGEST_SIZE] = digest.clone().into();
+        assert_eq!(public_values, lanes.to_vec());
+        assert_eq!(public_values.len(), chip.num_public_values());
+        chip.verify_with_trace_digest(&config, &proof, &public_values, &digest)
+            .expect("expected proof to match the digest");
+
+        // The digest of another trace is rejected.
+        let other: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&test_record(5), &mut ExecutionRecord::default());
+        assert!(matches!(
+            chip.verify_with_trace_digest(
+                &config,
+                &proof,
+                &public_values,
+                &super::trace_digest(&config, &other)
+            ),
+            Err(TraceDigestError::DigestMismatch)
+        ));
+
+        // So are public values claiming another digest, since they are bound to the proof.
+        let mut forged = public_values.clone();
+        forged[0] += BabyBear::one();
+        assert!(matches!(
+            chip.verify_with_trace_digest(&config, &proof, &forged, &digest),
+            Err(TraceDigestError::DigestMismatch)
+        ));
+        assert!(chip.verify_only(&config, &proof, &forged).is_err());
+
+        // A chip that does not expose the digest cannot check it.
+        assert!(matches!(
+            Poseidon2WideChip::<3>::default().verify_with_trace_digest(
+                &config,
+                &proof,
+                &public_values,
+                &digest
+            ),
+            Err(TraceDigestError::NoDigest)
+        ));
+    }
+
+    /// A test proving a permutation of a public input, checking that the proof is bound to it.
+    #[test]
+    fn public_input() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            expose_digest: true,
+            public_input: true,
+            ..Default::default()
+        });
+        let config = BabyBearPoseidon2Inner::new();
+        let record = test_record(4);
+
+        let public_values = chip.public_values(&record, &[]);
+        assert_eq!(public_values.len(), 8 + WIDTH);
+        assert_eq!(public_values[8..], record.poseidon2_events[0].input[..]);
+        let proof = prove_and_verify(&chip, &record, &public_values);
+
+        // A different public input is rejected.
+        let mut corrupted = public_values.clone();
+        corrupted[8] += BabyBear::one();
+        assert!(chip.verify_only(&config, &proof, &corrupted).is_err());
+
+        // So is a proof computed for different inputs.
+        let mut other = test_record(5);
+        other.poseidon2_events.remove(0);
+        let other_proof = chip
+            .prove_with_public_values(&config, &other, &chip.public_values(&other, &[]))
+            .unwrap();
+        assert!(chip
+            .verify_only(&config, &other_proof, &public_values)
+            .is_err());
+    }
+
+    /// A test checking that the digest read through the rate tap is the first block squeezed by
+    /// a reference sponge over the permutation.
+    #[test]
+    fn squeeze_tap() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            expose_digest: true,
+            squeeze_tap: SqueezeTap::Rate,
+            ..Default::default()
+        });
+
+        let message: [BabyBear; HASH_RATE] =
+            core::array::from_fn(|i| BabyBear::from_canonical_usize(7 * i + 3));
+        let sponge = PaddingFreeSponge::<_, WIDTH, HASH_RATE, HASH_RATE>::new(inner_perm());
+        let expected = sponge.hash_iter(message);
+
+        // A sponge absorbs the message into the rate lanes of a zero state before permuting it.
+        let mut state = [BabyBear::zero(); WIDTH];
+        state[..HASH_RATE].copy_from_slice(&message);
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record
+            .poseidon2_events
+            .push(Poseidon2Event::dummy_from_input(state));
+
+        let public_values = chip.public_values(&record, &[]);
+        assert_eq!(public_values, expected);
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        check_constraints(&chip, &trace, &public_values).expect("expected constraints to hold");
+
+        // The constraints read exactly the tapped lanes.
+        let mut corrupted = public_values.clone();
+        corrupted[HASH_RATE - 1] += BabyBear::one();
+        assert!(check_constraints(&chip, &trace, &corrupted).is_err());
+    }
+
+    /// A test checking that short digests are deterministic truncations of the default digest,
+    /// and that a chip tapping a prefix of the output exposes the short digest.
+    #[test]
+    fn digest_len() {
+        use crate::poseidon2_wide::{
+            poseidon2_hash, poseidon2_hash_fields_with_len, poseidon2_hash_with_len,
+        };
+
+        for len in [0, 1, 8, 9, 33] {
+            let input = (0..len)
+                .map(|i| BabyBear::from_canonical_u32(5 * i + 1))
+                .collect::<Vec<_>>();
+            let long = poseidon2_hash_with_len::<_, 8>(&input);
+            let short = poseidon2_hash_with_len::<_, 4>(&input);
+            assert_eq!(long, poseidon2_hash(&input));
+            assert_eq!(long, poseidon2_hash_with_len::<_, 8>(&input));
+            assert_eq!(short, poseidon2_hash_with_len::<_, 4>(&input));
+            assert_eq!(short[..], long[..4]);
+        }
+        let parts: [&[BabyBear]; 2] = [&[BabyBear::one()], &[BabyBear::two(), BabyBear::one()]];
+        assert_eq!(
+            poseidon2_hash_fields_with_len::<_, 4>(&parts)[..],
+            poseidon2_hash_fields_with_len::<_, 8>(&parts)[..4]
+        );
+
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            expose_digest: true,
+            squeeze_tap: SqueezeTap::Prefix(4),
+            ..Default::default()
+        });
+        let message: [BabyBear; HASH_RATE] =
+            core::array::from_fn(|i| BabyBear::from_canonical_usize(7 * i + 3));
+        let mut state = [BabyBear::zero(); WIDTH];
+        state[..HASH_RATE].copy_from_slice(&message);
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record
+            .poseidon2_events
+            .push(Poseidon2Event::dummy_from_input(state));
+
+        let config = BabyBearPoseidon2Inner::new();
+        let public_values = chip.public_values(&record, &[]);
+        assert_eq!(public_values.len(), 4);
+        let proof = chip
+            .prove_with_public_values(&config, &record, &public_values)
+            .unwrap();
+        let digest = chip
+            .verify_and_extract_with_len::<_, 4>(&config, &proof, &public_values)
+            .unwrap();
+        assert_eq!(digest, poseidon2_hash_with_len::<_, 4>(&message));
+
+        // The digest cannot be read with another length than the tap.
+        assert!(matches!(
+            chip.verify_and_extract(&config, &proof, &public_values),
+            Err(DigestVerificationError::NoDigest)
+        ));
+    }
+
+    /// A test chaining two shards of a sponge through a boundary value exported by the first one
+    /// and imported by the second one.
+    #[test]
+    fn boundary_chained_shards() {
+        let exporter = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            export_boundary: true,
+            ..Default::default()
+        });
+        let importer = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            import_boundary: true,
+            ..Default::default()
+        });
+        let chain = |start: [BabyBear; WIDTH], len: usize| {
+            let mut record = ExecutionRecord::<BabyBear>::default();
+            let mut state = start;
+            for _ in 0..len {
+                let event = Poseidon2Event::dummy_from_input(state);
+                state = event.result_array;
+                record.poseidon2_events.push(event);
+            }
+            record
+        };
+
+        // The last permutation of the first shard is followed by a padding row with 3 events,
+        // and is the last row of the trace with 4.
+        for len in [3, 4] {
+            let first = chain(core::array::from_fn(BabyBear::from_canonical_usize), len);
+            let boundary = exporter.public_values(&first, &[]);
+            assert_eq!(
+                boundary,
+                first.poseidon2_events[len - 1].result_array.to_vec()
+            );
+            let trace: RowMajorMatrix<BabyBear> =
+                exporter.generate_trace(&first, &mut ExecutionRecord::default());
+            check_constraints(&exporter, &trace, &boundary).expect("expected constraints to hold");
+
+            // The first shard does not export the output of an earlier permutation.
+            let earlier = first.poseidon2_events[len - 2].result_array.to_vec();
+            assert!(check_constraints(&exporter, &trace, &earlier).is_err());
+        }
+
+        let config = BabyBearPoseidon2Inner::new();
+        let first = chain(core::array::from_fn(BabyBear::from_canonical_usize), 3);
+        let boundary: [BabyBear; WIDTH] = first.poseidon2_events[2].result_array;
+        let second = chain(boundary, 3);
+        assert_eq!(
+            exporter.public_values(&first, &[]),
+            importer.public_values(&second, &[])
+        );
+
+        prove_and_verify(&exporter, &first, &boundary);
+        let second_proof = prove_and_verify(&importer, &second, &boundary);
+
+        // A second shard that does not continue from the boundary is rejected.
+        let mut mismatched = boundary;
+        mismatched[0] += BabyBear::one();
+        assert!(importer
+            .verify_only(&config, &second_proof, &mismatched)
+            .is_err());
+        let other = chain(mismatched, 3);
+        let other_proof = importer
+            .prove_with_public_values(&config, &other, &mismatched)
+            .unwrap();
+        assert!(importer
+            .verify_only(&config, &other_proof, &boundary)
+            .is_err());
+    }
+}
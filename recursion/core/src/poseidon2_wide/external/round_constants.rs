@@ -0,0 +1,270 @@
+use p3_field::{AbstractField, PrimeField32};
+use sp1_primitives::RC_16_30_U32;
+
+use super::{Poseidon2WideChip, NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS, NUM_ROUNDS, WIDTH};
+
+/// An error returned by [`Poseidon2WideChip::validate_round_constants`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundConstantsError {
+    /// The table does not have one row per round.
+    WrongNumberOfRounds { expected: usize, actual: usize },
+    /// An entry is not the canonical representative of a field element.
+    NonCanonical {
+        round: usize,
+        lane: usize,
+        value: u32,
+    },
+    /// Every constant of a round is zero, so the round adds no constants.
+    ZeroRound(usize),
+}
+
+/// The rounds of a Poseidon2 permutation: how many of each kind there are and their constants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundSchedule {
+    pub num_external_rounds: usize,
+    pub num_internal_rounds: usize,
+    /// The round constants as `u32`s, indexed by round.
+    pub round_constants: Vec<[u32; WIDTH]>,
+}
+
+/// Returns whether `a` and `b` give the same permutation over `F`, so that a proof generated under
+/// one of them verifies under the other.
+///
+/// The constants are compared once reduced into `F`, since two tables that only differ by
+/// multiples of its order give the same permutation.
+pub fn schedules_compatible<F: PrimeField32>(a: &RoundSchedule, b: &RoundSchedule) -> bool {
+    a.num_external_rounds == b.num_external_rounds
+        && a.num_internal_rounds == b.num_internal_rounds
+        && a.round_constants.len() == b.round_constants.len()
+        && a.round_constants
+            .iter()
+            .flatten()
+            .zip(b.round_constants.iter().flatten())
+            .all(|(&x, &y)| F::from_wrapped_u32(x) == F::from_wrapped_u32(y))
+}
+
+/// The columns and constraints saved by folding the round constants into the constraints of the
+/// sboxes, compared to a layout with a column for every state element plus its round constant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundConstantSavings {
+    /// The number of columns of the trace that are not needed.
+    pub columns: usize,
+    /// The number of constraints that are not needed, one defining every such column.
+    pub constraints: usize,
+}
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Returns the table of round constants used by the chip, as canonical `u32`s indexed by
+    /// round.
+    pub fn round_constant_table(&self) -> [[u32; WIDTH]; NUM_ROUNDS] {
+        self.options
+            .custom_round_constants
+            .unwrap_or_else(|| core::array::from_fn(|r| RC_16_30_U32[r]))
+    }
+
+    /// Returns the round schedule of the permutation proven by the chip.
+    pub fn round_schedule(&self) -> RoundSchedule {
+        RoundSchedule {
+            num_external_rounds: NUM_EXTERNAL_ROUNDS,
+            num_internal_rounds: NUM_INTERNAL_ROUNDS,
+            round_constants: self.round_constant_table().to_vec(),
+        }
+    }
+
+    /// Checks that `table` is shaped like a table of round constants over `F`, returning it as
+    /// a table for [`Self::custom_round_constants`] if it is.
+    ///
+    /// The table must have one row per round, every entry must be below the order of `F`, and
+    /// no row may be all zeros. This only rules out obviously broken tables and says nothing
+    /// about the security of the constants.
+    pub fn validate_round_constants<F: PrimeField32>(
+        table: &[[u32; WIDTH]],
+    ) -> Result<[[u32; WIDTH]; NUM_ROUNDS], RoundConstantsError> {
+        let table: [[u32; WIDTH]; NUM_ROUNDS] =
+            table
+                .try_into()
+                .map_err(|_| RoundConstantsError::WrongNumberOfRounds {
+                    expected: NUM_ROUNDS,
+                    actual: table.len(),
+                })?;
+        for (round, constants) in table.iter().enumerate() {
+            if let Some(lane) = constants.iter().position(|&value| value >= F::ORDER_U32) {
+                return Err(RoundConstantsError::NonCanonical {
+                    round,
+                    lane,
+                    value: constants[lane],
+                });
+            }
+            if constants.iter().all(|&value| value == 0) {
+                return Err(RoundConstantsError::ZeroRound(round));
+            }
+        }
+        Ok(table)
+    }
+
+    /// Returns the round constants used by the chip, indexed by round.
+    pub fn round_constants<F: AbstractField>(&self) -> [[F; WIDTH]; NUM_ROUNDS] {
+        let table = self.round_constant_table();
+        core::array::from_fn(|r| core::array::from_fn(|i| F::from_wrapped_u32(table[r][i])))
+    }
+
+    /// Returns what the chip saves by adding the round constants inside the constraints of the
+    /// sboxes rather than in columns of their own.
+    ///
+    /// An explicit layout would have a column for every lane of every external round and for the
+    /// 0th lane of every internal round, each with a constraint setting it to the state plus the
+    /// round constant. Adding a constant is degree 1, so folding it into the sbox constraint
+    /// leaves the maximum degree of the constraints unchanged.
+    pub fn round_constant_savings() -> RoundConstantSavings {
+        let columns = NUM_EXTERNAL_ROUNDS * WIDTH + NUM_INTERNAL_ROUNDS;
+        RoundConstantSavings {
+            columns,
+            constraints: columns,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::tests::prove_and_verify;
+    use crate::poseidon2_wide::external::{
+        schedules_compatible, Poseidon2WideChip, Poseidon2WideOptions, RoundConstantSavings,
+        RoundConstantsError, RoundSchedule, NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS, NUM_ROUNDS,
+        NUM_ROUND_CONSTANT_COLS, WIDTH,
+    };
+    use crate::poseidon2_wide::round_constants;
+    use crate::runtime::ExecutionRecord;
+    use crate::stark::utils::check_constraints;
+    use p3_baby_bear::BabyBear;
+    use p3_field::{AbstractField, PrimeField32};
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_uni_stark::get_max_constraint_degree;
+    use sp1_core::air::MachineAir;
+    use sp1_primitives::RC_16_30_U32;
+
+    #[test]
+    fn round_constant_savings() {
+        let savings = Poseidon2WideChip::<3>::round_constant_savings();
+        assert_eq!(
+            savings,
+            RoundConstantSavings {
+                columns: 141,
+                constraints: 141,
+            }
+        );
+
+        // A column of its own for every constant is what reading them from a table appends.
+        assert_eq!(savings.columns, NUM_ROUND_CONSTANT_COLS);
+
+        // Folding the constants into the sboxes leaves the degree at that of the sboxes.
+        let chip = Poseidon2WideChip::<3>::default();
+        assert_eq!(get_max_constraint_degree::<BabyBear, _>(&chip, 0, 0), 3);
+    }
+
+    #[test]
+    fn custom_round_constants() {
+        // Any well-shaped table is a valid set of round constants, so reverse the order of the
+        // rounds.
+        let table = core::array::from_fn(|r| RC_16_30_U32[NUM_ROUNDS - 1 - r]);
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            custom_round_constants: Some(table),
+            ..Default::default()
+        });
+        let rc = chip.round_constants::<BabyBear>();
+
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..4 {
+            let input = core::array::from_fn(|j| BabyBear::from_canonical_u32(i * 31 + j as u32));
+            let event = Poseidon2Event::dummy_from_input_with_constants(
+                input,
+                [BabyBear::zero(); WIDTH],
+                &rc,
+            );
+
+            let mut expected = input;
+            crate::poseidon2_wide::permute_with_constants(&mut expected, &rc);
+            assert_eq!(event.result_array, expected);
+            assert_ne!(
+                Poseidon2Event::dummy_from_input(input).result_array,
+                expected
+            );
+
+            record.poseidon2_events.push(event);
+        }
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        assert!(check_constraints(&chip, &trace, &[]).is_ok());
+        assert!(check_constraints(&Poseidon2WideChip::<3>::default(), &trace, &[]).is_err());
+
+        prove_and_verify(&chip, &record, &[]);
+    }
+
+    #[test]
+    fn validate_round_constants() {
+        let table = round_constants::<BabyBear>()
+            .map(|round| round.map(|constant| constant.as_canonical_u32()));
+        assert_eq!(
+            Poseidon2WideChip::<3>::validate_round_constants::<BabyBear>(&table),
+            Ok(table)
+        );
+
+        assert_eq!(
+            Poseidon2WideChip::<3>::validate_round_constants::<BabyBear>(&table[1..]),
+            Err(RoundConstantsError::WrongNumberOfRounds {
+                expected: NUM_ROUNDS,
+                actual: NUM_ROUNDS - 1,
+            })
+        );
+
+        let mut non_canonical = table;
+        non_canonical[5][3] = BabyBear::ORDER_U32;
+        assert_eq!(
+            Poseidon2WideChip::<3>::validate_round_constants::<BabyBear>(&non_canonical),
+            Err(RoundConstantsError::NonCanonical {
+                round: 5,
+                lane: 3,
+                value: BabyBear::ORDER_U32,
+            })
+        );
+
+        let mut zero_round = table;
+        zero_round[NUM_EXTERNAL_ROUNDS / 2] = [0; WIDTH];
+        assert_eq!(
+            Poseidon2WideChip::<3>::validate_round_constants::<BabyBear>(&zero_round),
+            Err(RoundConstantsError::ZeroRound(NUM_EXTERNAL_ROUNDS / 2))
+        );
+    }
+
+    #[test]
+    fn compatible_schedules() {
+        let schedule = Poseidon2WideChip::<3>::default().round_schedule();
+        assert_eq!(schedule.round_constants.len(), NUM_ROUNDS);
+        let identical = Poseidon2WideChip::<3>::default().round_schedule();
+        assert!(schedules_compatible::<BabyBear>(&schedule, &identical));
+
+        // Constants that only differ by the order of the field give the same permutation.
+        let mut wrapped = schedule.clone();
+        wrapped.round_constants[0][0] += BabyBear::ORDER_U32;
+        assert!(schedules_compatible::<BabyBear>(&schedule, &wrapped));
+
+        // A single round with other constants does not.
+        let mut table = Poseidon2WideChip::<3>::default().round_constant_table();
+        table[5][3] ^= 1;
+        let other = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            custom_round_constants: Some(table),
+            ..Default::default()
+        })
+        .round_schedule();
+        assert!(!schedules_compatible::<BabyBear>(&schedule, &other));
+
+        // Neither does a schedule with another split between external and internal rounds.
+        let split = RoundSchedule {
+            num_external_rounds: NUM_EXTERNAL_ROUNDS - 2,
+            num_internal_rounds: NUM_INTERNAL_ROUNDS + 2,
+            ..schedule.clone()
+        };
+        assert!(!schedules_compatible::<BabyBear>(&schedule, &split));
+    }
+}
@@ -0,0 +1,1390 @@
+use core::borrow::Borrow;
+use p3_air::BaseAir;
+use p3_field::{AbstractField, PrimeField32};
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+use p3_maybe_rayon::prelude::*;
+use sp1_core::air::MachineAir;
+use sp1_core::utils::pad_to_power_of_two;
+use std::borrow::{BorrowMut, Cow};
+
+use super::columns::{
+    Poseidon2MemCols, Poseidon2WideByteInputCols, Poseidon2WideCols, Poseidon2WideIvCols,
+    Poseidon2WideNegationCols, Poseidon2WideRotationCols, Poseidon2WideRoundConstantCols,
+    Poseidon2WideVariableRoundCols, BYTES_PER_LANE, NUM_BYTE_INPUT_COLS, NUM_IV_COLS,
+    NUM_NEGATION_COLS, NUM_ROTATION_COLS, NUM_ROUND_CONSTANT_COLS, NUM_VARIABLE_ROUND_COLS,
+};
+use super::{
+    Poseidon2WideChip, NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS, NUM_POSEIDON2_WIDE_COLS,
+    NUM_ROUNDS, WIDTH,
+};
+use crate::memory::MemoryCols;
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::{external_linear_layer, internal_linear_layer};
+use crate::runtime::ExecutionRecord;
+
+impl<const DEGREE: usize> Poseidon2WideChip<DEGREE> {
+    /// Generates the trace of `record` like `generate_trace`, calling `on_event` with the index,
+    /// input lanes and output lanes of every real row.
+    ///
+    /// The rows are generated in parallel, so the callback fires once the trace is built, in the
+    /// order of the events. The lanes are read from the trace rather than from the events, so
+    /// the callback sees exactly what is proven.
+    pub fn generate_trace_with_observer<F: PrimeField32>(
+        &self,
+        record: &ExecutionRecord<F>,
+        mut on_event: impl FnMut(usize, &[F; WIDTH], &[F; WIDTH]),
+    ) -> RowMajorMatrix<F> {
+        let trace =
+            <Self as MachineAir<F>>::generate_trace(self, record, &mut ExecutionRecord::default());
+        for (i, row) in trace
+            .values
+            .chunks_exact(trace.width())
+            .take(self.real_events(record).len())
+            .enumerate()
+        {
+            let cols: &Poseidon2WideCols<F> = row[..NUM_POSEIDON2_WIDE_COLS].borrow();
+            let input = core::array::from_fn(|j| *cols.memory.input[j].value());
+            let output = core::array::from_fn(|j| *cols.memory.output[j].value());
+            on_event(i, &input, &output);
+        }
+        trace
+    }
+
+    /// Generates the trace of `record` with its rows reordered so that the permutations of equal
+    /// inputs are adjacent, and returns it along with the order of the rows.
+    ///
+    /// Row `i` of the trace holds the event `order[i]` of the record. Every row carries the
+    /// timestamps of its own memory accesses, and interactions are balanced as a multiset, so
+    /// the reordered trace proves the same record as the one in event order.
+    pub fn generate_trace_grouped<F: PrimeField32>(
+        &self,
+        record: &ExecutionRecord<F>,
+    ) -> (RowMajorMatrix<F>, Vec<usize>) {
+        let events = self.real_events(record);
+        let mut order = (0..events.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| events[i].input.map(|x| x.as_canonical_u32()));
+
+        let grouped = order.iter().map(|&i| events[i].clone()).collect::<Vec<_>>();
+        let trace = self.generate_rows(&grouped);
+        (self.finish_trace(trace, &grouped), order)
+    }
+
+    /// Appends the optional columns to a padded `trace` whose first rows are the rows of
+    /// `events`: the round constant columns if the chip reads its round constants from a table,
+    /// then the internal round selectors if their number varies by event, then the bytes of the
+    /// input if it encodes bytes, then the initialization vector, the negation flag and the
+    /// rotation of the output if the events may have them.
+    ///
+    /// The constants of the padding rows are left at zero, like the constants they stand in for
+    /// are gated by `is_real` when they are inlined, and the padding rows apply every internal
+    /// round, with no initialization vector and an output that is neither negated nor rotated.
+    fn with_appended_cols<F: PrimeField32>(
+        &self,
+        trace: RowMajorMatrix<F>,
+        events: &[Poseidon2Event<F>],
+    ) -> RowMajorMatrix<F> {
+        let width = <Self as BaseAir<F>>::width(self);
+        if width == NUM_POSEIDON2_WIDE_COLS {
+            return trace;
+        }
+
+        let rc = self.round_constants::<F>();
+        let mut real_constants = [F::zero(); NUM_ROUND_CONSTANT_COLS];
+        let constant_cols: &mut Poseidon2WideRoundConstantCols<F> =
+            real_constants.as_mut_slice().borrow_mut();
+        for r in 0..NUM_EXTERNAL_ROUNDS {
+            constant_cols.external[r] = rc[external_round_index(r)];
+        }
+        for r in 0..NUM_INTERNAL_ROUNDS {
+            constant_cols.internal[r] = rc[r + NUM_EXTERNAL_ROUNDS / 2][0];
+        }
+
+        let mut values = vec![F::zero(); trace.height() * width];
+        for (i, (row, base)) in values
+            .chunks_exact_mut(width)
+            .zip(trace.values.chunks_exact(NUM_POSEIDON2_WIDE_COLS))
+            .enumerate()
+        {
+            row[..NUM_POSEIDON2_WIDE_COLS].copy_from_slice(base);
+            let is_real = i < events.len();
+            let mut offset = NUM_POSEIDON2_WIDE_COLS;
+            if self.options.rc_bus.is_some() {
+                if is_real {
+                    row[offset..offset + NUM_ROUND_CONSTANT_COLS].copy_from_slice(&real_constants);
+                }
+                offset += NUM_ROUND_CONSTANT_COLS;
+            }
+            if self.options.variable_internal_rounds {
+                let num_rounds = if is_real {
+                    events[i].num_internal_rounds
+                } else {
+                    NUM_INTERNAL_ROUNDS
+                };
+                let base: &Poseidon2WideCols<F> = base.borrow();
+                let round_cols: &mut Poseidon2WideVariableRoundCols<F> =
+                    row[offset..offset + NUM_VARIABLE_ROUND_COLS].borrow_mut();
+                round_cols.num_internal_rounds = F::from_canonical_usize(num_rounds);
+                for r in 0..NUM_INTERNAL_ROUNDS {
+                    round_cols.round_selectors[r] = F::from_bool(r < num_rounds);
+
+                    let internal = &base.internal_rounds;
+                    let input = if r == 0 {
+                        internal.state[0]
+                    } else {
+                        internal.s0[r - 1]
+                    };
+                    let constant = if is_real {
+                        rc[r + NUM_EXTERNAL_ROUNDS / 2][0]
+                    } else {
+                        F::zero()
+                    };
+                    let add_rc = input + constant;
+                    round_cols.sbox_deg_7[r] = if self.options.linear_only {
+                        add_rc
+                    } else {
+                        internal.sbox_deg_3[r] * internal.sbox_deg_3[r] * add_rc
+                    };
+                }
+                offset += NUM_VARIABLE_ROUND_COLS;
+            }
+            if self.options.byte_input {
+                if is_real {
+                    let byte_cols: &mut Poseidon2WideByteInputCols<F> =
+                        row[offset..offset + NUM_BYTE_INPUT_COLS].borrow_mut();
+                    for (bytes, &lane) in byte_cols.input_bytes.iter_mut().zip(&events[i].input) {
+                        *bytes = lane_bytes(lane).map(F::from_canonical_u8);
+                    }
+                }
+                offset += NUM_BYTE_INPUT_COLS;
+            }
+            if self.options.initialization_vector {
+                if is_real {
+                    let iv_cols: &mut Poseidon2WideIvCols<F> =
+                        row[offset..offset + NUM_IV_COLS].borrow_mut();
+                    iv_cols.iv = events[i].iv;
+                }
+                offset += NUM_IV_COLS;
+            }
+            if self.options.negated_output {
+                if is_real {
+                    let negation_cols: &mut Poseidon2WideNegationCols<F> =
+                        row[offset..offset + NUM_NEGATION_COLS].borrow_mut();
+                    negation_cols.negate_output = F::from_bool(events[i].negate_output);
+                }
+                offset += NUM_NEGATION_COLS;
+            }
+            if self.options.rotated_output && is_real && events[i].output_rotation != 0 {
+                let rotation_cols: &mut Poseidon2WideRotationCols<F> =
+                    row[offset..offset + NUM_ROTATION_COLS].borrow_mut();
+                // A rotation out of range sets no selector, and fails the output constraints.
+                if let Some(selector) = rotation_cols
+                    .output_rotation
+                    .get_mut(events[i].output_rotation - 1)
+                {
+                    *selector = F::one();
+                }
+            }
+        }
+        RowMajorMatrix::new(values, width)
+    }
+
+    /// Returns the events of `record` that get a real row in the trace.
+    ///
+    /// Both `generate_trace` and `included` use this, so that a chip is only included in a shard
+    /// when its trace has real rows. These are the events accepted by `clk_filter`, or all of
+    /// them without one.
+    pub fn real_events<'a, F: PrimeField32>(
+        &self,
+        record: &'a ExecutionRecord<F>,
+    ) -> Cow<'a, [Poseidon2Event<F>]> {
+        match self.options.clk_filter {
+            Some(filter) => record
+                .poseidon2_events
+                .iter()
+                .filter(|event| filter(event.clk.as_canonical_u32()))
+                .cloned()
+                .collect(),
+            None => Cow::Borrowed(&record.poseidon2_events),
+        }
+    }
+
+    /// Generates the rows of `events`, in order and without padding.
+    ///
+    /// Every row only depends on its own event, so the rows of consecutive slices of events can
+    /// be generated separately and concatenated into the rows of the whole slice.
+    pub fn generate_rows<F: PrimeField32>(
+        &self,
+        events: &[Poseidon2Event<F>],
+    ) -> RowMajorMatrix<F> {
+        // Convert the round constants once rather than for every event.
+        let rc = self.round_constants::<F>();
+
+        // The rows are generated in parallel. Collecting an indexed parallel iterator keeps them in
+        // the order of the events, so the trace does not depend on how the work is scheduled.
+        let rows = events
+            .par_iter()
+            .map(|event| {
+                let mut row = [F::zero(); NUM_POSEIDON2_WIDE_COLS];
+                self.populate_row(event, &rc, row.as_mut_slice().borrow_mut());
+                row
+            })
+            .collect::<Vec<_>>();
+
+        // Convert the trace to a row major matrix.
+        RowMajorMatrix::new(flatten_rows(&rows), NUM_POSEIDON2_WIDE_COLS)
+    }
+
+    /// Populates the zeroed columns `cols` with the row of `event`, permuting with the round
+    /// constants `rc`.
+    ///
+    /// The event is not checked here, since this runs once per row: an event rejected by
+    /// [`Self::dry_run`] gives a row that does not satisfy the constraints.
+    pub(super) fn populate_row<F: PrimeField32>(
+        &self,
+        event: &Poseidon2Event<F>,
+        rc: &[[F; WIDTH]; NUM_ROUNDS],
+        cols: &mut Poseidon2WideCols<F>,
+    ) {
+        populate_memory(&mut cols.memory, event);
+        if self.options.interactions_only {
+            return;
+        }
+
+        // Apply the initial round, after adding the initialization vector, which is zero unless
+        // the chip appends its columns.
+        cols.external_rounds[0].state = core::array::from_fn(|i| event.input[i] + event.iv[i]);
+        external_linear_layer(&mut cols.external_rounds[0].state);
+
+        // Apply the first half of external rounds.
+        for r in 0..NUM_EXTERNAL_ROUNDS / 2 {
+            let next_state = populate_external_round(cols, r, rc, self.options.linear_only);
+
+            if r == NUM_EXTERNAL_ROUNDS / 2 - 1 {
+                cols.internal_rounds.state = next_state;
+            } else {
+                cols.external_rounds[r + 1].state = next_state;
+            }
+        }
+
+        // Apply the internal rounds.
+        cols.external_rounds[NUM_EXTERNAL_ROUNDS / 2].state = populate_internal_rounds(
+            cols,
+            rc,
+            event.num_internal_rounds,
+            self.options.linear_only,
+        );
+
+        // Apply the second half of external rounds.
+        for r in NUM_EXTERNAL_ROUNDS / 2..NUM_EXTERNAL_ROUNDS {
+            let next_state = populate_external_round(cols, r, rc, self.options.linear_only);
+            if r == NUM_EXTERNAL_ROUNDS - 1 {
+                // Do nothing, since we set the cols.output by populating the output records
+                // after this loop.
+            } else {
+                cols.external_rounds[r + 1].state = next_state;
+            }
+        }
+    }
+
+    /// Pads the rows of `events` generated by `generate_rows` to a power of two and appends the
+    /// optional columns, giving the trace of `events`.
+    pub(super) fn finish_trace<F: PrimeField32>(
+        &self,
+        mut trace: RowMajorMatrix<F>,
+        events: &[Poseidon2Event<F>],
+    ) -> RowMajorMatrix<F> {
+        let num_real_rows = trace.height();
+        pad_to_power_of_two::<NUM_POSEIDON2_WIDE_COLS, F>(&mut trace.values);
+        if let Some(sentinel) = self
+            .options
+            .padding_sentinel
+            .filter(|_| !self.options.interactions_only)
+        {
+            let padding_row = self.padding_row(F::from_canonical_u32(sentinel));
+            trace.values[num_real_rows * NUM_POSEIDON2_WIDE_COLS..]
+                .chunks_exact_mut(NUM_POSEIDON2_WIDE_COLS)
+                .for_each(|row| row.copy_from_slice(&padding_row));
+        }
+        self.with_appended_cols(trace, events)
+    }
+
+    /// Generates a padding row whose input lanes all hold `sentinel`.
+    ///
+    /// The round constants are multiplied by `is_real` in the constraints, so the row permutes
+    /// the sentinel without them.
+    fn padding_row<F: PrimeField32>(&self, sentinel: F) -> [F; NUM_POSEIDON2_WIDE_COLS] {
+        let rc = [[F::zero(); WIDTH]; NUM_ROUNDS];
+
+        let mut row = [F::zero(); NUM_POSEIDON2_WIDE_COLS];
+        let cols: &mut Poseidon2WideCols<F> = row.as_mut_slice().borrow_mut();
+        for i in 0..WIDTH {
+            cols.memory.input[i].access.value = sentinel;
+        }
+        cols.external_rounds[0].state = [sentinel; WIDTH];
+        external_linear_layer(&mut cols.external_rounds[0].state);
+
+        for r in 0..NUM_EXTERNAL_ROUNDS / 2 {
+            let next_state = populate_external_round(cols, r, &rc, self.options.linear_only);
+            if r == NUM_EXTERNAL_ROUNDS / 2 - 1 {
+                cols.internal_rounds.state = next_state;
+            } else {
+                cols.external_rounds[r + 1].state = next_state;
+            }
+        }
+        cols.external_rounds[NUM_EXTERNAL_ROUNDS / 2].state =
+            populate_internal_rounds(cols, &rc, NUM_INTERNAL_ROUNDS, self.options.linear_only);
+        for r in NUM_EXTERNAL_ROUNDS / 2..NUM_EXTERNAL_ROUNDS {
+            let next_state = populate_external_round(cols, r, &rc, self.options.linear_only);
+            if r == NUM_EXTERNAL_ROUNDS - 1 {
+                for i in 0..WIDTH {
+                    cols.memory.output[i].access.value = next_state[i];
+                }
+            } else {
+                cols.external_rounds[r + 1].state = next_state;
+            }
+        }
+
+        row
+    }
+
+    /// Returns the state at every round boundary of the permutation in `row`, a row of the trace:
+    /// the state after the initial linear layer, followed by the state after each of the
+    /// `NUM_ROUNDS` rounds.
+    ///
+    /// The trace only stores the 0th lane of the state between internal rounds, so the other
+    /// lanes of those states are recomputed from the columns.
+    pub fn round_states<F: PrimeField32>(&self, row: &[F]) -> Vec<[F; WIDTH]> {
+        let cols: &Poseidon2WideCols<F> = row[..NUM_POSEIDON2_WIDE_COLS].borrow();
+        let rc = self.round_constants::<F>();
+        let mut states = Vec::with_capacity(NUM_ROUNDS + 1);
+        states.extend(
+            cols.external_rounds[..NUM_EXTERNAL_ROUNDS / 2]
+                .iter()
+                .map(|round| round.state),
+        );
+        states.push(cols.internal_rounds.state);
+
+        let mut state = cols.internal_rounds.state;
+        for r in 0..NUM_INTERNAL_ROUNDS {
+            let add_rc = state[0] + rc[r + NUM_EXTERNAL_ROUNDS / 2][0];
+            let sbox_deg_3 = cols.internal_rounds.sbox_deg_3[r];
+            state[0] = if self.options.linear_only {
+                add_rc
+            } else {
+                sbox_deg_3 * sbox_deg_3 * add_rc
+            };
+            internal_linear_layer(&mut state);
+            states.push(state);
+        }
+
+        states.extend(
+            cols.external_rounds[NUM_EXTERNAL_ROUNDS / 2 + 1..]
+                .iter()
+                .map(|round| round.state),
+        );
+        let sign = if self.options.negated_output {
+            let offset = self.negation_cols_offset();
+            let negation_cols: &Poseidon2WideNegationCols<F> =
+                row[offset..offset + NUM_NEGATION_COLS].borrow();
+            F::one() - negation_cols.negate_output.double()
+        } else {
+            F::one()
+        };
+        let rotation = if self.options.rotated_output {
+            let offset = self.rotation_cols_offset();
+            let rotation_cols: &Poseidon2WideRotationCols<F> =
+                row[offset..offset + NUM_ROTATION_COLS].borrow();
+            rotation_cols
+                .output_rotation
+                .iter()
+                .position(|selector| selector.is_one())
+                .map_or(0, |k| k + 1)
+        } else {
+            0
+        };
+        states.push(core::array::from_fn(|i| {
+            *cols.memory.output[(i + WIDTH - rotation) % WIDTH].value() * sign
+        }));
+        states
+    }
+
+    /// Returns a copy of `trace` with the input lanes of every row and their bytes zeroed, along
+    /// with the intermediate states and the output lanes if `redact_states` is set, for sharing a
+    /// failing trace without leaking its secret inputs.
+    ///
+    /// The memory timestamps, the selectors and the other structural columns are left untouched
+    /// for debugging the constraints. The redacted trace does not satisfy them, so it does not
+    /// verify.
+    pub fn redact_inputs<F: PrimeField32>(
+        &self,
+        trace: &RowMajorMatrix<F>,
+        redact_states: bool,
+    ) -> RowMajorMatrix<F> {
+        let mut redacted = trace.clone();
+        let width = redacted.width();
+        for row in redacted.values.chunks_exact_mut(width) {
+            if self.options.byte_input {
+                let offset = self.byte_input_cols_offset();
+                row[offset..offset + NUM_BYTE_INPUT_COLS].fill(F::zero());
+            }
+
+            let cols: &mut Poseidon2WideCols<F> = row[..NUM_POSEIDON2_WIDE_COLS].borrow_mut();
+            for input in cols.memory.input.iter_mut() {
+                *input.value_mut() = F::zero();
+            }
+            if !redact_states {
+                continue;
+            }
+
+            for output in cols.memory.output.iter_mut() {
+                *output.value_mut() = F::zero();
+            }
+            for round in cols.external_rounds.iter_mut() {
+                round.state = [F::zero(); WIDTH];
+                round.sbox_deg_3 = [F::zero(); WIDTH];
+            }
+            cols.internal_rounds.state = [F::zero(); WIDTH];
+            cols.internal_rounds.s0 = [F::zero(); NUM_INTERNAL_ROUNDS - 1];
+            cols.internal_rounds.sbox_deg_3 = [F::zero(); NUM_INTERNAL_ROUNDS];
+            if self.options.variable_internal_rounds {
+                let offset = self.variable_round_cols_offset();
+                let round_cols: &mut Poseidon2WideVariableRoundCols<F> =
+                    row[offset..offset + NUM_VARIABLE_ROUND_COLS].borrow_mut();
+                round_cols.sbox_deg_7 = [F::zero(); NUM_INTERNAL_ROUNDS];
+            }
+        }
+        redacted
+    }
+
+    /// Returns the output lanes of a row of the trace as canonical `u32`s, for callers that
+    /// serialize them.
+    ///
+    /// Every lane is converted with [`PrimeField32::as_canonical_u32`], so converting the result
+    /// back with `F::from_canonical_u32` gives the output lanes of the row.
+    pub fn output_as_u32<F: PrimeField32>(row: &[F]) -> [u32; WIDTH] {
+        let cols: &Poseidon2WideCols<F> = row[..NUM_POSEIDON2_WIDE_COLS].borrow();
+        core::array::from_fn(|i| cols.memory.output[i].value().as_canonical_u32())
+    }
+}
+
+/// Copies `rows` into a single row-major buffer.
+///
+/// This copies a row at a time into a preallocated buffer, which is faster than collecting the
+/// elements through a flattening iterator.
+pub fn flatten_rows<F: Copy + Default, const N: usize>(rows: &[[F; N]]) -> Vec<F> {
+    let mut values = vec![F::default(); rows.len() * N];
+    for (chunk, row) in values.chunks_exact_mut(N).zip(rows) {
+        chunk.copy_from_slice(row);
+    }
+    values
+}
+
+/// Returns the index in the table of round constants of external round `r`, counting the internal
+/// rounds that run between the two halves of the external rounds.
+pub(super) fn external_round_index(r: usize) -> usize {
+    if r < NUM_EXTERNAL_ROUNDS / 2 {
+        r
+    } else {
+        r + NUM_INTERNAL_ROUNDS
+    }
+}
+
+/// Populates the memory columns of a real row from the operands and memory records of `event`.
+///
+/// These hold every value the interactions of the row read, except for the round constants
+/// received from a table.
+fn populate_memory<F: PrimeField32>(cols: &mut Poseidon2MemCols<F>, event: &Poseidon2Event<F>) {
+    cols.timestamp = event.clk;
+    cols.dst = event.dst;
+    cols.left = event.left;
+    cols.right = event.right;
+    cols.is_real = F::one();
+    for i in 0..WIDTH {
+        cols.input[i].populate(&event.input_records[i]);
+        cols.output[i].populate(&event.result_records[i]);
+    }
+}
+
+fn populate_external_round<F: PrimeField32>(
+    cols: &mut Poseidon2WideCols<F>,
+    r: usize,
+    rc: &[[F; WIDTH]; NUM_ROUNDS],
+    linear_only: bool,
+) -> [F; WIDTH] {
+    let mut state = {
+        let round_cols = cols.external_rounds[r].borrow_mut();
+
+        // Add round constants.
+        //
+        // Optimization: Since adding a constant is a degree 1 operation, we can avoid adding
+        // columns for it, and instead include it in the constraint for the x^3 part of the sbox.
+        let round = external_round_index(r);
+        let mut add_rc = round_cols.state;
+        for i in 0..WIDTH {
+            add_rc[i] += rc[round][i];
+        }
+
+        // Apply the sboxes.
+        // Optimization: since the linear layer that comes after the sbox is degree 1, we can
+        // avoid adding columns for the result of the sbox, and instead include the x^3 -> x^7
+        // part of the sbox in the constraint for the linear layer
+        //
+        // Without the sboxes, the sbox columns are left at zero.
+        if linear_only {
+            add_rc
+        } else {
+            let mut sbox_deg_7: [F; 16] = [F::zero(); WIDTH];
+            for i in 0..WIDTH {
+                round_cols.sbox_deg_3[i] = add_rc[i] * add_rc[i] * add_rc[i];
+                sbox_deg_7[i] = round_cols.sbox_deg_3[i] * round_cols.sbox_deg_3[i] * add_rc[i];
+            }
+            sbox_deg_7
+        }
+    };
+
+    // Apply the linear layer.
+    external_linear_layer(&mut state);
+    state
+}
+
+/// Populates the columns of every internal round and returns the state after the first
+/// `num_rounds` of them.
+fn populate_internal_rounds<F: PrimeField32>(
+    cols: &mut Poseidon2WideCols<F>,
+    rc: &[[F; WIDTH]; NUM_ROUNDS],
+    num_rounds: usize,
+    linear_only: bool,
+) -> [F; WIDTH] {
+    let cols = cols.internal_rounds.borrow_mut();
+    let mut state = cols.state;
+    let mut output = state;
+    for r in 0..NUM_INTERNAL_ROUNDS {
+        // Add the round constant to the 0th state element.
+        // Optimization: Since adding a constant is a degree 1 operation, we can avoid adding
+        // columns for it, just like for external rounds.
+        let round = r + NUM_EXTERNAL_ROUNDS / 2;
+        let add_rc = state[0] + rc[round][0];
+
+        // Apply the sboxes.
+        // Optimization: since the linear layer that comes after the sbox is degree 1, we can
+        // avoid adding columns for the result of the sbox, just like for external rounds.
+        let sbox_deg_7 = if linear_only {
+            add_rc
+        } else {
+            cols.sbox_deg_3[r] = add_rc * add_rc * add_rc;
+            cols.sbox_deg_3[r] * cols.sbox_deg_3[r] * add_rc
+        };
+
+        // Apply the linear layer.
+        state[0] = sbox_deg_7;
+        internal_linear_layer(&mut state);
+
+        // Optimization: since we're only applying the sbox to the 0th state element, we only
+        // need to have columns for the 0th state element at every step. This is because the
+        // linear layer is degree 1, so all state elements at the end can be expressed as a
+        // degree-3 polynomial of the state at the beginning of the internal rounds and the 0th
+        // state element at rounds prior to the current round
+        if r < NUM_INTERNAL_ROUNDS - 1 {
+            cols.s0[r] = state[0];
+        }
+        if r < num_rounds {
+            output = state;
+        }
+    }
+
+    output
+}
+
+/// Packs `bytes` into the input lanes of a permutation, `BYTES_PER_LANE` bytes per lane in
+/// little-endian order, as read by a chip with `byte_input` set.
+pub fn pack_input_bytes<F: AbstractField>(bytes: &[u8; BYTES_PER_LANE * WIDTH]) -> [F; WIDTH] {
+    core::array::from_fn(|i| {
+        let lane = &bytes[i * BYTES_PER_LANE..(i + 1) * BYTES_PER_LANE];
+        F::from_canonical_u32(
+            lane.iter()
+                .rev()
+                .fold(0, |packed, &byte| (packed << 8) | byte as u32),
+        )
+    })
+}
+
+/// Returns the little-endian bytes of an input lane packed by [`pack_input_bytes`].
+///
+/// Panics if the lane does not fit in `BYTES_PER_LANE` bytes.
+pub(super) fn lane_bytes<F: PrimeField32>(lane: F) -> [u8; BYTES_PER_LANE] {
+    let value = lane.as_canonical_u32();
+    assert!(
+        value < 1 << (8 * BYTES_PER_LANE),
+        "an input lane does not fit in {} bytes",
+        BYTES_PER_LANE
+    );
+    core::array::from_fn(|i| (value >> (8 * i)) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::borrow::{Borrow, BorrowMut};
+    use core::iter::repeat;
+    use std::collections::{BTreeMap, HashMap};
+
+    use crate::air::{Block, SP1RecursionAirBuilder};
+
+    use crate::memory::MemoryCols;
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::tests::{prove_and_verify, test_record};
+    use crate::poseidon2_wide::external::{
+        Poseidon2WideChip, Poseidon2WideCols, Poseidon2WideOptions, UnsupportedEventError,
+        NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS, NUM_POSEIDON2_WIDE_COLS, NUM_ROUNDS, WIDTH,
+    };
+    use crate::poseidon2_wide::snapshot::{record_from_snapshot, PermutationSpec};
+    use crate::poseidon2_wide::{external_linear_layer, permute_in_place, round_constants};
+    use crate::runtime::{ExecutionRecord, MemoryEntry, RecursionProgram};
+    use crate::stark::utils::{accumulate_interactions, check_constraints};
+    use itertools::Itertools;
+    use p3_air::{Air, BaseAir};
+    use p3_baby_bear::{BabyBear, DiffusionMatrixBabyBear};
+    use p3_field::{AbstractField, Field, PrimeField32};
+    use p3_matrix::dense::RowMajorMatrix;
+    use p3_matrix::Matrix;
+    use p3_poseidon2::{Poseidon2, Poseidon2ExternalMatrixGeneral};
+    use p3_symmetric::Permutation;
+    use sp1_core::air::{AirInteraction, MachineAir};
+    use sp1_core::lookup::InteractionKind;
+    use sp1_core::stark::{Chip, StarkGenericConfig};
+    use sp1_core::utils::{
+        inner_perm, pad_to_power_of_two, uni_stark_prove, uni_stark_verify, BabyBearPoseidon2Inner,
+    };
+    use sp1_primitives::RC_16_30_U32;
+
+    /// Declares a test checking that permuting `input` gives `expected`, both natively and in a
+    /// row of the wide chip. Both are arrays of `WIDTH` canonical `u32`s.
+    macro_rules! poseidon2_test_vector {
+        ($name:ident, input = $input:expr, expected = $expected:expr) => {
+            #[test]
+            fn $name() {
+                let input: [u32; WIDTH] = $input;
+                let expected: [u32; WIDTH] = $expected;
+                let input = input.map(BabyBear::from_canonical_u32);
+                let expected = expected.map(BabyBear::from_canonical_u32);
+
+                let mut state = input;
+                permute_in_place(&mut state);
+                assert_eq!(state, expected);
+
+                let mut record = ExecutionRecord::<BabyBear>::default();
+                record
+                    .poseidon2_events
+                    .push(Poseidon2Event::dummy_from_input(input));
+                let trace: RowMajorMatrix<BabyBear> = Poseidon2WideChip::<3>::default()
+                    .generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+                let row = trace.row(0).collect_vec();
+                let cols: &Poseidon2WideCols<BabyBear> = row.as_slice().borrow();
+                let output: [BabyBear; WIDTH] =
+                    core::array::from_fn(|i| *cols.memory.output[i].value());
+                assert_eq!(output, expected);
+            }
+        };
+    }
+
+    /// Permutes `input` with the Plonky3 permutation, for test vectors without a known answer.
+    fn reference_permute(input: [u32; WIDTH]) -> [u32; WIDTH] {
+        inner_perm()
+            .permute(input.map(BabyBear::from_canonical_u32))
+            .map(|x| x.as_canonical_u32())
+    }
+
+    poseidon2_test_vector!(
+        vector_zero,
+        input = [0; WIDTH],
+        expected = reference_permute([0; WIDTH])
+    );
+
+    poseidon2_test_vector!(
+        vector_one,
+        input = [1; WIDTH],
+        expected = reference_permute([1; WIDTH])
+    );
+
+    poseidon2_test_vector!(
+        vector_lanes,
+        input = core::array::from_fn(|i| i as u32),
+        expected = reference_permute(core::array::from_fn(|i| i as u32))
+    );
+
+    poseidon2_test_vector!(
+        vector_max,
+        input = [BabyBear::ORDER_U32 - 1; WIDTH],
+        expected = reference_permute([BabyBear::ORDER_U32 - 1; WIDTH])
+    );
+
+    const NUM_STUB_MEMORY_COLS: usize = 5;
+
+    /// A stub chip standing in for the memory table, which sends the entries of some addresses
+    /// before the permutations and receives their entries after them. Each row holds the
+    /// timestamp, the address and the value of an entry, and whether it is sent or received.
+    struct StubMemoryChip {
+        before: BTreeMap<usize, MemoryEntry<BabyBear>>,
+        after: BTreeMap<usize, MemoryEntry<BabyBear>>,
+    }
+
+    impl MachineAir<BabyBear> for StubMemoryChip {
+        type Record = ExecutionRecord<BabyBear>;
+
+        type Program = RecursionProgram<BabyBear>;
+
+        fn name(&self) -> String {
+            "StubMemory".to_string()
+        }
+
+        fn generate_trace(
+            &self,
+            _: &ExecutionRecord<BabyBear>,
+            _: &mut ExecutionRecord<BabyBear>,
+        ) -> RowMajorMatrix<BabyBear> {
+            let row = |addr: usize, entry: &MemoryEntry<BabyBear>, is_send: bool| {
+                [
+                    entry.timestamp,
+                    BabyBear::from_canonical_usize(addr),
+                    entry.value.0[0],
+                    BabyBear::from_bool(is_send),
+                    BabyBear::from_bool(!is_send),
+                ]
+            };
+            let mut values = self
+                .before
+                .iter()
+                .map(|(&addr, entry)| row(addr, entry, true))
+                .chain(
+                    self.after
+                        .iter()
+                        .map(|(&addr, entry)| row(addr, entry, false)),
+                )
+                .flatten()
+                .collect::<Vec<_>>();
+            pad_to_power_of_two::<NUM_STUB_MEMORY_COLS, BabyBear>(&mut values);
+            RowMajorMatrix::new(values, NUM_STUB_MEMORY_COLS)
+        }
+
+        fn included(&self, _: &Self::Record) -> bool {
+            true
+        }
+    }
+
+    impl<F> BaseAir<F> for StubMemoryChip {
+        fn width(&self) -> usize {
+            NUM_STUB_MEMORY_COLS
+        }
+    }
+
+    impl<AB: SP1RecursionAirBuilder> Air<AB> for StubMemoryChip {
+        fn eval(&self, builder: &mut AB) {
+            let main = builder.main();
+            let local = main.row_slice(0);
+            let local: &[AB::Var] = &local;
+            let values = local[..3]
+                .iter()
+                .map(|&value| value.into())
+                .chain(repeat(AB::Expr::zero()).take(3))
+                .collect::<Vec<AB::Expr>>();
+            builder.send(AirInteraction::new(
+                values.clone(),
+                local[3].into(),
+                InteractionKind::Memory,
+            ));
+            builder.receive(AirInteraction::new(
+                values,
+                local[4].into(),
+                InteractionKind::Memory,
+            ));
+        }
+    }
+
+    /// A test generating a trace for a single permutation that checks that the output is correct
+    #[test]
+    fn generate_trace() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let test_inputs = vec![
+            [BabyBear::from_canonical_u32(1); WIDTH],
+            [BabyBear::from_canonical_u32(2); WIDTH],
+            [BabyBear::from_canonical_u32(3); WIDTH],
+            [BabyBear::from_canonical_u32(4); WIDTH],
+        ];
+
+        let gt: Poseidon2<
+            BabyBear,
+            Poseidon2ExternalMatrixGeneral,
+            DiffusionMatrixBabyBear,
+            16,
+            7,
+        > = inner_perm();
+
+        let expected_outputs = test_inputs
+            .iter()
+            .map(|input| gt.permute(*input))
+            .collect::<Vec<_>>();
+
+        let mut input_exec = ExecutionRecord::<BabyBear>::default();
+        for input in test_inputs.iter().cloned() {
+            input_exec
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&input_exec, &mut ExecutionRecord::<BabyBear>::default());
+
+        assert_eq!(trace.height(), test_inputs.len());
+        for (i, expected_output) in expected_outputs.iter().enumerate() {
+            let row = trace.row(i).collect_vec();
+            let cols: &Poseidon2WideCols<BabyBear> = row.as_slice().borrow();
+            for i in 0..WIDTH {
+                assert_eq!(expected_output[i], *cols.memory.output[i].value());
+            }
+        }
+    }
+
+    /// A test checking that the rows of the trace follow the order of the events, which the
+    /// timestamps of their memory accesses rely on, even though they are generated in parallel.
+    #[test]
+    fn rows_in_event_order() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let mut record = test_record(1 << 8);
+        for (i, event) in record.poseidon2_events.iter_mut().enumerate() {
+            // Interleave the clocks, so that the order of the rows differs from their clocks.
+            event.clk = BabyBear::from_canonical_usize((i * 37) % 256);
+        }
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        for (i, event) in record.poseidon2_events.iter().enumerate() {
+            let row = trace.row(i).collect_vec();
+            let cols: &Poseidon2WideCols<BabyBear> = row.as_slice().borrow();
+            assert_eq!(cols.memory.timestamp, event.clk);
+            let output: [BabyBear; WIDTH] =
+                core::array::from_fn(|j| *cols.memory.output[j].value());
+            assert_eq!(output, event.result_array);
+        }
+    }
+
+    #[test]
+    fn generate_trace_with_observer() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(6);
+        let mut observed = Vec::new();
+        let trace = chip.generate_trace_with_observer(&record, |i, input, output| {
+            observed.push((i, *input, *output));
+        });
+
+        assert_eq!(observed.len(), record.poseidon2_events.len());
+        for (i, (event, (index, input, output))) in
+            record.poseidon2_events.iter().zip(observed).enumerate()
+        {
+            assert_eq!(index, i);
+            assert_eq!(input, event.input);
+            let mut expected = event.input;
+            permute_in_place(&mut expected);
+            assert_eq!(output, expected);
+        }
+
+        let expected: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        assert_eq!(trace.values, expected.values);
+    }
+
+    #[test]
+    fn generate_trace_grouped() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..40u32 {
+            let input = [BabyBear::from_canonical_u32((i * 7) % 5); WIDTH];
+            let mut event = Poseidon2Event::dummy_from_input(input);
+            event.clk = BabyBear::from_canonical_u32(i);
+            record.poseidon2_events.push(event);
+        }
+
+        let (grouped, order) = chip.generate_trace_grouped(&record);
+        let natural: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        assert_eq!(grouped.dimensions(), natural.dimensions());
+
+        // Equal inputs are adjacent.
+        let inputs = order
+            .iter()
+            .map(|&i| record.poseidon2_events[i].input)
+            .collect_vec();
+        assert_eq!(inputs.iter().dedup().count(), 5);
+
+        // Undoing the reordering gives the trace in event order.
+        for (row, &event) in order.iter().enumerate() {
+            assert_eq!(grouped.row_slice(row)[..], natural.row_slice(event)[..]);
+        }
+        assert_eq!(
+            grouped.values[order.len() * NUM_POSEIDON2_WIDE_COLS..],
+            natural.values[order.len() * NUM_POSEIDON2_WIDE_COLS..]
+        );
+
+        let config = BabyBearPoseidon2Inner::new();
+        for trace in [grouped, natural] {
+            let proof = uni_stark_prove(&config, &chip, &mut config.challenger(), trace);
+            uni_stark_verify(&config, &chip, &mut config.challenger(), &proof)
+                .expect("expected proof to be valid");
+        }
+    }
+
+    /// A test checking that a grouped trace pads with the sentinel row when one is set, and that
+    /// the padded trace still proves.
+    #[test]
+    fn generate_trace_grouped_with_padding_sentinel() {
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            padding_sentinel: Some(7),
+            ..Default::default()
+        });
+        let record = test_record(5);
+
+        let (grouped, order) = chip.generate_trace_grouped(&record);
+        let natural: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        assert_eq!(grouped.height(), 8);
+        for row in order.len()..grouped.height() {
+            let cols: &Poseidon2WideCols<BabyBear> =
+                grouped.row_slice(row)[..NUM_POSEIDON2_WIDE_COLS].borrow();
+            for input in cols.memory.input.iter() {
+                assert_eq!(*input.value(), BabyBear::from_canonical_u32(7));
+            }
+            assert_eq!(grouped.row_slice(row)[..], natural.row_slice(row)[..]);
+        }
+
+        let config = BabyBearPoseidon2Inner::new();
+        let proof = uni_stark_prove(&config, &chip, &mut config.challenger(), grouped);
+        uni_stark_verify(&config, &chip, &mut config.challenger(), &proof)
+            .expect("expected proof to be valid");
+    }
+
+    /// A test checking that a trace of only the interactions balances the memory accesses of the
+    /// permutations against a stub memory table, without satisfying the permutation constraints.
+    #[test]
+    fn interactions_only() {
+        let input: [BabyBear; WIDTH] = core::array::from_fn(BabyBear::from_canonical_usize);
+        let before = (0..WIDTH)
+            .map(|i| {
+                let entry = MemoryEntry {
+                    value: Block::from(input[i]),
+                    timestamp: BabyBear::from_canonical_usize(i),
+                };
+                (i, entry)
+            })
+            .collect::<HashMap<_, _>>();
+        let specs = [
+            PermutationSpec {
+                input_addr: 0,
+                output_addr: 32,
+            },
+            PermutationSpec {
+                input_addr: 32,
+                output_addr: 0,
+            },
+            PermutationSpec {
+                input_addr: 8,
+                output_addr: 48,
+            },
+        ];
+        let mut after = before.clone();
+        let record = record_from_snapshot(&mut after, &specs);
+        let memory = StubMemoryChip {
+            before: after
+                .keys()
+                .map(|&addr| (addr, before.get(&addr).cloned().unwrap_or_default()))
+                .collect(),
+            after: after.into_iter().collect(),
+        };
+
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            interactions_only: true,
+            ..Default::default()
+        });
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        let full: RowMajorMatrix<BabyBear> = Poseidon2WideChip::<3>::default()
+            .generate_trace(&record, &mut ExecutionRecord::default());
+        for i in 0..specs.len() {
+            let row = trace.row_slice(i);
+            let full_row = full.row_slice(i);
+            let cols: &Poseidon2WideCols<BabyBear> = (*row).borrow();
+            let full_cols: &Poseidon2WideCols<BabyBear> = (*full_row).borrow();
+            assert_eq!(
+                cols.memory.output.map(|access| *access.value()),
+                full_cols.memory.output.map(|access| *access.value())
+            );
+            assert!(cols.external_rounds.iter().all(|round| round
+                .state
+                .iter()
+                .chain(&round.sbox_deg_3)
+                .all(|x| x.is_zero())));
+        }
+        assert!(check_constraints(&chip, &trace, &[]).is_err());
+
+        let memory = Chip::<BabyBear, _>::new(memory);
+        let chip = Chip::<BabyBear, _>::new(chip);
+        let memory_trace = memory.generate_trace(&record, &mut ExecutionRecord::default());
+        let memory_balance = |memory_trace: &RowMajorMatrix<BabyBear>| {
+            let mut balance = BTreeMap::new();
+            accumulate_interactions(&memory, memory_trace, InteractionKind::Memory, &mut balance);
+            accumulate_interactions(&chip, &trace, InteractionKind::Memory, &mut balance);
+            balance
+        };
+        assert!(memory_balance(&memory_trace)
+            .values()
+            .all(|count| count.is_zero()));
+
+        // A memory table missing the initial entry of an address no longer balances.
+        let mut truncated = memory_trace.clone();
+        truncated.values[..NUM_STUB_MEMORY_COLS].fill(BabyBear::zero());
+        assert!(!memory_balance(&truncated)
+            .values()
+            .all(|count| count.is_zero()));
+    }
+
+    /// A test checking that an event with an initialization vector is rejected by the dry run of
+    /// a chip without the columns for it, and that its trace does not satisfy the constraints.
+    #[test]
+    fn initialization_vector_without_columns() {
+        let input = [BabyBear::from_canonical_u32(7); WIDTH];
+        let mut record = test_record(2);
+        record
+            .poseidon2_events
+            .push(Poseidon2Event::dummy_from_input_with_iv(
+                input,
+                [BabyBear::one(); WIDTH],
+            ));
+        let chip = Poseidon2WideChip::<3>::default();
+        assert_eq!(
+            chip.dry_run(&record),
+            Err(UnsupportedEventError::InitializationVector { event: 2 })
+        );
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        let failure = check_constraints(&chip, &trace, &[]).unwrap_err();
+        assert_eq!(failure.row, 2);
+    }
+
+    /// A test checking that an event rotating its output by `WIDTH` lanes is rejected by the dry
+    /// run, and gives a trace that does not satisfy the constraints instead of a panic.
+    #[test]
+    fn rotation_out_of_range() {
+        let mut record = test_record(1);
+        record.poseidon2_events[0].output_rotation = WIDTH;
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            rotated_output: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            chip.dry_run(&record),
+            Err(UnsupportedEventError::RotationOutOfRange { event: 0 })
+        );
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        assert!(check_constraints(&chip, &trace, &[]).is_err());
+    }
+
+    #[test]
+    fn flatten_rows() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let trace: RowMajorMatrix<BabyBear> = chip.generate_trace(
+            &test_record(1 << 10),
+            &mut ExecutionRecord::<BabyBear>::default(),
+        );
+        let rows = trace
+            .rows()
+            .map(|row| {
+                let row = row.collect_vec();
+                let row: [BabyBear; NUM_POSEIDON2_WIDE_COLS] = row.try_into().unwrap();
+                row
+            })
+            .collect_vec();
+
+        let expected = rows.clone().into_iter().flatten().collect::<Vec<_>>();
+        let values = super::flatten_rows(&rows);
+        assert_eq!(values, expected);
+        assert_eq!(values, trace.values);
+    }
+
+    /// A test checking that the sboxes of a round only read their own lane, by permuting a state
+    /// with a single nonzero lane.
+    #[test]
+    fn sbox_lane_independence() {
+        let rc = round_constants::<BabyBear>();
+        let value = BabyBear::from_canonical_u32(0x1234567);
+
+        for lane in 0..WIDTH {
+            let mut row = [BabyBear::zero(); NUM_POSEIDON2_WIDE_COLS];
+            let cols: &mut Poseidon2WideCols<BabyBear> = row.as_mut_slice().borrow_mut();
+            cols.external_rounds[0].state[lane] = value;
+            super::populate_external_round(cols, 0, &rc, false);
+
+            for i in 0..WIDTH {
+                let add_rc = if i == lane {
+                    value + rc[0][i]
+                } else {
+                    rc[0][i]
+                };
+                assert_eq!(
+                    cols.external_rounds[0].sbox_deg_3[i],
+                    add_rc.cube(),
+                    "lane {} of the sbox depends on lane {}",
+                    i,
+                    lane
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn redact_inputs() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&test_record(5), &mut ExecutionRecord::default());
+        let names = Poseidon2WideChip::<3>::columns_layout();
+        let is_lane =
+            |name: &str, array: &str| name.starts_with(array) && name.ends_with("].value");
+        let is_state = |name: &str| {
+            name.starts_with("external_rounds")
+                || name.starts_with("internal_rounds")
+                || is_lane(name, "memory.output[")
+        };
+
+        for redact_states in [false, true] {
+            let redacted = chip.redact_inputs(&trace, redact_states);
+            assert_eq!(redacted.width(), trace.width());
+            assert_eq!(redacted.height(), trace.height());
+            for row in 0..trace.height() {
+                for (col, name) in names.iter().enumerate() {
+                    let expected =
+                        if is_lane(name, "memory.input[") || (redact_states && is_state(name)) {
+                            BabyBear::zero()
+                        } else {
+                            trace.get(row, col)
+                        };
+                    assert_eq!(redacted.get(row, col), expected, "{}", name);
+                }
+            }
+
+            // The redacted trace is only for reading, it does not satisfy the constraints.
+            assert!(check_constraints(&chip, &redacted, &[]).is_err());
+        }
+    }
+
+    #[test]
+    fn output_as_u32() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(3);
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+
+        for (i, event) in record.poseidon2_events.iter().enumerate() {
+            let input = event.input.map(|x| x.as_canonical_u32());
+            let output = Poseidon2WideChip::<3>::output_as_u32(&trace.row_slice(i));
+            assert_eq!(output, reference_permute(input));
+            assert_eq!(output.map(BabyBear::from_canonical_u32), event.result_array);
+        }
+    }
+
+    #[test]
+    fn generate_trace_deterministic() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(1 << 10);
+        let expected: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+
+        for _ in 0..10 {
+            let trace: RowMajorMatrix<BabyBear> =
+                chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+            assert_eq!(trace.values, expected.values);
+        }
+    }
+
+    #[test]
+    fn round_states() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let record = test_record(1);
+        let event = &record.poseidon2_events[0];
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        let row = trace.row(0).collect_vec();
+        let cols: &Poseidon2WideCols<BabyBear> = row.as_slice().borrow();
+
+        let states = chip.round_states(&row);
+        assert_eq!(states.len(), NUM_ROUNDS + 1);
+
+        let mut first = event.input;
+        external_linear_layer(&mut first);
+        assert_eq!(states[0], first);
+        assert_eq!(states[NUM_ROUNDS], event.result_array);
+
+        // The recomputed state after the internal rounds matches the next stored state.
+        assert_eq!(
+            states[NUM_EXTERNAL_ROUNDS / 2 + NUM_INTERNAL_ROUNDS],
+            cols.external_rounds[NUM_EXTERNAL_ROUNDS / 2].state
+        );
+    }
+
+    /// Builds the row of `event` column by column, with the linear layers of `p3_poseidon2` and
+    /// plain exponentiations rather than the helpers used by the chip.
+    fn reference_row(event: &Poseidon2Event<BabyBear>) -> [BabyBear; NUM_POSEIDON2_WIDE_COLS] {
+        let rc = |round: usize, i: usize| BabyBear::from_wrapped_u32(RC_16_30_U32[round][i]);
+
+        let mut row = [BabyBear::zero(); NUM_POSEIDON2_WIDE_COLS];
+        let cols: &mut Poseidon2WideCols<BabyBear> = row.as_mut_slice().borrow_mut();
+        cols.memory.timestamp = event.clk;
+        cols.memory.dst = event.dst;
+        cols.memory.left = event.left;
+        cols.memory.right = event.right;
+        cols.memory.is_real = BabyBear::one();
+        for i in 0..WIDTH {
+            cols.memory.input[i].populate(&event.input_records[i]);
+            cols.memory.output[i].populate(&event.result_records[i]);
+        }
+
+        // The states are recorded before the rounds that consume them: the external rounds of
+        // the first half, then the internal rounds, then the external rounds of the second half.
+        let mut state: [BabyBear; WIDTH] = core::array::from_fn(|i| event.input[i] + event.iv[i]);
+        Poseidon2ExternalMatrixGeneral.permute_mut(&mut state);
+        for r in 0..NUM_EXTERNAL_ROUNDS {
+            if r == NUM_EXTERNAL_ROUNDS / 2 {
+                cols.internal_rounds.state = state;
+                for k in 0..NUM_INTERNAL_ROUNDS {
+                    let add_rc = state[0] + rc(NUM_EXTERNAL_ROUNDS / 2 + k, 0);
+                    cols.internal_rounds.sbox_deg_3[k] = add_rc.exp_u64(3);
+                    state[0] = add_rc.exp_u64(7);
+                    DiffusionMatrixBabyBear.permute_mut(&mut state);
+                    if k < NUM_INTERNAL_ROUNDS - 1 {
+                        cols.internal_rounds.s0[k] = state[0];
+                    }
+                }
+            }
+
+            let round = if r < NUM_EXTERNAL_ROUNDS / 2 {
+                r
+            } else {
+                r + NUM_INTERNAL_ROUNDS
+            };
+            cols.external_rounds[r].state = state;
+            for i in 0..WIDTH {
+                let add_rc = state[i] + rc(round, i);
+                cols.external_rounds[r].sbox_deg_3[i] = add_rc.exp_u64(3);
+                state[i] = add_rc.exp_u64(7);
+            }
+            Poseidon2ExternalMatrixGeneral.permute_mut(&mut state);
+        }
+        assert_eq!(state, event.result_array);
+
+        row
+    }
+
+    /// The reference trace of [`reference_trace`], checked in alongside the crate.
+    const REFERENCE_TRACE_FIXTURE: &str =
+        include_str!("../../../tests/fixtures/poseidon2_wide_trace.csv");
+
+    /// Renders `rows` as a reference trace: a header of the names of the columns, then the
+    /// canonical values of every row, each line separated by commas.
+    fn render_reference_trace(rows: impl IntoIterator<Item = Vec<BabyBear>>) -> String {
+        let mut rendered = Poseidon2WideChip::<3>::columns_layout().join(",");
+        rendered.push('\n');
+        for row in rows {
+            rendered += &row.iter().map(|x| x.as_canonical_u32()).join(",");
+            rendered.push('\n');
+        }
+        rendered
+    }
+
+    /// A test comparing every column of the generated trace, including the intermediate ones,
+    /// byte for byte against a reference trace checked in as a fixture.
+    ///
+    /// The fixture was written from an independent implementation of the rows, and is checked
+    /// against [`reference_row`] as well as against the chip under test, so a change to either
+    /// of them shows up as a difference rather than as a new fixture.
+    #[test]
+    fn reference_trace() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let inputs = [
+            [BabyBear::zero(); WIDTH],
+            [BabyBear::one(); WIDTH],
+            core::array::from_fn(|i| BabyBear::from_canonical_u32(i as u32)),
+            core::array::from_fn(|i| BabyBear::neg_one() - BabyBear::from_canonical_u32(i as u32)),
+        ];
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record.poseidon2_events = inputs
+            .into_iter()
+            .map(Poseidon2Event::dummy_from_input)
+            .collect();
+
+        let reference = render_reference_trace(
+            record
+                .poseidon2_events
+                .iter()
+                .map(|event| reference_row(event).to_vec()),
+        );
+        assert_eq!(REFERENCE_TRACE_FIXTURE, reference);
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::<BabyBear>::default());
+        let generated = render_reference_trace(
+            trace
+                .rows()
+                .take(record.poseidon2_events.len())
+                .map(|row| row.collect_vec()),
+        );
+
+        // Name the first differing column before comparing the bytes.
+        let names = Poseidon2WideChip::<3>::columns_layout();
+        for (row, (expected, actual)) in REFERENCE_TRACE_FIXTURE
+            .lines()
+            .zip(generated.lines())
+            .enumerate()
+            .skip(1)
+        {
+            for (name, (expected, actual)) in
+                names.iter().zip(expected.split(',').zip(actual.split(',')))
+            {
+                assert_eq!(actual, expected, "row {}, column {}", row - 1, name);
+            }
+        }
+        assert_eq!(generated.as_bytes(), REFERENCE_TRACE_FIXTURE.as_bytes());
+    }
+
+    /// A test checking that a trace padded with a sentinel satisfies the constraints and proves,
+    /// while its padding rows differ from those of a zero-padded trace.
+    #[test]
+    fn sentinel_padding() {
+        let record = test_record(5);
+        let chip = Poseidon2WideChip::<3>::default();
+        let sentinel_chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            padding_sentinel: Some(0xdead),
+            ..Default::default()
+        });
+
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        let padded: RowMajorMatrix<BabyBear> =
+            sentinel_chip.generate_trace(&record, &mut ExecutionRecord::default());
+        assert_eq!(padded.height(), trace.height());
+
+        let real_len = 5 * NUM_POSEIDON2_WIDE_COLS;
+        assert_eq!(padded.values[..real_len], trace.values[..real_len]);
+        for row in 5..padded.height() {
+            let padding_row = padded.row_slice(row);
+            assert_ne!(*padding_row, *trace.row_slice(row));
+            let cols: &Poseidon2WideCols<BabyBear> = (*padding_row).borrow();
+            assert_eq!(cols.memory.is_real, BabyBear::zero());
+            for input in cols.memory.input.iter() {
+                assert_eq!(*input.value(), BabyBear::from_canonical_u32(0xdead));
+            }
+        }
+
+        check_constraints(&sentinel_chip, &padded, &[]).expect("expected constraints to hold");
+
+        prove_and_verify(&sentinel_chip, &record, &[]);
+    }
+}
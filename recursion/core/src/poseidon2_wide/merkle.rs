@@ -0,0 +1,237 @@
+//! An append-only Merkle accumulator built on the Poseidon2 permutation.
+
+use std::collections::HashMap;
+
+use p3_field::PrimeField32;
+
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::WIDTH;
+use crate::poseidon2_wide::permute_state_in_memory;
+use crate::poseidon2_wide::snapshot::MemoryBackend;
+use crate::runtime::{ExecutionRecord, MemoryEntry, DIGEST_SIZE};
+
+/// Compresses two digests into one, by permuting their concatenation and keeping the first
+/// `DIGEST_SIZE` lanes, like the `Poseidon2Compress` opcode.
+///
+/// The concatenation is written to the `WIDTH` addresses of `memory` starting at `addr` one
+/// cycle after its latest timestamp, and permuted in place as by [`permute_state_in_memory`].
+/// Returns the event of the permutation along with the digest.
+pub fn poseidon2_compress<F: PrimeField32>(
+    memory: &mut impl MemoryBackend<F>,
+    addr: usize,
+    left: &[F; DIGEST_SIZE],
+    right: &[F; DIGEST_SIZE],
+) -> ([F; DIGEST_SIZE], Poseidon2Event<F>) {
+    let input: [F; WIDTH] = core::array::from_fn(|i| {
+        if i < DIGEST_SIZE {
+            left[i]
+        } else {
+            right[i - DIGEST_SIZE]
+        }
+    });
+    let clk = memory.latest_timestamp() + F::one();
+    let event = permute_state_in_memory(memory, clk, addr, &input, [F::zero(); WIDTH]);
+    (core::array::from_fn(|i| event.result_array[i]), event)
+}
+
+/// Builds the Merkle tree of `leaves`, padded with zeros up to the next power of two, and returns
+/// a record of its compressions ordered from the root down.
+///
+/// The compression of the root is the first event of the record, so proving the record with a
+/// [`Poseidon2WideChip`](crate::poseidon2_wide::Poseidon2WideChip) that exposes a digest makes
+/// the root the only public output of the proof, while the other compressions stay private. The
+/// chip proves every compression but does not constrain how their inputs and outputs are linked,
+/// so a verifier learns that the root is the output of a proven permutation, not that it is the
+/// root of a particular set of leaves.
+///
+/// Panics if there are fewer than two leaves, since the tree then has no compressions.
+pub fn merkle_tree_record<F: PrimeField32>(leaves: &[[F; DIGEST_SIZE]]) -> ExecutionRecord<F> {
+    assert!(leaves.len() > 1, "a tree needs at least two leaves");
+
+    let mut memory = HashMap::<usize, MemoryEntry<F>>::new();
+    let mut level = leaves.to_vec();
+    level.resize(leaves.len().next_power_of_two(), [F::zero(); DIGEST_SIZE]);
+    let mut levels = Vec::new();
+    while level.len() > 1 {
+        let (digests, events): (Vec<_>, Vec<_>) = level
+            .chunks_exact(2)
+            .map(|pair| poseidon2_compress(&mut memory, 0, &pair[0], &pair[1]))
+            .unzip();
+        levels.push(events);
+        level = digests;
+    }
+
+    let mut record = ExecutionRecord::<F>::default();
+    record.poseidon2_events = levels.into_iter().rev().flatten().collect();
+    record
+}
+
+/// An incremental Merkle tree over digests, which only stores the frontier of the tree.
+///
+/// The tree is padded on the right with zero leaves up to the next power of two. Appending a
+/// leaf only compresses the nodes on the path from the leaf to the first incomplete subtree, and
+/// every compression is recorded as a [`Poseidon2Event`], so the evolution of the accumulator
+/// can be proven by the wide Poseidon2 chip.
+#[derive(Debug, Clone, Default)]
+pub struct MerkleAccumulator<F> {
+    /// The root of the complete subtree of `2^level` leaves at every level that is waiting for a
+    /// right sibling.
+    frontier: Vec<Option<[F; DIGEST_SIZE]>>,
+    num_leaves: usize,
+    /// The memory the compressions are made in, at the same addresses one after the other.
+    memory: HashMap<usize, MemoryEntry<F>>,
+    /// The events of every compression computed so far.
+    pub events: Vec<Poseidon2Event<F>>,
+}
+
+impl<F: PrimeField32> MerkleAccumulator<F> {
+    pub fn new() -> Self {
+        Self {
+            frontier: Vec::new(),
+            num_leaves: 0,
+            memory: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// The number of leaves appended so far.
+    pub fn len(&self) -> usize {
+        self.num_leaves
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.num_leaves == 0
+    }
+
+    /// Appends a leaf to the tree.
+    pub fn append(&mut self, leaf: [F; DIGEST_SIZE]) {
+        let mut node = leaf;
+        let mut level = 0;
+        while let Some(left) = self.frontier.get_mut(level).and_then(Option::take) {
+            node = self.compress(&left, &node);
+            level += 1;
+        }
+        if level == self.frontier.len() {
+            self.frontier.push(None);
+        }
+        self.frontier[level] = Some(node);
+        self.num_leaves += 1;
+    }
+
+    /// Returns the root of the tree, padding the leaves with zeros up to the next power of two.
+    ///
+    /// The root of an empty tree is the zero digest.
+    pub fn root(&mut self) -> [F; DIGEST_SIZE] {
+        let depth = self.num_leaves.next_power_of_two().trailing_zeros() as usize;
+        if self.num_leaves.is_power_of_two() {
+            return self.frontier[depth].unwrap();
+        }
+        if self.num_leaves == 0 {
+            return [F::zero(); DIGEST_SIZE];
+        }
+
+        let mut zero = [F::zero(); DIGEST_SIZE];
+        let mut node: Option<[F; DIGEST_SIZE]> = None;
+        for level in 0..depth {
+            node = match (self.frontier[level], node) {
+                (Some(left), Some(right)) => Some(self.compress(&left, &right)),
+                (Some(left), None) => Some(self.compress(&left, &zero)),
+                (None, Some(left)) => Some(self.compress(&left, &zero)),
+                (None, None) => None,
+            };
+            zero = self.compress(&zero, &zero);
+        }
+        node.unwrap()
+    }
+
+    fn compress(&mut self, left: &[F; DIGEST_SIZE], right: &[F; DIGEST_SIZE]) -> [F; DIGEST_SIZE] {
+        let (digest, event) = poseidon2_compress(&mut self.memory, 0, left, right);
+        self.events.push(event);
+        digest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+
+    use super::{merkle_tree_record, poseidon2_compress, MerkleAccumulator};
+    use crate::poseidon2_wide::{Poseidon2WideChip, Poseidon2WideOptions};
+    use crate::runtime::{MemoryEntry, DIGEST_SIZE};
+    use sp1_core::utils::BabyBearPoseidon2Inner;
+
+    /// Computes the root of the tree of `leaves` padded with zeros, by building every level.
+    fn full_tree_root(leaves: &[[BabyBear; DIGEST_SIZE]]) -> [BabyBear; DIGEST_SIZE] {
+        let mut memory = HashMap::<usize, MemoryEntry<BabyBear>>::new();
+        let mut level = leaves.to_vec();
+        level.resize(
+            leaves.len().next_power_of_two(),
+            [BabyBear::zero(); DIGEST_SIZE],
+        );
+        while level.len() > 1 {
+            level = level
+                .chunks_exact(2)
+                .map(|pair| poseidon2_compress(&mut memory, 0, &pair[0], &pair[1]).0)
+                .collect();
+        }
+        level[0]
+    }
+
+    #[test]
+    fn append() {
+        let leaves = (0..100u32)
+            .map(|i| core::array::from_fn(|j| BabyBear::from_canonical_u32(i * 8 + j as u32)))
+            .collect::<Vec<_>>();
+
+        let mut accumulator = MerkleAccumulator::new();
+        for (i, leaf) in leaves.iter().enumerate() {
+            accumulator.append(*leaf);
+            if [1, 2, 3, 7, 8, 33].contains(&(i + 1)) {
+                let mut snapshot = accumulator.clone();
+                assert_eq!(snapshot.root(), full_tree_root(&leaves[..i + 1]));
+            }
+        }
+
+        // Every append only compresses the path up to the first incomplete subtree, so a tree of
+        // 100 leaves takes 100 - popcount(100) compressions.
+        assert_eq!(accumulator.events.len(), 100 - 100u32.count_ones() as usize);
+        assert_eq!(accumulator.len(), 100);
+        assert_eq!(accumulator.root(), full_tree_root(&leaves));
+    }
+
+    #[test]
+    fn prove_tree_root() {
+        let leaves = (0..16u32)
+            .map(|i| core::array::from_fn(|j| BabyBear::from_canonical_u32(i * 8 + j as u32)))
+            .collect::<Vec<_>>();
+        let root = full_tree_root(&leaves);
+        let record = merkle_tree_record(&leaves);
+        assert_eq!(record.poseidon2_events.len(), 15);
+
+        let chip = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            expose_digest: true,
+            ..Default::default()
+        });
+        let public_values = chip.public_values(&record, &[]);
+        assert_eq!(public_values, root.to_vec());
+
+        let config = BabyBearPoseidon2Inner::new();
+        let proof = chip
+            .prove_with_public_values(&config, &record, &public_values)
+            .unwrap();
+        assert_eq!(
+            chip.verify_and_extract(&config, &proof, &public_values)
+                .unwrap(),
+            root
+        );
+
+        let mut wrong_root = public_values;
+        wrong_root[0] += BabyBear::one();
+        assert!(chip
+            .verify_and_extract(&config, &proof, &wrong_root)
+            .is_err());
+    }
+}
@@ -1,20 +1,38 @@
 #![allow(clippy::needless_range_loop)]
 
-use crate::poseidon2_wide::external::WIDTH;
+use std::collections::HashMap;
+
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::{
+    NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS, NUM_ROUNDS, WIDTH,
+};
+use crate::poseidon2_wide::snapshot::MemoryBackend;
+use crate::runtime::{ExecutionRecord, MemoryEntry, DIGEST_SIZE, HASH_RATE};
 use p3_baby_bear::{MONTY_INVERSE, POSEIDON2_INTERNAL_MATRIX_DIAG_16_BABYBEAR_MONTY};
 use p3_field::AbstractField;
+use p3_field::Field;
+use p3_field::PackedValue;
 use p3_field::PrimeField32;
+use p3_symmetric::{CryptographicPermutation, Permutation};
+use sp1_primitives::RC_16_30_U32;
 
+pub mod buffer;
+pub mod challenges;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+pub mod constants;
 pub mod external;
+pub mod merkle;
+pub mod pass;
+pub mod receipt;
+pub mod snapshot;
+pub mod stream;
+pub mod timestamp;
+pub mod witness;
 
-pub use external::Poseidon2WideChip;
+pub use external::{Poseidon2WideChip, Poseidon2WideOptions};
 use p3_poseidon2::matmul_internal;
 
-#[derive(Debug, Clone)]
-pub struct Poseidon2Event<F> {
-    pub input: [F; WIDTH],
-}
-
 // TODO: Make this public inside Plonky3 and import directly.
 pub fn apply_m_4<AF>(x: &mut [AF])
 where
@@ -60,3 +78,674 @@ pub(crate) fn internal_linear_layer<F: AbstractField>(state: &mut [F; WIDTH]) {
     let monty_inverse = F::from_wrapped_u32(MONTY_INVERSE.as_canonical_u32());
     state.iter_mut().for_each(|i| *i *= monty_inverse.clone());
 }
+
+/// Applies the Poseidon2 permutation to `state` natively.
+///
+/// This follows the same round schedule and round constants as [`Poseidon2WideChip`], so the
+/// result matches the output columns of a row generated for the same input.
+///
+/// All `WIDTH` lanes are treated as data: no lanes are reserved as a capacity, unlike in
+/// [`poseidon2_hash`]. Permuting a full-width input therefore comes with no domain separation,
+/// and callers mixing several uses of the permutation must separate them themselves, for example
+/// with an initialization vector.
+///
+/// The control flow does not depend on the state: every round runs the same field operations on
+/// every lane, and the only branches and indices are over round and lane numbers. Whether the
+/// field operations themselves run in constant time is up to the field, and is not guaranteed
+/// by the compiler either, so this is a best-effort property rather than a side-channel proof.
+pub fn permute_in_place<F: PrimeField32>(state: &mut [F; WIDTH]) {
+    // The initial linear layer.
+    external_linear_layer(state);
+
+    // The first half of the external rounds.
+    for r in 0..NUM_EXTERNAL_ROUNDS / 2 {
+        external_round(state, r);
+    }
+
+    // The internal rounds.
+    for r in 0..NUM_INTERNAL_ROUNDS {
+        let round = r + NUM_EXTERNAL_ROUNDS / 2;
+        let add_rc = state[0] + F::from_wrapped_u32(RC_16_30_U32[round][0]);
+        let sbox_deg_3 = add_rc * add_rc * add_rc;
+        state[0] = sbox_deg_3 * sbox_deg_3 * add_rc;
+        internal_linear_layer(state);
+    }
+
+    // The second half of the external rounds.
+    for r in NUM_EXTERNAL_ROUNDS / 2..NUM_EXTERNAL_ROUNDS {
+        external_round(state, r + NUM_INTERNAL_ROUNDS);
+    }
+}
+
+/// The permutation of [`permute_in_place`], as a Plonky3 permutation.
+///
+/// This can be plugged into the sponges and challengers of Plonky3, such as a `DuplexChallenger`
+/// sampling challenges from an extension field, in place of the permutation of `p3_poseidon2`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NativePoseidon2;
+
+impl<F: PrimeField32> Permutation<[F; WIDTH]> for NativePoseidon2 {
+    fn permute_mut(&self, input: &mut [F; WIDTH]) {
+        permute_in_place(input);
+    }
+}
+
+impl<F: PrimeField32> CryptographicPermutation<[F; WIDTH]> for NativePoseidon2 {}
+
+/// Hashes `input` natively with a sponge over [`permute_in_place`], absorbing `HASH_RATE`
+/// elements at a time by overwriting the rate part of the state.
+///
+/// For a nonempty input this is the same hash as `sp1_primitives::poseidon2_hash`. An empty
+/// input is hashed as a single block with a zero rate and the capacity set to
+/// [`empty_input_state`]: that state is permuted once and its first `DIGEST_SIZE` lanes are
+/// returned.
+///
+/// Every nonempty input starts from a zero capacity, so the capacity of the empty input
+/// separates it from them. In particular, the empty digest is not the digest of `[0]`, which is
+/// the permutation of the all-zero state.
+pub fn poseidon2_hash<F: PrimeField32>(input: &[F]) -> [F; DIGEST_SIZE] {
+    poseidon2_hash_with_len(input)
+}
+
+/// Hashes `input` like [`poseidon2_hash`], returning the first `DIGEST_LEN` lanes of the final
+/// state as the digest.
+///
+/// A shorter digest saves space in commitments, and a digest of 4 lanes is the prefix of the
+/// digest of 8 lanes of the same input. It comes at the cost of security: over a 31-bit field,
+/// 4 lanes hold about 124 bits, so finding a collision takes only about 2^62 hashes by the
+/// birthday bound, against about 2^124 for the default digest. `DIGEST_LEN` must be at most
+/// `HASH_RATE`, the lanes squeezed by a single permutation.
+pub fn poseidon2_hash_with_len<F: PrimeField32, const DIGEST_LEN: usize>(
+    input: &[F],
+) -> [F; DIGEST_LEN] {
+    assert!(
+        DIGEST_LEN <= HASH_RATE,
+        "the digest is longer than the rate"
+    );
+    let mut state = [F::zero(); WIDTH];
+    if input.is_empty() {
+        state = empty_input_state();
+        permute_in_place(&mut state);
+    }
+    for chunk in input.chunks(HASH_RATE) {
+        state[..chunk.len()].copy_from_slice(chunk);
+        permute_in_place(&mut state);
+    }
+    core::array::from_fn(|i| state[i])
+}
+
+/// Returns the state that [`poseidon2_hash`] permutes for an empty input: a zero rate, with the
+/// initialization vector `[0, ..., 0, 1]` in the capacity.
+pub fn empty_input_state<F: AbstractField>() -> [F; WIDTH] {
+    let mut state = core::array::from_fn(|_| F::zero());
+    state[WIDTH - 1] = F::one();
+    state
+}
+
+/// Hashes a tuple of fields of arbitrary lengths with [`poseidon2_hash`], prefixing every part
+/// with its length.
+///
+/// Hashing the concatenation of the parts would give `[[1, 2], [3]]` and `[[1], [2, 3]]` the same
+/// digest, since the boundaries between the parts are lost. With the length prefixes, the
+/// absorbed sequence can be parsed back into the parts it came from, so two different tuples
+/// always absorb different sequences and only collide if the hash itself does.
+pub fn poseidon2_hash_fields<F: PrimeField32>(parts: &[&[F]]) -> [F; DIGEST_SIZE] {
+    poseidon2_hash_fields_with_len(parts)
+}
+
+/// Hashes a tuple of fields like [`poseidon2_hash_fields`], with a digest of `DIGEST_LEN` lanes
+/// as returned by [`poseidon2_hash_with_len`].
+pub fn poseidon2_hash_fields_with_len<F: PrimeField32, const DIGEST_LEN: usize>(
+    parts: &[&[F]],
+) -> [F; DIGEST_LEN] {
+    let mut input = Vec::with_capacity(parts.iter().map(|part| part.len() + 1).sum());
+    for part in parts {
+        input.push(F::from_canonical_usize(part.len()));
+        input.extend_from_slice(part);
+    }
+    poseidon2_hash_with_len(&input)
+}
+
+/// Hashes the inputs returned by `input_gen` with [`poseidon2_hash`] until a digest satisfies
+/// `predicate`, and returns that digest along with the number of inputs hashed.
+///
+/// Only the permutations of the successful hash are recorded in `events`, replayed in `memory`
+/// with the state of the sponge at `addr` as by [`poseidon2_hash_events`], so that a proof
+/// attests the digest without paying for the failed attempts. This is meant for vanity hashes and
+/// proofs of work during development, and it does not terminate if no input satisfies the
+/// predicate.
+pub fn poseidon2_hash_until<F: PrimeField32>(
+    mut input_gen: impl FnMut() -> Vec<F>,
+    predicate: impl Fn(&[F; DIGEST_SIZE]) -> bool,
+    memory: &mut impl MemoryBackend<F>,
+    addr: usize,
+    events: &mut Vec<Poseidon2Event<F>>,
+) -> ([F; DIGEST_SIZE], u64) {
+    let mut iterations = 0;
+    loop {
+        let input = input_gen();
+        iterations += 1;
+        let digest = poseidon2_hash(&input);
+        if !predicate(&digest) {
+            continue;
+        }
+
+        events.extend(poseidon2_hash_events(memory, addr, &input));
+        return (digest, iterations);
+    }
+}
+
+/// Replays the sponge of [`poseidon2_hash`] over `input` in `memory`, with its state held at the
+/// `WIDTH` addresses starting at `addr`, returning the event of every permutation in order. The
+/// first lanes of the output of the last one are the digest.
+///
+/// The sponge starts one cycle after the latest timestamp of `memory`, and every block is
+/// absorbed by writing the state with the block in its rate before permuting it in place, as by
+/// [`permute_state_in_memory`].
+pub fn poseidon2_hash_events<F: PrimeField32>(
+    memory: &mut impl MemoryBackend<F>,
+    addr: usize,
+    input: &[F],
+) -> Vec<Poseidon2Event<F>> {
+    let blocks = if input.is_empty() {
+        vec![&[][..]]
+    } else {
+        input.chunks(HASH_RATE).collect()
+    };
+    let mut clk = memory.latest_timestamp() + F::one();
+    let mut state = if input.is_empty() {
+        empty_input_state()
+    } else {
+        [F::zero(); WIDTH]
+    };
+    let mut events = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        state[..block.len()].copy_from_slice(block);
+        let event = permute_state_in_memory(memory, clk, addr, &state, [F::zero(); WIDTH]);
+        state = event.result_array;
+        events.push(event);
+        clk += F::from_canonical_usize(STATE_PERMUTATION_CYCLES);
+    }
+    events
+}
+
+/// The number of cycles taken by [`permute_state_in_memory`], after which the next permutation
+/// of the same addresses can start.
+pub const STATE_PERMUTATION_CYCLES: usize = 3;
+
+/// Writes `state` to the `WIDTH` addresses of `memory` starting at `addr` at timestamp `clk`, and
+/// returns the event of permuting it in place at `clk + 1`, with `iv` added to it. The output
+/// is written back over the state at `clk + 2`.
+pub fn permute_state_in_memory<F: PrimeField32>(
+    memory: &mut impl MemoryBackend<F>,
+    clk: F,
+    addr: usize,
+    state: &[F; WIDTH],
+    iv: [F; WIDTH],
+) -> Poseidon2Event<F> {
+    for (i, &lane) in state.iter().enumerate() {
+        memory.write(addr + i, clk, lane);
+    }
+    Poseidon2Event::from_memory(memory, clk + F::one(), addr, addr, addr + WIDTH / 2, iv)
+}
+
+/// Returns a record holding a single permutation of the zero state in a fresh memory, the
+/// smallest record the chip can prove.
+pub(crate) fn probe_record<F: PrimeField32>() -> ExecutionRecord<F> {
+    let mut memory = HashMap::<usize, MemoryEntry<F>>::new();
+    let mut probe = ExecutionRecord::<F>::default();
+    probe.poseidon2_events.push(permute_state_in_memory(
+        &mut memory,
+        F::one(),
+        0,
+        &[F::zero(); WIDTH],
+        [F::zero(); WIDTH],
+    ));
+    probe
+}
+
+/// Returns the round constants of every round as field elements, indexed by round.
+///
+/// The constants of `RC_16_30_U32` are stored as canonical `u32`s, so converting them is not
+/// free for fields with a Montgomery representation like BabyBear. Callers permuting many states
+/// should convert them once with this and use [`permute_with_constants`].
+pub fn round_constants<F: AbstractField>() -> [[F; WIDTH]; NUM_ROUNDS] {
+    core::array::from_fn(|r| core::array::from_fn(|i| F::from_wrapped_u32(RC_16_30_U32[r][i])))
+}
+
+/// Applies the Poseidon2 permutation to `state` natively, like [`permute_in_place`], with round
+/// constants that were already converted by [`round_constants`].
+///
+/// The state can be made of packed field elements, to permute several states at once.
+pub fn permute_with_constants<AF: AbstractField>(
+    state: &mut [AF; WIDTH],
+    rc: &[[AF::F; WIDTH]; NUM_ROUNDS],
+) {
+    permute_with_internal_rounds(state, rc, NUM_INTERNAL_ROUNDS);
+}
+
+/// Applies the permutation to `state` like [`permute_with_constants`], with only the first
+/// `num_internal_rounds` of the internal rounds.
+///
+/// The external rounds keep their constants, so the second half of them uses the constants
+/// following all of the internal rounds whatever their number. Fewer internal rounds than
+/// `NUM_INTERNAL_ROUNDS` do not give a secure permutation.
+pub fn permute_with_internal_rounds<AF: AbstractField>(
+    state: &mut [AF; WIDTH],
+    rc: &[[AF::F; WIDTH]; NUM_ROUNDS],
+    num_internal_rounds: usize,
+) {
+    permute_with_sbox(state, rc, num_internal_rounds, |x: AF| {
+        let x3 = x.cube();
+        x3.square() * x
+    });
+}
+
+/// Applies the rounds of the permutation to `state` like [`permute_with_constants`], but without
+/// the sboxes, which leaves an affine map of the state.
+///
+/// This is not a cryptographic permutation: it is trivially invertible and linear, so it must
+/// never be used to hash or commit to anything. It is only meant for cheap checksums while
+/// debugging.
+pub fn permute_linear_with_constants<AF: AbstractField>(
+    state: &mut [AF; WIDTH],
+    rc: &[[AF::F; WIDTH]; NUM_ROUNDS],
+) {
+    permute_with_sbox(state, rc, NUM_INTERNAL_ROUNDS, |x| x);
+}
+
+fn permute_with_sbox<AF: AbstractField>(
+    state: &mut [AF; WIDTH],
+    rc: &[[AF::F; WIDTH]; NUM_ROUNDS],
+    num_internal_rounds: usize,
+    sbox: impl Fn(AF) -> AF,
+) {
+    assert!(
+        num_internal_rounds <= NUM_INTERNAL_ROUNDS,
+        "too many internal rounds"
+    );
+    external_linear_layer(state);
+    for round in rc.iter().take(NUM_EXTERNAL_ROUNDS / 2) {
+        *state = core::array::from_fn(|i| sbox(state[i].clone() + AF::from_f(round[i])));
+        external_linear_layer(state);
+    }
+    for round in rc
+        .iter()
+        .skip(NUM_EXTERNAL_ROUNDS / 2)
+        .take(num_internal_rounds)
+    {
+        state[0] = sbox(state[0].clone() + AF::from_f(round[0]));
+        internal_linear_layer(state);
+    }
+    for round in rc
+        .iter()
+        .skip(NUM_EXTERNAL_ROUNDS / 2 + NUM_INTERNAL_ROUNDS)
+    {
+        *state = core::array::from_fn(|i| sbox(state[i].clone() + AF::from_f(round[i])));
+        external_linear_layer(state);
+    }
+}
+
+/// Applies the Poseidon2 permutation to every state of `states`.
+///
+/// The states are permuted `F::Packing::WIDTH` at a time with packed field elements, and the
+/// states left over when that width does not divide their number are permuted one by one.
+pub fn permute_batch<F: PrimeField32>(states: &mut [[F; WIDTH]], rc: &[[F; WIDTH]; NUM_ROUNDS]) {
+    let packing_width = F::Packing::WIDTH;
+    let num_packed = states.len() - states.len() % packing_width;
+    let (packed, tail) = states.split_at_mut(num_packed);
+
+    for chunk in packed.chunks_exact_mut(packing_width) {
+        let mut state: [F::Packing; WIDTH] =
+            core::array::from_fn(|i| F::Packing::from_fn(|j| chunk[j][i]));
+        permute_with_constants(&mut state, rc);
+        unpack_states(&state, chunk);
+    }
+
+    for state in tail {
+        permute_with_constants(state, rc);
+    }
+}
+
+/// Writes the lanes of the packed state `packed` back into the `F::Packing::WIDTH` states of
+/// `states`, transposing the lane-major packed state into the state-major layout of the rows.
+///
+/// The packed lanes are read one at a time in order, and each of them is scattered across all the
+/// states. This touches every packed vector once instead of indexing into all of them for every
+/// state, and the states of a single chunk are small enough to stay in cache while they are
+/// written.
+pub fn unpack_states<F: Field>(packed: &[F::Packing; WIDTH], states: &mut [[F; WIDTH]]) {
+    for (i, lane) in packed.iter().enumerate() {
+        for (state, &value) in states.iter_mut().zip(lane.as_slice()) {
+            state[i] = value;
+        }
+    }
+}
+
+/// Returns an upper bound on the algebraic degree of the state, as a polynomial in the input,
+/// after each round of [`permute_in_place`].
+///
+/// The degree of every lane is tracked through the rounds: an S-box multiplies the degree of its
+/// lane by 7, and the linear layers raise every lane to the maximum degree of the state, since
+/// each of their outputs depends on all of their inputs. The bound ignores the reduction of
+/// exponents modulo the field order, so it is only meaningful while it is below that order.
+pub fn round_degrees() -> [usize; NUM_ROUNDS] {
+    let mix = |lanes: &mut [usize; WIDTH]| {
+        let max = *lanes.iter().max().unwrap();
+        *lanes = [max; WIDTH];
+    };
+
+    let mut lanes = [1; WIDTH];
+    let mut degrees = [0; NUM_ROUNDS];
+    for (round, degree) in degrees.iter_mut().enumerate() {
+        let is_internal = (NUM_EXTERNAL_ROUNDS / 2..NUM_EXTERNAL_ROUNDS / 2 + NUM_INTERNAL_ROUNDS)
+            .contains(&round);
+        if is_internal {
+            lanes[0] *= 7;
+        } else {
+            lanes.iter_mut().for_each(|lane| *lane *= 7);
+        }
+        mix(&mut lanes);
+        *degree = lanes[0];
+    }
+    degrees
+}
+
+fn external_round<F: PrimeField32>(state: &mut [F; WIDTH], round: usize) {
+    for i in 0..WIDTH {
+        let add_rc = state[i] + F::from_wrapped_u32(RC_16_30_U32[round][i]);
+        let sbox_deg_3 = add_rc * add_rc * add_rc;
+        state[i] = sbox_deg_3 * sbox_deg_3 * add_rc;
+    }
+    external_linear_layer(state);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    use crate::air::Block;
+    use crate::poseidon2_wide::external::{NUM_ROUNDS, WIDTH};
+    use crate::poseidon2_wide::{permute_in_place, round_constants};
+    use crate::runtime::{ExecutionRecord, MemoryEntry, DIGEST_SIZE, HASH_RATE};
+    use itertools::Itertools;
+    use p3_baby_bear::BabyBear;
+    use p3_field::{AbstractField, Field, PackedValue, PrimeField32};
+    use p3_symmetric::Permutation;
+    use sp1_core::utils::inner_perm;
+
+    #[test]
+    fn round_degrees() {
+        // Every round applies at least one S-box to a lane of maximal degree, so the degree grows
+        // by a factor of 7 per round.
+        let expected: [usize; NUM_ROUNDS] = core::array::from_fn(|r| 7usize.pow(r as u32 + 1));
+        assert_eq!(crate::poseidon2_wide::round_degrees(), expected);
+    }
+
+    #[test]
+    fn permute_with_constants() {
+        let rc = round_constants::<BabyBear>();
+        let inputs = (0..1 << 12)
+            .map(|i| {
+                core::array::from_fn(|j| BabyBear::from_canonical_u32(i * WIDTH as u32 + j as u32))
+            })
+            .collect::<Vec<[BabyBear; WIDTH]>>();
+
+        let expected = inputs
+            .iter()
+            .map(|input| {
+                let mut state = *input;
+                permute_in_place(&mut state);
+                state
+            })
+            .collect::<Vec<_>>();
+        let outputs = inputs
+            .iter()
+            .map(|input| {
+                let mut state = *input;
+                crate::poseidon2_wide::permute_with_constants(&mut state, &rc);
+                state
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(outputs, expected);
+    }
+
+    #[test]
+    fn poseidon2_hash() {
+        use crate::poseidon2_wide::{empty_input_state, poseidon2_hash, poseidon2_hash_events};
+
+        for len in [1, 7, 8, 9, 16, 33] {
+            let input = (0..len)
+                .map(BabyBear::from_canonical_u32)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                poseidon2_hash(&input),
+                sp1_primitives::poseidon2_hash(input.clone())
+            );
+        }
+
+        // The empty digest is the permutation of a zero rate with the capacity `[0, ..., 0, 1]`,
+        // as computed by the reference permutation.
+        let mut block = [BabyBear::zero(); WIDTH];
+        block[WIDTH - 1] = BabyBear::one();
+        assert_eq!(block, empty_input_state());
+        inner_perm().permute_mut(&mut block);
+        let empty = poseidon2_hash::<BabyBear>(&[]);
+        assert_eq!(empty, core::array::from_fn(|i| block[i]));
+        assert_eq!(empty, poseidon2_hash::<BabyBear>(&[]));
+
+        // The capacity separates it from the digests of the inputs of a zero block.
+        for len in [1, HASH_RATE, HASH_RATE + 1] {
+            assert_ne!(empty, poseidon2_hash(&vec![BabyBear::zero(); len]));
+        }
+        assert_ne!(empty, [BabyBear::zero(); DIGEST_SIZE]);
+
+        // The sponge events of the empty input prove the same permutation.
+        let events =
+            poseidon2_hash_events(&mut HashMap::<usize, MemoryEntry<BabyBear>>::new(), 0, &[]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].input, empty_input_state());
+        assert_eq!(events[0].result_array[..DIGEST_SIZE], empty[..]);
+    }
+
+    /// Returns the number of output lanes of the permutation that change when `bit` of lane `lane`
+    /// of `input` is flipped.
+    ///
+    /// A flipped bit may leave the field, so the flipped lane is reduced modulo its order.
+    fn diffusion(input: [BabyBear; WIDTH], lane: usize, bit: u32) -> usize {
+        let mut flipped = input;
+        flipped[lane] = BabyBear::from_wrapped_u32(input[lane].as_canonical_u32() ^ (1 << bit));
+
+        let mut output = input;
+        permute_in_place(&mut output);
+        permute_in_place(&mut flipped);
+        output.iter().zip(&flipped).filter(|(a, b)| a != b).count()
+    }
+
+    /// A test checking that flipping any bit of any lane of pseudorandom inputs changes every
+    /// lane of the output.
+    #[test]
+    fn full_avalanche() {
+        // The inputs are drawn from the permutation itself, seeded with a counter.
+        let mut seed = [BabyBear::zero(); WIDTH];
+        for i in 0..8 {
+            seed[0] = BabyBear::from_canonical_u32(i);
+            let mut input = seed;
+            permute_in_place(&mut input);
+
+            for lane in 0..WIDTH {
+                for bit in 0..32 - BabyBear::ORDER_U32.leading_zeros() {
+                    let changed = diffusion(input, lane, bit);
+                    assert_eq!(
+                        changed, WIDTH,
+                        "incomplete diffusion: flipping bit {} of lane {} of {:?} only changes {} \
+                         of the {} output lanes",
+                        bit, lane, input, changed, WIDTH
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn poseidon2_hash_until() {
+        use crate::poseidon2_wide::{poseidon2_hash, poseidon2_hash_until};
+
+        let mut counter = 0u32;
+        let mut memory = HashMap::<usize, MemoryEntry<BabyBear>>::new();
+        let mut events = Vec::new();
+        let (digest, iterations) = poseidon2_hash_until(
+            || {
+                counter += 1;
+                vec![BabyBear::from_canonical_u32(counter); HASH_RATE + 1]
+            },
+            |digest| digest[0].as_canonical_u32() % 2 == 0,
+            &mut memory,
+            0,
+            &mut events,
+        );
+        assert!(iterations >= 1);
+        assert_eq!(iterations, counter as u64);
+        assert_eq!(digest[0].as_canonical_u32() % 2, 0);
+        let input = vec![BabyBear::from_canonical_u32(counter); HASH_RATE + 1];
+        assert_eq!(digest, poseidon2_hash(&input));
+
+        // Only the two permutations of the successful input are recorded, the second one
+        // reading the state written back by the first one.
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].result_array[..DIGEST_SIZE], digest);
+        assert_eq!(
+            events[1].clk,
+            events[0].clk + BabyBear::from_canonical_u32(3)
+        );
+        assert_eq!(
+            events[1].input_records[HASH_RATE].value,
+            Block::from(events[0].result_array[HASH_RATE])
+        );
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record.poseidon2_events = events;
+        record.validate().unwrap();
+    }
+
+    #[test]
+    fn poseidon2_hash_fields() {
+        use crate::poseidon2_wide::{poseidon2_hash, poseidon2_hash_fields};
+
+        let [one, two, three] = [1, 2, 3].map(BabyBear::from_canonical_u32);
+        let shifted_right = poseidon2_hash_fields::<BabyBear>(&[&[one, two], &[three]]);
+        let shifted_left = poseidon2_hash_fields::<BabyBear>(&[&[one], &[two, three]]);
+        assert_ne!(shifted_right, shifted_left);
+
+        // Every part is absorbed after its length.
+        assert_eq!(shifted_right, poseidon2_hash(&[two, one, two, one, three]));
+
+        // Empty parts are not dropped either.
+        let empty: &[BabyBear] = &[];
+        assert_ne!(
+            poseidon2_hash_fields::<BabyBear>(&[empty, &[one]]),
+            poseidon2_hash_fields::<BabyBear>(&[&[one]])
+        );
+    }
+
+    /// A test checking that the packed batch permutation, including its scalar tail, matches the
+    /// scalar permutation for a number of events that the packing width does not divide.
+    #[test]
+    fn permute_batch() {
+        let rc = round_constants::<BabyBear>();
+        let inputs = (0..108173u32)
+            .map(|i| {
+                core::array::from_fn(|j| BabyBear::from_canonical_u32(i * WIDTH as u32 + j as u32))
+            })
+            .collect::<Vec<[BabyBear; WIDTH]>>();
+
+        let mut batch = inputs.clone();
+        crate::poseidon2_wide::permute_batch(&mut batch, &rc);
+
+        for (input, output) in inputs.iter().zip(&batch) {
+            let mut expected = *input;
+            crate::poseidon2_wide::permute_with_constants(&mut expected, &rc);
+            assert_eq!(*output, expected);
+        }
+    }
+
+    /// A best-effort test that the time taken by `permute_in_place` does not depend on its input.
+    ///
+    /// Batches of inputs of each class are timed in turn, so that any drift of the machine affects
+    /// every class alike, and the fastest batches of the classes are compared. The bound is loose
+    /// enough to only catch a data-dependent branch skipping a sizeable part of the work.
+    ///
+    /// Wall-clock time depends on the machine and its load, so this is ignored by default and
+    /// meant to be run on its own with `cargo test --release -- --ignored constant_time`. The
+    /// guarantee itself rests on the code having no data-dependent branches, as documented on
+    /// `permute_in_place`.
+    #[test]
+    #[ignore]
+    fn constant_time_permutation() {
+        const BATCH: usize = 64;
+        const SAMPLES: usize = 200;
+
+        let mut random = [BabyBear::one(); WIDTH];
+        let mut randoms = Vec::with_capacity(BATCH);
+        for _ in 0..BATCH {
+            permute_in_place(&mut random);
+            randoms.push(random);
+        }
+        let classes = [
+            vec![[BabyBear::zero(); WIDTH]; BATCH],
+            vec![[BabyBear::neg_one(); WIDTH]; BATCH],
+            randoms,
+        ];
+
+        let mut fastest = [Duration::MAX; 3];
+        for _ in 0..SAMPLES {
+            for (class, inputs) in classes.iter().enumerate() {
+                let mut states = inputs.clone();
+                let start = Instant::now();
+                for state in states.iter_mut() {
+                    permute_in_place(std::hint::black_box(state));
+                }
+                std::hint::black_box(&states);
+                fastest[class] = fastest[class].min(start.elapsed());
+            }
+        }
+
+        let min = fastest.iter().min().unwrap().as_secs_f64();
+        let max = fastest.iter().max().unwrap().as_secs_f64();
+        assert!(
+            max < 1.5 * min,
+            "the permutation time depends on the input: {:?}",
+            fastest
+        );
+    }
+
+    /// A test checking that writing packed states back into rows gives the same states as
+    /// gathering every entry of every state from the packed lanes.
+    #[test]
+    fn unpack_states() {
+        type Packing = <BabyBear as Field>::Packing;
+        let num_chunks = 1 << 8;
+        let packed = (0..num_chunks)
+            .map(|c| {
+                core::array::from_fn::<Packing, WIDTH, _>(|i| {
+                    Packing::from_fn(|j| {
+                        BabyBear::from_canonical_usize((c * WIDTH + i) * Packing::WIDTH + j)
+                    })
+                })
+            })
+            .collect_vec();
+
+        let expected = packed
+            .iter()
+            .flat_map(|state| {
+                (0..Packing::WIDTH)
+                    .map(|j| core::array::from_fn::<BabyBear, WIDTH, _>(|i| state[i].as_slice()[j]))
+            })
+            .collect_vec();
+        let mut states = vec![[BabyBear::zero(); WIDTH]; num_chunks * Packing::WIDTH];
+        for (state, chunk) in packed.iter().zip(states.chunks_exact_mut(Packing::WIDTH)) {
+            crate::poseidon2_wide::unpack_states(state, chunk);
+        }
+        assert_eq!(states, expected);
+    }
+}
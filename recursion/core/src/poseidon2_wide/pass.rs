@@ -0,0 +1,184 @@
+//! Generates the traces of several chips reading the Poseidon2 events of a record in a single
+//! pass over the events.
+//!
+//! A machine can prove the same events with several chips, such as the wide chip alongside a
+//! chip decomposing its outputs. Generating their rows together visits every event once rather
+//! than once per chip.
+
+use p3_field::Field;
+use p3_matrix::dense::RowMajorMatrix;
+use p3_maybe_rayon::prelude::*;
+
+use crate::poseidon2::Poseidon2Event;
+use crate::runtime::ExecutionRecord;
+
+/// A function filling the zeroed row of an event.
+pub type EventRowPopulator<'a, F> = Box<dyn Fn(&Poseidon2Event<F>, &mut [F]) + Sync + 'a>;
+
+/// A chip whose trace starts with one row for every Poseidon2 event of a record, each depending
+/// only on its own event.
+pub trait Poseidon2EventRows<F>: Sync {
+    /// Returns the number of columns of the row of an event.
+    fn event_row_width(&self) -> usize;
+
+    /// Returns the function filling the row of an event, which is only built once for a pass,
+    /// so that it can hold anything the rows share.
+    fn event_row_populator(&self) -> EventRowPopulator<'_, F>;
+
+    /// Builds the trace from the rows of `events`, in order, padding it and appending any
+    /// columns that do not only depend on their own event.
+    fn trace_from_event_rows(
+        &self,
+        rows: RowMajorMatrix<F>,
+        events: &[Poseidon2Event<F>],
+    ) -> RowMajorMatrix<F>;
+}
+
+/// Generates the traces of `chips` for the Poseidon2 events of `record` in one pass over the
+/// events, in the order of the chips.
+///
+/// Every chip gets the trace it would generate for the record on its own, so each chip must
+/// give a row to every event of the record.
+pub fn generate_traces<F: Field>(
+    record: &ExecutionRecord<F>,
+    chips: &[&dyn Poseidon2EventRows<F>],
+) -> Vec<RowMajorMatrix<F>> {
+    let events = &record.poseidon2_events;
+    let widths = chips
+        .iter()
+        .map(|chip| chip.event_row_width())
+        .collect::<Vec<_>>();
+    let populators = chips
+        .iter()
+        .map(|chip| chip.event_row_populator())
+        .collect::<Vec<_>>();
+
+    // Generate the rows of all of the chips for an event side by side.
+    let total_width = widths.iter().sum::<usize>();
+    let rows = events
+        .par_iter()
+        .map(|event| {
+            let mut row = vec![F::zero(); total_width];
+            let mut offset = 0;
+            for (populate, &width) in populators.iter().zip(&widths) {
+                populate(event, &mut row[offset..offset + width]);
+                offset += width;
+            }
+            row
+        })
+        .collect::<Vec<_>>();
+
+    let mut offset = 0;
+    chips
+        .iter()
+        .zip(&widths)
+        .map(|(chip, &width)| {
+            let values = rows
+                .iter()
+                .flat_map(|row| &row[offset..offset + width])
+                .copied()
+                .collect();
+            offset += width;
+            chip.trace_from_event_rows(RowMajorMatrix::new(values, width), events)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::{AbstractField, PrimeField32};
+    use p3_matrix::dense::RowMajorMatrix;
+    use sp1_core::air::MachineAir;
+    use sp1_core::utils::pad_to_power_of_two;
+
+    use super::{generate_traces, EventRowPopulator, Poseidon2EventRows};
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::WIDTH;
+    use crate::poseidon2_wide::{Poseidon2WideChip, Poseidon2WideOptions};
+    use crate::runtime::ExecutionRecord;
+
+    const NUM_BYTE_COLS: usize = WIDTH * 4;
+
+    /// A chip decomposing the output lanes of every event into their little-endian bytes.
+    struct ByteDecompositionChip;
+
+    impl ByteDecompositionChip {
+        fn generate_trace(&self, record: &ExecutionRecord<BabyBear>) -> RowMajorMatrix<BabyBear> {
+            let mut values = record
+                .poseidon2_events
+                .iter()
+                .flat_map(|event| event.result_array)
+                .flat_map(|lane| lane.as_canonical_u32().to_le_bytes())
+                .map(BabyBear::from_canonical_u8)
+                .collect::<Vec<_>>();
+            pad_to_power_of_two::<NUM_BYTE_COLS, BabyBear>(&mut values);
+            RowMajorMatrix::new(values, NUM_BYTE_COLS)
+        }
+    }
+
+    impl Poseidon2EventRows<BabyBear> for ByteDecompositionChip {
+        fn event_row_width(&self) -> usize {
+            NUM_BYTE_COLS
+        }
+
+        fn event_row_populator(&self) -> EventRowPopulator<'_, BabyBear> {
+            Box::new(|event, row| {
+                for (i, lane) in event.result_array.iter().enumerate() {
+                    for (j, byte) in lane
+                        .as_canonical_u32()
+                        .to_le_bytes()
+                        .into_iter()
+                        .enumerate()
+                    {
+                        row[i * 4 + j] = BabyBear::from_canonical_u8(byte);
+                    }
+                }
+            })
+        }
+
+        fn trace_from_event_rows(
+            &self,
+            mut rows: RowMajorMatrix<BabyBear>,
+            _: &[Poseidon2Event<BabyBear>],
+        ) -> RowMajorMatrix<BabyBear> {
+            pad_to_power_of_two::<NUM_BYTE_COLS, BabyBear>(&mut rows.values);
+            rows
+        }
+    }
+
+    #[test]
+    fn traces_in_one_pass() {
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..11 {
+            let input = core::array::from_fn(|j| BabyBear::from_canonical_usize(i * WIDTH + j));
+            let num_internal_rounds = 8 + i % 6;
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_with_internal_rounds(
+                    input,
+                    num_internal_rounds,
+                ));
+        }
+
+        let wide = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            rc_bus: Some(1 << 20),
+            variable_internal_rounds: true,
+            padding_sentinel: Some(7),
+            ..Default::default()
+        });
+        let bytes = ByteDecompositionChip;
+        let chips: [&dyn Poseidon2EventRows<BabyBear>; 2] = [&wide, &bytes];
+        let traces = generate_traces(&record, &chips);
+
+        // Every trace is the one its chip generates on its own.
+        assert_eq!(traces.len(), 2);
+        let wide_trace: RowMajorMatrix<BabyBear> =
+            wide.generate_trace(&record, &mut ExecutionRecord::default());
+        assert_eq!(traces[0].values, wide_trace.values);
+        assert_eq!(traces[0].width, wide_trace.width);
+        let bytes_trace = bytes.generate_trace(&record);
+        assert_eq!(traces[1].values, bytes_trace.values);
+        assert_eq!(traces[1].width, bytes_trace.width);
+    }
+}
@@ -0,0 +1,198 @@
+//! Receipts attesting the Poseidon2 hash of some data, for applications that want a proof of a
+//! hash without assembling the record and public values of the chip themselves.
+
+use std::collections::HashMap;
+
+use p3_field::PrimeField32;
+use p3_uni_stark::{Proof, VerificationError};
+use sp1_core::stark::{StarkGenericConfig, UniConfig, Val};
+
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::WIDTH;
+use crate::poseidon2_wide::{poseidon2_hash_events, Poseidon2WideChip, Poseidon2WideOptions};
+use crate::runtime::{ExecutionRecord, MemoryEntry, DIGEST_SIZE};
+
+/// A proof that `digest` is the [`poseidon2_hash`](crate::poseidon2_wide::poseidon2_hash) of
+/// `data`.
+pub struct HashReceipt<SC: StarkGenericConfig> {
+    /// The hashed data.
+    pub data: Vec<Val<SC>>,
+    /// The state absorbing the last block of the data, which the sponge over `data` reaches.
+    pub input_commitment: [Val<SC>; WIDTH],
+    /// The first `DIGEST_SIZE` lanes of the permutation of `input_commitment`.
+    pub digest: [Val<SC>; DIGEST_SIZE],
+    /// A proof of the permutations of the hash, exposing the input and the digest of the last
+    /// one.
+    pub proof: Proof<UniConfig<SC>>,
+}
+
+/// An error returned by [`HashReceipt::verify`].
+#[derive(Debug)]
+pub enum HashReceiptError {
+    /// The committed input is not the last state of the sponge over the data.
+    InputMismatch,
+    /// The digest is not the digest of the committed input.
+    DigestMismatch,
+    /// The proof is invalid.
+    Verification(VerificationError),
+}
+
+/// Returns the chip proving the hashes of receipts, which exposes the input and the digest of
+/// the permutation of its first row.
+fn receipt_chip() -> Poseidon2WideChip<3> {
+    Poseidon2WideChip::new(Poseidon2WideOptions {
+        expose_digest: true,
+        public_input: true,
+        ..Default::default()
+    })
+}
+
+/// Returns the events of the sponge over `data`, replayed in a scratch memory holding its state
+/// at address zero.
+fn sponge_events<F: PrimeField32>(data: &[F]) -> Vec<Poseidon2Event<F>> {
+    poseidon2_hash_events(&mut HashMap::<usize, MemoryEntry<F>>::new(), 0, data)
+}
+
+/// Hashes `data` with [`poseidon2_hash`](crate::poseidon2_wide::poseidon2_hash) and proves it
+/// under `config`, returning the receipt of the hash.
+///
+/// The permutations of the sponge are proven with the last one first, so that its input and
+/// output are the public values of the proof. The chip proves every permutation but does not
+/// constrain how the states of the sponge are linked, so the receipt carries the data, and the
+/// verifier replays the sponge over it to link the committed input to the data.
+pub fn prove_hash<SC>(config: &SC, data: &[Val<SC>]) -> HashReceipt<SC>
+where
+    SC: StarkGenericConfig,
+    Val<SC>: PrimeField32,
+{
+    let mut record = ExecutionRecord::<Val<SC>>::default();
+    record.poseidon2_events = sponge_events(data);
+    record.poseidon2_events.reverse();
+    let last = &record.poseidon2_events[0];
+    let input_commitment = last.input;
+    let digest = core::array::from_fn(|i| last.result_array[i]);
+
+    let chip = receipt_chip();
+    let public_values = chip.public_values(&record, &[]);
+    let proof = chip
+        .prove_with_public_values(config, &record, &public_values)
+        .expect("a hash has at least one permutation");
+    HashReceipt {
+        data: data.to_vec(),
+        input_commitment,
+        digest,
+        proof,
+    }
+}
+
+impl<SC> HashReceipt<SC>
+where
+    SC: StarkGenericConfig,
+    Val<SC>: PrimeField32,
+{
+    /// Checks that the committed input is the last state of the sponge over the data, that the
+    /// digest is the digest of that input, and that the proof attests it under `config`.
+    ///
+    /// The sponge is replayed natively, so a receipt for a state that the data does not reach
+    /// is rejected even if its proof is valid.
+    pub fn verify(&self, config: &SC) -> Result<(), HashReceiptError> {
+        let events = sponge_events(&self.data);
+        let last = events.last().expect("a hash has at least one permutation");
+        if last.input != self.input_commitment {
+            return Err(HashReceiptError::InputMismatch);
+        }
+        if last.result_array[..DIGEST_SIZE] != self.digest {
+            return Err(HashReceiptError::DigestMismatch);
+        }
+
+        let public_values = self
+            .digest
+            .iter()
+            .chain(&self.input_commitment)
+            .copied()
+            .collect::<Vec<_>>();
+        receipt_chip()
+            .verify_only(config, &self.proof, &public_values)
+            .map_err(HashReceiptError::Verification)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use sp1_core::utils::BabyBearPoseidon2Inner;
+
+    use super::{prove_hash, receipt_chip, HashReceipt, HashReceiptError};
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::WIDTH;
+    use crate::poseidon2_wide::{permute_in_place, poseidon2_hash};
+    use crate::runtime::ExecutionRecord;
+
+    #[test]
+    fn hash_receipt() {
+        let config = BabyBearPoseidon2Inner::new();
+        let data = (0..20)
+            .map(|i| BabyBear::from_canonical_u32(i * 7 + 1))
+            .collect::<Vec<_>>();
+        let mut receipt = prove_hash(&config, &data);
+        assert_eq!(receipt.digest, poseidon2_hash(&data));
+        receipt
+            .verify(&config)
+            .expect("expected receipt to be valid");
+
+        // A tampered digest no longer matches the committed input.
+        let digest = receipt.digest;
+        receipt.digest[3] += BabyBear::one();
+        assert!(matches!(
+            receipt.verify(&config),
+            Err(HashReceiptError::DigestMismatch)
+        ));
+        receipt.digest = digest;
+
+        // A consistent digest of a tampered input is not reached by the data.
+        receipt.input_commitment[0] += BabyBear::one();
+        let mut state = receipt.input_commitment;
+        permute_in_place(&mut state);
+        receipt.digest = core::array::from_fn(|i| state[i]);
+        assert!(matches!(
+            receipt.verify(&config),
+            Err(HashReceiptError::InputMismatch)
+        ));
+    }
+
+    /// A test checking that a receipt for a state that no data produced is rejected, even though
+    /// its proof of the permutation of that state is valid.
+    #[test]
+    fn forged_receipt() {
+        let config = BabyBearPoseidon2Inner::new();
+        let state: [BabyBear; WIDTH] = core::array::from_fn(|i| BabyBear::from_canonical_usize(i));
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record
+            .poseidon2_events
+            .push(Poseidon2Event::dummy_from_input(state));
+
+        let chip = receipt_chip();
+        let public_values = chip.public_values(&record, &[]);
+        let proof = chip
+            .prove_with_public_values(&config, &record, &public_values)
+            .unwrap();
+        chip.verify_only(&config, &proof, &public_values)
+            .expect("expected proof of the permutation to be valid");
+
+        let output = record.poseidon2_events[0].result_array;
+        let mut receipt = HashReceipt {
+            data: vec![],
+            input_commitment: state,
+            digest: core::array::from_fn(|i| output[i]),
+            proof,
+        };
+        for data in [vec![], state[..8].to_vec(), state.to_vec()] {
+            receipt.data = data;
+            assert!(matches!(
+                receipt.verify(&config),
+                Err(HashReceiptError::InputMismatch)
+            ));
+        }
+    }
+}
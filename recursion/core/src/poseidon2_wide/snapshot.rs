@@ -0,0 +1,265 @@
+//! Synthesizes Poseidon2 events from a snapshot of the memory of the recursion VM.
+//!
+//! This turns a captured VM state into a provable record offline, for debugging executions
+//! without running their program again.
+
+use std::collections::HashMap;
+
+use p3_field::PrimeField32;
+
+use crate::air::Block;
+use crate::memory::MemoryRecord;
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::WIDTH;
+use crate::runtime::{ExecutionRecord, MemoryEntry};
+
+/// A permutation to synthesize: the `WIDTH` input lanes are read starting at `input_addr` and the
+/// `WIDTH` output lanes are written starting at `output_addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermutationSpec {
+    pub input_addr: usize,
+    pub output_addr: usize,
+}
+
+/// A memory that the permutations of [`record_from_backend`] read their inputs from and write
+/// their outputs to, so that synthesizing events is not tied to a representation of memory.
+///
+/// Every access returns its record, holding the value read or written along with the value and
+/// timestamp of the previous access to the same address, as the memory columns need them.
+pub trait MemoryBackend<F> {
+    /// Reads the value at `addr` at timestamp `clk`.
+    fn read(&mut self, addr: usize, clk: F) -> MemoryRecord<F>;
+
+    /// Writes `value` to `addr` at timestamp `clk`.
+    fn write(&mut self, addr: usize, clk: F, value: F) -> MemoryRecord<F>;
+
+    /// Returns the latest timestamp of any access to the memory, or zero if there is none.
+    fn latest_timestamp(&self) -> F;
+}
+
+/// A memory held in a map from addresses to their entries, like the memory of the runtime. An
+/// address that is not in the map holds zero and was last accessed at timestamp zero.
+impl<F: PrimeField32> MemoryBackend<F> for HashMap<usize, MemoryEntry<F>> {
+    fn read(&mut self, addr: usize, clk: F) -> MemoryRecord<F> {
+        let entry = self.entry(addr).or_default();
+        let record = MemoryRecord::new_read(
+            F::from_canonical_usize(addr),
+            entry.value,
+            clk,
+            entry.timestamp,
+        );
+        entry.timestamp = clk;
+        record
+    }
+
+    fn write(&mut self, addr: usize, clk: F, value: F) -> MemoryRecord<F> {
+        let entry = self.entry(addr).or_default();
+        let value = Block::from(value);
+        let record = MemoryRecord::new_write(
+            F::from_canonical_usize(addr),
+            value,
+            clk,
+            entry.value,
+            entry.timestamp,
+        );
+        *entry = MemoryEntry {
+            value,
+            timestamp: clk,
+        };
+        record
+    }
+
+    fn latest_timestamp(&self) -> F {
+        self.values()
+            .map(|entry| entry.timestamp)
+            .max_by_key(|timestamp| timestamp.as_canonical_u32())
+            .unwrap_or_else(F::zero)
+    }
+}
+
+/// Builds a record holding one event for every spec, applied in order to `snapshot`, which maps
+/// addresses to their entries like the memory of the runtime.
+///
+/// This is [`record_from_backend`] over the map.
+pub fn record_from_snapshot<F: PrimeField32>(
+    snapshot: &mut HashMap<usize, MemoryEntry<F>>,
+    specs: &[PermutationSpec],
+) -> ExecutionRecord<F> {
+    record_from_backend(snapshot, specs)
+}
+
+/// Builds a record holding one event for every spec, applied in order to `memory`.
+///
+/// The permutations run after every access of the memory: the first one reads its input one
+/// cycle after the latest timestamp of the memory and writes its output the cycle after, and
+/// each following one starts after the previous one. The outputs are written back to the
+/// memory as they are produced, so a later spec can read the output of an earlier one.
+pub fn record_from_backend<F: PrimeField32>(
+    memory: &mut impl MemoryBackend<F>,
+    specs: &[PermutationSpec],
+) -> ExecutionRecord<F> {
+    let mut clk = memory.latest_timestamp() + F::one();
+
+    let mut record = ExecutionRecord::<F>::default();
+    for spec in specs {
+        record.poseidon2_events.push(Poseidon2Event::from_memory(
+            memory,
+            clk,
+            spec.output_addr,
+            spec.input_addr,
+            spec.input_addr + WIDTH / 2,
+            [F::zero(); WIDTH],
+        ));
+        clk += F::two();
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+    use sp1_core::air::MachineAir;
+
+    use super::{record_from_backend, record_from_snapshot, MemoryBackend, PermutationSpec};
+    use crate::air::Block;
+    use crate::memory::MemoryRecord;
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::WIDTH;
+    use crate::poseidon2_wide::Poseidon2WideChip;
+    use crate::runtime::{ExecutionRecord, MemoryEntry};
+    use crate::stark::utils::check_constraints;
+
+    #[test]
+    fn events_from_snapshot() {
+        let input: [BabyBear; WIDTH] = core::array::from_fn(BabyBear::from_canonical_usize);
+        let mut snapshot = (0..WIDTH)
+            .map(|i| {
+                let entry = MemoryEntry {
+                    value: Block::from(input[i]),
+                    timestamp: BabyBear::from_canonical_usize(3 + i),
+                };
+                (i, entry)
+            })
+            .collect::<HashMap<_, _>>();
+
+        // The second permutation reads the output of the first.
+        let specs = [
+            PermutationSpec {
+                input_addr: 0,
+                output_addr: 32,
+            },
+            PermutationSpec {
+                input_addr: 32,
+                output_addr: 64,
+            },
+        ];
+        let record = record_from_snapshot(&mut snapshot, &specs);
+        let events = &record.poseidon2_events;
+        assert_eq!(events.len(), 2);
+
+        let first = Poseidon2Event::dummy_from_input(input);
+        let second = Poseidon2Event::dummy_from_input(first.result_array);
+        assert_eq!(events[0].input, input);
+        assert_eq!(events[0].result_array, first.result_array);
+        assert_eq!(events[1].input, first.result_array);
+        assert_eq!(events[1].result_array, second.result_array);
+
+        // The first permutation runs after the latest access of the snapshot.
+        assert_eq!(events[0].clk, BabyBear::from_canonical_usize(3 + WIDTH));
+        assert_eq!(events[1].clk, events[0].clk + BabyBear::two());
+        assert_eq!(
+            events[0].input_records[5].prev_timestamp,
+            BabyBear::from_canonical_usize(8)
+        );
+        assert_eq!(
+            events[1].input_records[0].prev_timestamp,
+            events[0].clk + BabyBear::one()
+        );
+        assert_eq!(
+            snapshot[&(64 + 7)].value,
+            Block::from(second.result_array[7])
+        );
+        record.validate().unwrap();
+
+        let chip = Poseidon2WideChip::<3>::default();
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        check_constraints(&chip, &trace, &[]).expect("expected constraints to hold");
+    }
+
+    /// A backend over a map that logs every access, as the address, the timestamp and whether
+    /// it is a write.
+    #[derive(Default)]
+    struct LoggingBackend {
+        memory: HashMap<usize, MemoryEntry<BabyBear>>,
+        log: Vec<(usize, BabyBear, bool)>,
+    }
+
+    impl MemoryBackend<BabyBear> for LoggingBackend {
+        fn read(&mut self, addr: usize, clk: BabyBear) -> MemoryRecord<BabyBear> {
+            self.log.push((addr, clk, false));
+            self.memory.read(addr, clk)
+        }
+
+        fn write(&mut self, addr: usize, clk: BabyBear, value: BabyBear) -> MemoryRecord<BabyBear> {
+            self.log.push((addr, clk, true));
+            self.memory.write(addr, clk, value)
+        }
+
+        fn latest_timestamp(&self) -> BabyBear {
+            self.memory.latest_timestamp()
+        }
+    }
+
+    #[test]
+    fn events_from_backend() {
+        let mut backend = LoggingBackend::default();
+        for i in 0..WIDTH {
+            backend
+                .memory
+                .write(i, BabyBear::one(), BabyBear::from_canonical_usize(i));
+        }
+        let specs = [
+            PermutationSpec {
+                input_addr: 0,
+                output_addr: 16,
+            },
+            PermutationSpec {
+                input_addr: 16,
+                output_addr: 0,
+            },
+        ];
+        let record = record_from_backend(&mut backend, &specs);
+
+        // Every permutation reads its input lanes in order, then writes its output lanes in order
+        // one cycle later.
+        let two = BabyBear::two();
+        let expected = [(0, 16, two), (16, 0, two + two)]
+            .into_iter()
+            .flat_map(|(input_addr, output_addr, clk)| {
+                let reads = (0..WIDTH).map(move |i| (input_addr + i, clk, false));
+                let writes =
+                    (0..WIDTH).map(move |i| (output_addr + i, clk + BabyBear::one(), true));
+                reads.chain(writes)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(backend.log, expected);
+
+        // The backend holds the outputs, and the events prove.
+        let events = &record.poseidon2_events;
+        assert_eq!(events[1].input, events[0].result_array);
+        assert_eq!(
+            backend.memory[&3].value,
+            Block::from(events[1].result_array[3])
+        );
+        record.validate().unwrap();
+        let chip = Poseidon2WideChip::<3>::default();
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        check_constraints(&chip, &trace, &[]).expect("expected constraints to hold");
+    }
+}
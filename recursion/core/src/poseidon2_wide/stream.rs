@@ -0,0 +1,254 @@
+//! A flat, versioned encoding of the Poseidon2 events of a record, for external provers.
+//!
+//! The stream holds every value needed to regenerate the exact trace of [`Poseidon2WideChip`],
+//! encoded as canonical `u32`s, along with a manifest describing the permutation it was produced
+//! for.
+
+use p3_field::PrimeField32;
+use serde::{Deserialize, Serialize};
+
+use crate::air::Block;
+use crate::memory::MemoryRecord;
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::{NUM_EXTERNAL_ROUNDS, NUM_INTERNAL_ROUNDS, WIDTH};
+use crate::poseidon2_wide::Poseidon2WideChip;
+use crate::runtime::{ExecutionRecord, D};
+
+/// The version of the encoding of [`EventStream`].
+pub const EVENT_STREAM_VERSION: u32 = 4;
+
+/// The number of words encoding a single event.
+///
+/// An event is encoded as its operands, its input, initialization vector and output lanes,
+/// whether its output is negated, by how many lanes it is rotated and how many internal rounds
+/// it has, and then its input and output memory records.
+const EVENT_WORDS: usize = 7 + 3 * WIDTH + WIDTH * (D + 3) + WIDTH * (2 * D + 3);
+
+/// The parameters of the permutation that the events of a stream were produced for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventStreamManifest {
+    pub version: u32,
+    pub num_events: usize,
+    pub width: usize,
+    pub num_external_rounds: usize,
+    pub num_internal_rounds: usize,
+    /// A checksum of the table of round constants.
+    pub rc_checksum: u64,
+}
+
+/// The Poseidon2 events of a record, encoded as a flat stream of words.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventStream {
+    pub manifest: EventStreamManifest,
+    pub words: Vec<u32>,
+}
+
+/// An error returned when decoding an [`EventStream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventStreamError {
+    /// The stream was encoded with an unsupported version.
+    UnsupportedVersion(u32),
+    /// The stream was produced for a permutation with a different width or round schedule.
+    ScheduleMismatch,
+    /// The stream was produced for a different table of round constants.
+    RoundConstantsMismatch,
+    /// The number of words does not match the number of events in the manifest.
+    LengthMismatch(usize, usize),
+}
+
+impl EventStream {
+    /// Encodes the Poseidon2 events of `record`, as proven by `chip`.
+    pub fn from_record<F: PrimeField32, const DEGREE: usize>(
+        chip: &Poseidon2WideChip<DEGREE>,
+        record: &ExecutionRecord<F>,
+    ) -> Self {
+        let mut words = Vec::with_capacity(record.poseidon2_events.len() * EVENT_WORDS);
+        for event in &record.poseidon2_events {
+            let mut push = |value: F| words.push(value.as_canonical_u32());
+
+            push(event.clk);
+            push(event.dst);
+            push(event.left);
+            push(event.right);
+            event.input.into_iter().for_each(&mut push);
+            event.iv.into_iter().for_each(&mut push);
+            event.result_array.into_iter().for_each(&mut push);
+            push(F::from_bool(event.negate_output));
+            push(F::from_canonical_usize(event.output_rotation));
+            push(F::from_canonical_usize(event.num_internal_rounds));
+            for record in &event.input_records {
+                push(record.addr);
+                record.value.0.into_iter().for_each(&mut push);
+                push(record.timestamp);
+                push(record.prev_timestamp);
+            }
+            for record in &event.result_records {
+                push(record.addr);
+                record.value.0.into_iter().for_each(&mut push);
+                record.prev_value.0.into_iter().for_each(&mut push);
+                push(record.timestamp);
+                push(record.prev_timestamp);
+            }
+        }
+
+        Self {
+            manifest: EventStreamManifest {
+                version: EVENT_STREAM_VERSION,
+                num_events: record.poseidon2_events.len(),
+                width: WIDTH,
+                num_external_rounds: NUM_EXTERNAL_ROUNDS,
+                num_internal_rounds: NUM_INTERNAL_ROUNDS,
+                rc_checksum: rc_checksum(chip),
+            },
+            words,
+        }
+    }
+
+    /// Decodes the stream into a record holding its Poseidon2 events, checking that it was
+    /// produced for the same permutation as `chip`.
+    pub fn to_record<F: PrimeField32, const DEGREE: usize>(
+        &self,
+        chip: &Poseidon2WideChip<DEGREE>,
+    ) -> Result<ExecutionRecord<F>, EventStreamError> {
+        let manifest = &self.manifest;
+        if manifest.version != EVENT_STREAM_VERSION {
+            return Err(EventStreamError::UnsupportedVersion(manifest.version));
+        }
+        if manifest.width != WIDTH
+            || manifest.num_external_rounds != NUM_EXTERNAL_ROUNDS
+            || manifest.num_internal_rounds != NUM_INTERNAL_ROUNDS
+        {
+            return Err(EventStreamError::ScheduleMismatch);
+        }
+        if manifest.rc_checksum != rc_checksum(chip) {
+            return Err(EventStreamError::RoundConstantsMismatch);
+        }
+        if self.words.len() != manifest.num_events * EVENT_WORDS {
+            return Err(EventStreamError::LengthMismatch(
+                manifest.num_events * EVENT_WORDS,
+                self.words.len(),
+            ));
+        }
+
+        let mut record = ExecutionRecord::<F>::default();
+        for chunk in self.words.chunks_exact(EVENT_WORDS) {
+            let mut words = chunk.iter().map(|&word| F::from_canonical_u32(word));
+            let mut next = || words.next().unwrap();
+
+            let clk = next();
+            let dst = next();
+            let left = next();
+            let right = next();
+            let input = core::array::from_fn(|_| next());
+            let iv = core::array::from_fn(|_| next());
+            let result_array = core::array::from_fn(|_| next());
+            let negate_output = next().is_one();
+            let output_rotation = next().as_canonical_u32() as usize;
+            let num_internal_rounds = next().as_canonical_u32() as usize;
+            let input_records = core::array::from_fn(|_| {
+                let addr = next();
+                let value = Block(core::array::from_fn(|_| next()));
+                let timestamp = next();
+                let prev_timestamp = next();
+                MemoryRecord::new_read(addr, value, timestamp, prev_timestamp)
+            });
+            let result_records = core::array::from_fn(|_| {
+                let addr = next();
+                let value = Block(core::array::from_fn(|_| next()));
+                let prev_value = Block(core::array::from_fn(|_| next()));
+                let timestamp = next();
+                let prev_timestamp = next();
+                MemoryRecord::new_write(addr, value, timestamp, prev_value, prev_timestamp)
+            });
+
+            record.poseidon2_events.push(Poseidon2Event {
+                clk,
+                dst,
+                left,
+                right,
+                input,
+                iv,
+                result_array,
+                negate_output,
+                output_rotation,
+                num_internal_rounds,
+                input_records,
+                result_records,
+            });
+        }
+        Ok(record)
+    }
+}
+
+/// Computes the FNV-1a hash of the round constants used by `chip`.
+fn rc_checksum<const DEGREE: usize>(chip: &Poseidon2WideChip<DEGREE>) -> u64 {
+    fnv1a(
+        chip.round_constant_table()
+            .iter()
+            .flatten()
+            .flat_map(|constant| constant.to_le_bytes()),
+    )
+}
+
+/// Computes the FNV-1a hash of `bytes`.
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+    use sp1_core::air::MachineAir;
+    use sp1_primitives::RC_16_30_U32;
+
+    use super::{EventStream, EventStreamError};
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::{NUM_ROUNDS, WIDTH};
+    use crate::poseidon2_wide::{Poseidon2WideChip, Poseidon2WideOptions};
+    use crate::runtime::ExecutionRecord;
+
+    #[test]
+    fn round_trip() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..5 {
+            let input = core::array::from_fn(|j| BabyBear::from_canonical_u32(i * 17 + j as u32));
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+
+        let stream = EventStream::from_record(&chip, &record);
+        assert_eq!(stream.manifest.num_events, 5);
+        let decoded = stream.to_record::<BabyBear, 3>(&chip).unwrap();
+
+        let expected: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        let actual: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&decoded, &mut ExecutionRecord::default());
+        assert_eq!(actual.values, expected.values);
+
+        // A chip with other round constants refuses the stream.
+        let other = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            custom_round_constants: Some(core::array::from_fn(|r| {
+                RC_16_30_U32[NUM_ROUNDS - 1 - r]
+            })),
+            ..Default::default()
+        });
+        assert_eq!(
+            stream.to_record::<BabyBear, 3>(&other).unwrap_err(),
+            EventStreamError::RoundConstantsMismatch
+        );
+
+        let mut truncated = stream.clone();
+        truncated.words.truncate(WIDTH);
+        assert!(matches!(
+            truncated.to_record::<BabyBear, 3>(&chip),
+            Err(EventStreamError::LengthMismatch(..))
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+//! Proofs of Poseidon2 permutations bound to a timestamp, for applications attesting that some
+//! states were permuted along with a point in time.
+//!
+//! The timestamp is provided by the prover and is only bound to the proof: the chip does not
+//! check it against any clock, so a proof attests what timestamp it was made for, not when it
+//! was made.
+
+use std::collections::HashMap;
+
+use p3_field::{AbstractField, PrimeField32};
+use p3_uni_stark::{Proof, VerificationError};
+use sp1_core::stark::{StarkGenericConfig, UniConfig, Val};
+
+use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::WIDTH;
+use crate::poseidon2_wide::{
+    permute_state_in_memory, Poseidon2WideChip, Poseidon2WideOptions, STATE_PERMUTATION_CYCLES,
+};
+use crate::runtime::{ExecutionRecord, MemoryEntry};
+
+/// The number of bits of every limb of a timestamp, which keeps the limbs canonical in a 31-bit
+/// field.
+pub const TIMESTAMP_LIMB_BITS: usize = 24;
+
+/// The number of limbs a `u64` timestamp is split into.
+pub const NUM_TIMESTAMP_LIMBS: usize = 64usize.div_ceil(TIMESTAMP_LIMB_BITS);
+
+/// Splits `timestamp` into its limbs, least significant first, which are the public values of
+/// a proof bound to it.
+pub fn timestamp_limbs<F: AbstractField>(timestamp: u64) -> [F; NUM_TIMESTAMP_LIMBS] {
+    core::array::from_fn(|i| {
+        let limb = (timestamp >> (i * TIMESTAMP_LIMB_BITS)) & ((1 << TIMESTAMP_LIMB_BITS) - 1);
+        F::from_canonical_u32(limb as u32)
+    })
+}
+
+/// Returns the chip proving timestamped permutations, which binds the limbs of the timestamp
+/// to the first lanes of the initialization vector of every permutation.
+fn timestamp_chip() -> Poseidon2WideChip<3> {
+    Poseidon2WideChip::new(Poseidon2WideOptions {
+        num_aux_public: NUM_TIMESTAMP_LIMBS,
+        initialization_vector: true,
+        ..Default::default()
+    })
+}
+
+/// Returns the events permuting every state of `inputs` with the limbs of `timestamp` absorbed
+/// into the first lanes of its initial state.
+///
+/// The states are permuted one after the other at the same addresses of a fresh memory, as by
+/// [`permute_state_in_memory`].
+pub fn timestamp_events<F: PrimeField32>(
+    inputs: &[[F; WIDTH]],
+    timestamp: u64,
+) -> Vec<Poseidon2Event<F>> {
+    let mut iv = [F::zero(); WIDTH];
+    iv[..NUM_TIMESTAMP_LIMBS].copy_from_slice(&timestamp_limbs(timestamp));
+
+    let mut memory = HashMap::<usize, MemoryEntry<F>>::new();
+    let mut clk = F::one();
+    inputs
+        .iter()
+        .map(|input| {
+            let event = permute_state_in_memory(&mut memory, clk, 0, input, iv);
+            clk += F::from_canonical_usize(STATE_PERMUTATION_CYCLES);
+            event
+        })
+        .collect()
+}
+
+/// Proves the permutations of `inputs` under `config` with `timestamp` absorbed into their
+/// initial states, returning `None` if there are no inputs.
+pub fn prove_with_timestamp<SC>(
+    config: &SC,
+    inputs: &[[Val<SC>; WIDTH]],
+    timestamp: u64,
+) -> Option<Proof<UniConfig<SC>>>
+where
+    SC: StarkGenericConfig,
+    Val<SC>: PrimeField32,
+{
+    let mut record = ExecutionRecord::<Val<SC>>::default();
+    record.poseidon2_events = timestamp_events(inputs, timestamp);
+    timestamp_chip().prove_with_public_values(config, &record, &timestamp_limbs(timestamp))
+}
+
+/// Verifies a proof of [`prove_with_timestamp`] under `config` against the timestamp the
+/// verifier expects, so that a proof made for any other timestamp is rejected.
+pub fn verify_timestamp<SC: StarkGenericConfig>(
+    config: &SC,
+    proof: &Proof<UniConfig<SC>>,
+    timestamp: u64,
+) -> Result<(), VerificationError> {
+    timestamp_chip().verify_only(config, proof, &timestamp_limbs(timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+    use sp1_core::air::MachineAir;
+    use sp1_core::utils::BabyBearPoseidon2Inner;
+
+    use super::{
+        prove_with_timestamp, timestamp_chip, timestamp_events, timestamp_limbs, verify_timestamp,
+        NUM_TIMESTAMP_LIMBS, TIMESTAMP_LIMB_BITS,
+    };
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::WIDTH;
+    use crate::runtime::ExecutionRecord;
+    use crate::stark::utils::check_constraints;
+
+    #[test]
+    fn timestamped_proof() {
+        let config = BabyBearPoseidon2Inner::new();
+        let inputs = (0..4)
+            .map(|i| core::array::from_fn(|j| BabyBear::from_canonical_usize(i * WIDTH + j)))
+            .collect::<Vec<_>>();
+        let expected = 1_760_400_000u64;
+
+        // The timestamp is absorbed into the permutations.
+        let events = timestamp_events(&inputs, expected);
+        assert_ne!(
+            events[0].result_array,
+            Poseidon2Event::dummy_from_input(inputs[0]).result_array
+        );
+
+        let proof = prove_with_timestamp(&config, &inputs, expected).unwrap();
+        verify_timestamp(&config, &proof, expected).expect("expected proof to be valid");
+
+        // A proof is only valid against the timestamp it was made for, whichever limb differs.
+        for timestamp in [
+            expected + 1,
+            expected + (1 << TIMESTAMP_LIMB_BITS),
+            u64::MAX,
+        ] {
+            assert!(verify_timestamp(&config, &proof, timestamp).is_err());
+            let other = prove_with_timestamp(&config, &inputs, timestamp).unwrap();
+            assert!(verify_timestamp(&config, &other, expected).is_err());
+        }
+
+        // A trace of permutations absorbing another timestamp does not satisfy the constraints.
+        let chip = timestamp_chip();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        record.poseidon2_events = events;
+        let trace: RowMajorMatrix<BabyBear> =
+            chip.generate_trace(&record, &mut ExecutionRecord::default());
+        let limbs: [BabyBear; NUM_TIMESTAMP_LIMBS] = timestamp_limbs(expected);
+        assert!(check_constraints(&chip, &trace, &limbs).is_ok());
+        let limbs: [BabyBear; NUM_TIMESTAMP_LIMBS] = timestamp_limbs(expected + 1);
+        assert!(check_constraints(&chip, &trace, &limbs).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! A serialized bundle of the witness of a proof of [`Poseidon2WideChip`], for reproducing the
+//! exact proof elsewhere.
+//!
+//! The bundle holds the trace and the public values as canonical `u32`s, along with a
+//! fingerprint of the configuration and round schedule they were generated for, which the
+//! recipient checks against its own before proving.
+
+use core::any::type_name;
+
+use p3_air::BaseAir;
+use p3_field::{AbstractField, PrimeField32};
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+use p3_uni_stark::Proof;
+use serde::{Deserialize, Serialize};
+use sp1_core::air::MachineAir;
+use sp1_core::stark::{StarkGenericConfig, UniConfig, Val};
+
+use crate::poseidon2_wide::external::LAYOUT_VERSION;
+use crate::poseidon2_wide::stream::fnv1a;
+use crate::poseidon2_wide::{probe_record, Poseidon2WideChip};
+use crate::runtime::ExecutionRecord;
+
+/// The version of the encoding of [`WitnessBundle`].
+pub const WITNESS_BUNDLE_VERSION: u32 = 1;
+
+/// The trace and public values of a proof, with the fingerprint of the configuration they were
+/// generated for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WitnessBundle {
+    pub version: u32,
+    /// The fingerprint computed by [`config_fingerprint`] for the chip and configuration.
+    pub fingerprint: u64,
+    pub width: usize,
+    /// The padded trace in row-major order.
+    pub trace: Vec<u32>,
+    pub public_values: Vec<u32>,
+}
+
+/// An error returned when deserializing a [`WitnessBundle`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitnessBundleError {
+    /// The bytes do not encode a bundle.
+    Malformed,
+    /// The bundle was encoded with an unsupported version.
+    UnsupportedVersion(u32),
+    /// The bundle was generated for another configuration or round schedule.
+    FingerprintMismatch { expected: u64, actual: u64 },
+    /// The trace does not have the width of the chip, or does not hold a whole number of rows.
+    ShapeMismatch,
+    /// A value is not the canonical representative of a field element.
+    NonCanonical(u32),
+}
+
+impl WitnessBundle {
+    /// Bundles the trace of `record` generated by `chip` with `public_values`, for proving under
+    /// `config`.
+    pub fn new<SC, const DEGREE: usize>(
+        chip: &Poseidon2WideChip<DEGREE>,
+        config: &SC,
+        record: &ExecutionRecord<Val<SC>>,
+        public_values: &[Val<SC>],
+    ) -> Self
+    where
+        SC: StarkGenericConfig,
+        Val<SC>: PrimeField32,
+    {
+        let trace = <Poseidon2WideChip<DEGREE> as MachineAir<Val<SC>>>::generate_trace(
+            chip,
+            record,
+            &mut ExecutionRecord::default(),
+        );
+        Self {
+            version: WITNESS_BUNDLE_VERSION,
+            fingerprint: config_fingerprint(chip, config),
+            width: trace.width(),
+            trace: trace.values.iter().map(|x| x.as_canonical_u32()).collect(),
+            public_values: public_values.iter().map(|x| x.as_canonical_u32()).collect(),
+        }
+    }
+
+    /// Serializes the bundle.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("failed to serialize witness bundle")
+    }
+
+    /// Deserializes a bundle from `bytes`, checking that it was generated for `chip` under
+    /// `config` and that it holds a trace of the width of the chip.
+    pub fn from_bytes<SC, const DEGREE: usize>(
+        bytes: &[u8],
+        chip: &Poseidon2WideChip<DEGREE>,
+        config: &SC,
+    ) -> Result<Self, WitnessBundleError>
+    where
+        SC: StarkGenericConfig,
+        Val<SC>: PrimeField32,
+    {
+        let bundle: Self =
+            bincode::deserialize(bytes).map_err(|_| WitnessBundleError::Malformed)?;
+        if bundle.version != WITNESS_BUNDLE_VERSION {
+            return Err(WitnessBundleError::UnsupportedVersion(bundle.version));
+        }
+        let expected = config_fingerprint(chip, config);
+        if bundle.fingerprint != expected {
+            return Err(WitnessBundleError::FingerprintMismatch {
+                expected,
+                actual: bundle.fingerprint,
+            });
+        }
+        if bundle.width != <Poseidon2WideChip<DEGREE> as BaseAir<Val<SC>>>::width(chip)
+            || bundle.trace.len() % bundle.width != 0
+        {
+            return Err(WitnessBundleError::ShapeMismatch);
+        }
+        if let Some(&value) = bundle
+            .trace
+            .iter()
+            .chain(&bundle.public_values)
+            .find(|&&value| value >= Val::<SC>::ORDER_U32)
+        {
+            return Err(WitnessBundleError::NonCanonical(value));
+        }
+        Ok(bundle)
+    }
+
+    /// Proves the bundled trace against the bundled public values with `chip` under `config`.
+    ///
+    /// Proving is deterministic, so this gives the same proof as the one the bundle was
+    /// generated for.
+    pub fn prove<SC, const DEGREE: usize>(
+        &self,
+        chip: &Poseidon2WideChip<DEGREE>,
+        config: &SC,
+    ) -> Proof<UniConfig<SC>>
+    where
+        SC: StarkGenericConfig,
+        Val<SC>: PrimeField32,
+    {
+        let trace = RowMajorMatrix::new(
+            self.trace
+                .iter()
+                .map(|&x| Val::<SC>::from_canonical_u32(x))
+                .collect(),
+            self.width,
+        );
+        let public_values = self
+            .public_values
+            .iter()
+            .map(|&x| Val::<SC>::from_canonical_u32(x))
+            .collect::<Vec<_>>();
+        p3_uni_stark::prove(
+            &UniConfig(config.clone()),
+            chip,
+            &mut config.challenger(),
+            trace,
+            &public_values,
+        )
+    }
+}
+
+/// Computes the fingerprint of `config` and of the round schedule of `chip`.
+///
+/// The parameters of a configuration cannot be read back from it, so besides its type and the
+/// round schedule the fingerprint hashes the proof of a probe record holding a single
+/// permutation of the zero state. That proof depends on every parameter of the configuration,
+/// such as those of FRI, at the cost of proving a minimal trace.
+pub fn config_fingerprint<SC, const DEGREE: usize>(
+    chip: &Poseidon2WideChip<DEGREE>,
+    config: &SC,
+) -> u64
+where
+    SC: StarkGenericConfig,
+    Val<SC>: PrimeField32,
+{
+    let probe = probe_record::<Val<SC>>();
+    let aux_public = vec![Val::<SC>::zero(); chip.options.num_aux_public];
+    let public_values = chip.public_values(&probe, &aux_public);
+    let proof = chip
+        .prove_with_public_values(config, &probe, &public_values)
+        .expect("the probe record has a permutation");
+    let proof = bincode::serialize(&proof).expect("failed to serialize proof");
+
+    let schedule = chip.round_schedule();
+    fnv1a(
+        type_name::<SC>()
+            .bytes()
+            .chain(LAYOUT_VERSION.to_le_bytes())
+            .chain((schedule.num_external_rounds as u64).to_le_bytes())
+            .chain((schedule.num_internal_rounds as u64).to_le_bytes())
+            .chain(
+                schedule
+                    .round_constants
+                    .iter()
+                    .flatten()
+                    .flat_map(|constant| constant.to_le_bytes()),
+            )
+            .chain(proof),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use sp1_core::utils::{BabyBearPoseidon2, BabyBearPoseidon2Inner};
+    use sp1_primitives::RC_16_30_U32;
+
+    use super::{WitnessBundle, WitnessBundleError};
+    use crate::poseidon2::Poseidon2Event;
+    use crate::poseidon2_wide::external::{proof_commitment, NUM_ROUNDS, WIDTH};
+    use crate::poseidon2_wide::{Poseidon2WideChip, Poseidon2WideOptions};
+    use crate::runtime::ExecutionRecord;
+
+    #[test]
+    fn bundle_round_trip() {
+        let chip = Poseidon2WideChip::<3>::default();
+        let config = BabyBearPoseidon2Inner::new();
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..5 {
+            let input = core::array::from_fn(|j| BabyBear::from_canonical_u32(i * 17 + j as u32));
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+
+        let bundle = WitnessBundle::new(&chip, &config, &record, &[]);
+        let bytes = bundle.to_bytes();
+        let decoded = WitnessBundle::from_bytes(&bytes, &chip, &config).unwrap();
+        assert_eq!(decoded, bundle);
+
+        // Proving the reconstructed bundle gives the same proof as proving the record.
+        let proof = decoded.prove(&chip, &config);
+        chip.verify_only(&config, &proof, &[])
+            .expect("expected proof to be valid");
+        assert_eq!(
+            proof_commitment(&proof),
+            proof_commitment(&chip.prove(&config, &record).unwrap())
+        );
+
+        // Another configuration or round schedule refuses the bundle.
+        assert!(matches!(
+            WitnessBundle::from_bytes(&bytes, &chip, &BabyBearPoseidon2::new()),
+            Err(WitnessBundleError::FingerprintMismatch { .. })
+        ));
+        assert!(matches!(
+            WitnessBundle::from_bytes(&bytes, &chip, &BabyBearPoseidon2Inner::compact()),
+            Err(WitnessBundleError::FingerprintMismatch { .. })
+        ));
+        let other = Poseidon2WideChip::<3>::new(Poseidon2WideOptions {
+            custom_round_constants: Some(core::array::from_fn(|r| {
+                RC_16_30_U32[NUM_ROUNDS - 1 - r]
+            })),
+            ..Default::default()
+        });
+        assert!(matches!(
+            WitnessBundle::from_bytes(&bytes, &other, &config),
+            Err(WitnessBundleError::FingerprintMismatch { .. })
+        ));
+
+        let mut truncated = bundle.clone();
+        truncated.trace.truncate(WIDTH);
+        assert_eq!(
+            WitnessBundle::from_bytes(&truncated.to_bytes(), &chip, &config).unwrap_err(),
+            WitnessBundleError::ShapeMismatch
+        );
+        assert_eq!(
+            WitnessBundle::from_bytes(&bytes[..bytes.len() / 2], &chip, &config).unwrap_err(),
+            WitnessBundleError::Malformed
+        );
+    }
+}
@@ -36,6 +36,11 @@ impl<AB: SP1RecursionAirBuilder + PairBuilder> Air<AB> for RangeCheckChip<AB::F>
                 builder.when(local.u12_out_range).assert_zero(mult);
             }
 
+            // Ensure that all U8 range check lookups are not outside of the U8 range.
+            if *opcode == RangeCheckOpcode::U8 {
+                builder.when(local.u8_out_range).assert_zero(mult);
+            }
+
             builder.receive_range_check(field_op, local.value_u16, mult);
         }
     }
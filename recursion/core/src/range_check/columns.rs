@@ -17,6 +17,9 @@ pub struct RangeCheckPreprocessedCols<T> {
 
     /// A flag indicating whether the value is out of U12 range.
     pub u12_out_range: T,
+
+    /// A flag indicating whether the value is out of U8 range.
+    pub u8_out_range: T,
 }
 
 /// For each byte operation in the preprocessed table, a corresponding RangeCheckMultCols row tracks the
@@ -18,7 +18,7 @@ use self::columns::{RangeCheckPreprocessedCols, NUM_RANGE_CHECK_PREPROCESSED_COL
 use crate::range_check::trace::NUM_ROWS;
 
 /// The number of different range check operations.
-pub const NUM_RANGE_CHECK_OPS: usize = 2;
+pub const NUM_RANGE_CHECK_OPS: usize = 3;
 
 /// A chip for computing range check operations.
 ///
@@ -61,6 +61,9 @@ impl<F: Field> RangeCheckChip<F> {
                 if *opcode == RangeCheckOpcode::U12 {
                     col.u12_out_range = F::from_bool(val > 0xFFF);
                 }
+                if *opcode == RangeCheckOpcode::U8 {
+                    col.u8_out_range = F::from_bool(val > 0xFF);
+                }
 
                 let event = RangeCheckEvent::new(*opcode, val);
                 event_map.insert(event, (row_index, i));
@@ -11,12 +11,19 @@ pub enum RangeCheckOpcode {
 
     /// U16 range check
     U16 = 1,
+
+    /// U8 range check
+    U8 = 2,
 }
 
 impl RangeCheckOpcode {
     /// Get all the range check opcodes.
     pub fn all() -> Vec<Self> {
-        let opcodes = vec![RangeCheckOpcode::U12, RangeCheckOpcode::U16];
+        let opcodes = vec![
+            RangeCheckOpcode::U12,
+            RangeCheckOpcode::U16,
+            RangeCheckOpcode::U8,
+        ];
         assert_eq!(opcodes.len(), NUM_RANGE_CHECK_OPS);
         opcodes
     }
@@ -23,6 +23,7 @@ use crate::cpu::CpuEvent;
 use crate::fri_fold::FriFoldEvent;
 use crate::memory::MemoryRecord;
 use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::NUM_INTERNAL_ROUNDS;
 use crate::range_check::{RangeCheckEvent, RangeCheckOpcode};
 
 use p3_field::{ExtensionField, PrimeField32};
@@ -652,7 +653,11 @@ where
                         left,
                         right,
                         input: array,
+                        iv: [F::zero(); PERMUTATION_WIDTH],
                         result_array: result,
+                        negate_output: false,
+                        output_rotation: 0,
+                        num_internal_rounds: NUM_INTERNAL_ROUNDS,
                         input_records,
                         result_records: result_records.try_into().unwrap(),
                     });
@@ -848,12 +853,13 @@ where
 #[cfg(test)]
 mod tests {
     use p3_field::AbstractField;
+    use p3_symmetric::Permutation;
     use sp1_core::{
         stark::{RiscvAir, StarkGenericConfig},
         utils::BabyBearPoseidon2,
     };
 
-    use super::{Instruction, Opcode, RecursionProgram, Runtime};
+    use super::{Instruction, Opcode, RecursionProgram, Runtime, PERMUTATION_WIDTH, STACK_SIZE};
 
     type SC = BabyBearPoseidon2;
     type F = <SC as StarkGenericConfig>::Val;
@@ -897,4 +903,77 @@ mod tests {
             vec![vec![F::two().into(), F::two().into(), F::two().into()]].into();
         runtime.run();
     }
+
+    #[test]
+    fn test_poseidon2_compress() {
+        let zero = F::zero();
+        let zero_block = [F::zero(); 4];
+        let fp = F::from_canonical_usize(STACK_SIZE);
+        let (dst, left, right) = (30, 10, 18);
+
+        // Writes `value` to the frame slot at `offset` with an immediate addition.
+        let store = |offset: usize, value: F| {
+            Instruction::new(
+                Opcode::ADD,
+                F::from_canonical_usize(offset),
+                [value, zero, zero, zero],
+                zero_block,
+                zero,
+                zero,
+                true,
+                true,
+                "".to_string(),
+            )
+        };
+
+        let input: [F; PERMUTATION_WIDTH] =
+            core::array::from_fn(|i| F::from_canonical_usize(i + 1));
+        let mut instructions = vec![
+            store(0, fp + F::from_canonical_usize(dst)),
+            store(1, fp + F::from_canonical_usize(left)),
+            store(2, fp + F::from_canonical_usize(right)),
+        ];
+        instructions.extend((0..PERMUTATION_WIDTH).map(|i| store(left + i, input[i])));
+        instructions.push(Instruction::new(
+            Opcode::Poseidon2Compress,
+            zero,
+            [F::one(), zero, zero, zero],
+            [F::two(), zero, zero, zero],
+            zero,
+            zero,
+            false,
+            false,
+            "".to_string(),
+        ));
+        let program = RecursionProgram {
+            traces: vec![],
+            instructions,
+        };
+
+        let machine = A::machine(SC::default());
+        let perm = machine.config().perm.clone();
+        let mut runtime = Runtime::<F, EF, _>::new(&program, perm.clone());
+        runtime.run();
+
+        assert_eq!(runtime.record.poseidon2_events.len(), 1);
+        let event = &runtime.record.poseidon2_events[0];
+        let expected = perm.permute(input);
+        let clk = F::from_canonical_usize(4 * (program.instructions.len() - 1));
+        assert_eq!(event.clk, clk);
+        assert_eq!(event.dst, fp + F::from_canonical_usize(dst));
+        assert_eq!(event.left, fp + F::from_canonical_usize(left));
+        assert_eq!(event.right, fp + F::from_canonical_usize(right));
+        assert_eq!(event.input, input);
+        assert_eq!(event.result_array, expected);
+        for i in 0..PERMUTATION_WIDTH {
+            let input_record = &event.input_records[i];
+            assert_eq!(input_record.addr, fp + F::from_canonical_usize(left + i));
+            assert_eq!(input_record.timestamp, clk);
+
+            let result_record = &event.result_records[i];
+            assert_eq!(result_record.addr, fp + F::from_canonical_usize(dst + i));
+            assert_eq!(result_record.value[0], expected[i]);
+            assert_eq!(result_record.timestamp, clk + F::one());
+        }
+    }
 }
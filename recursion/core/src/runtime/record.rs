@@ -1,15 +1,18 @@
 use std::collections::BTreeMap;
 use std::sync::Arc;
 
+use itertools::Itertools;
 use p3_field::{AbstractField, PrimeField32};
 use sp1_core::stark::{MachineRecord, PROOF_MAX_NUM_PVS};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use super::RecursionProgram;
 use crate::air::Block;
 use crate::cpu::CpuEvent;
 use crate::fri_fold::FriFoldEvent;
 use crate::poseidon2::Poseidon2Event;
+use crate::poseidon2_wide::external::NUM_INTERNAL_ROUNDS;
+use crate::poseidon2_wide::{permute_with_internal_rounds, round_constants};
 use crate::range_check::RangeCheckEvent;
 
 #[derive(Default, Debug, Clone)]
@@ -38,6 +41,108 @@ impl<F: Default> ExecutionRecord<F> {
     }
 }
 
+/// An error returned by [`ExecutionRecord::validate`], pointing at the first invalid Poseidon2
+/// event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordError {
+    /// An input lane differs from the value of the memory read it comes from.
+    InputMismatch { event: usize, lane: usize },
+    /// An output lane differs from the value of the memory write it goes to.
+    OutputMismatch { event: usize, lane: usize },
+    /// The output is not the permutation of the input plus the initialization vector.
+    WrongPermutation { event: usize },
+    /// Two output lanes are written to the same address.
+    DuplicateWriteAddress { event: usize, addr: u32 },
+}
+
+impl<F: PrimeField32> ExecutionRecord<F> {
+    /// Checks that the Poseidon2 events are consistent, so that a bad record is rejected before it
+    /// is proven rather than by a failing proof.
+    ///
+    /// The lanes of every event must match the values of its memory accesses, its output must be
+    /// the permutation of its input with the default round constants and its number of internal
+    /// rounds, negated and rotated if the event asks for it, and its output lanes must be written
+    /// to distinct addresses.
+    pub fn validate(&self) -> Result<(), RecordError> {
+        let rc = round_constants::<F>();
+        for (i, event) in self.poseidon2_events.iter().enumerate() {
+            if let Some(lane) = (0..event.input.len())
+                .find(|&j| event.input_records[j].value.0[0] != event.input[j])
+            {
+                return Err(RecordError::InputMismatch { event: i, lane });
+            }
+            if let Some(lane) = (0..event.result_array.len())
+                .find(|&j| event.result_records[j].value.0[0] != event.result_array[j])
+            {
+                return Err(RecordError::OutputMismatch { event: i, lane });
+            }
+
+            if event.num_internal_rounds > NUM_INTERNAL_ROUNDS {
+                return Err(RecordError::WrongPermutation { event: i });
+            }
+            let mut expected = core::array::from_fn(|j| event.input[j] + event.iv[j]);
+            permute_with_internal_rounds(&mut expected, &rc, event.num_internal_rounds);
+            if event.negate_output {
+                expected = expected.map(|x| -x);
+            }
+            expected.rotate_left(event.output_rotation % expected.len());
+            if expected != event.result_array {
+                return Err(RecordError::WrongPermutation { event: i });
+            }
+
+            let mut addrs = HashSet::new();
+            for record in &event.result_records {
+                let addr = record.addr.as_canonical_u32();
+                if !addrs.insert(addr) {
+                    return Err(RecordError::DuplicateWriteAddress { event: i, addr });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts the number of distinct inputs among the Poseidon2 events.
+    ///
+    /// Comparing this to `poseidon2_events.len()` shows how much memoizing or deduplicating
+    /// permutations would save.
+    pub fn distinct_poseidon2_inputs(&self) -> usize {
+        self.poseidon2_events
+            .iter()
+            .map(|event| event.input.map(|x| x.as_canonical_u32()))
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// Groups the Poseidon2 events by input, returning the index of the representative event of
+    /// every group along with the number of events in it.
+    ///
+    /// The representative of a group is its first event, so its clock, operands and memory
+    /// records are the same from one run to the next, and the groups are listed in the order of
+    /// their representatives. Neither depends on the iteration order of a hash map, so a proof
+    /// built from the groups is reproducible.
+    pub fn group_poseidon2_events(&self) -> Vec<(usize, usize)> {
+        let mut groups = Vec::<(usize, usize)>::new();
+        let mut group_of_input = HashMap::new();
+        for (i, event) in self.poseidon2_events.iter().enumerate() {
+            let input = event.input.map(|x| x.as_canonical_u32());
+            let group = *group_of_input.entry(input).or_insert_with(|| {
+                groups.push((i, 0));
+                groups.len() - 1
+            });
+            groups[group].1 += 1;
+        }
+        groups
+    }
+
+    /// The number of Poseidon2 events whose input lanes are all equal.
+    pub fn uniform_poseidon2_inputs(&self) -> usize {
+        self.poseidon2_events
+            .iter()
+            .filter(|event| event.input.iter().all_equal())
+            .count()
+    }
+}
+
 impl<F: PrimeField32> MachineRecord for ExecutionRecord<F> {
     type Config = ();
 
@@ -86,3 +191,98 @@ impl<F: PrimeField32> MachineRecord for ExecutionRecord<F> {
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+
+    use super::{ExecutionRecord, RecordError};
+    use crate::air::Block;
+    use crate::poseidon2::Poseidon2Event;
+    use crate::runtime::PERMUTATION_WIDTH;
+
+    #[test]
+    fn distinct_poseidon2_inputs() {
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..100 {
+            let input = [BabyBear::from_canonical_u32(i % 7); PERMUTATION_WIDTH];
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+
+        assert_eq!(record.poseidon2_events.len(), 100);
+        assert_eq!(record.distinct_poseidon2_inputs(), 7);
+        assert_eq!(record.uniform_poseidon2_inputs(), 100);
+    }
+
+    #[test]
+    fn group_poseidon2_events() {
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..30 {
+            let input = [BabyBear::from_canonical_u32((i * 7) % 4); PERMUTATION_WIDTH];
+            let mut event = Poseidon2Event::dummy_from_input(input);
+            event.clk = BabyBear::from_canonical_u32(i);
+            record.poseidon2_events.push(event);
+        }
+
+        let groups = record.group_poseidon2_events();
+        assert_eq!(groups, vec![(0, 8), (1, 8), (2, 7), (3, 7)]);
+        for _ in 0..10 {
+            assert_eq!(record.clone().group_poseidon2_events(), groups);
+        }
+        for &(representative, _) in &groups {
+            let event = &record.poseidon2_events[representative];
+            let first = record
+                .poseidon2_events
+                .iter()
+                .find(|other| other.input == event.input)
+                .unwrap();
+            assert_eq!(first.clk, event.clk);
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let mut record = ExecutionRecord::<BabyBear>::default();
+        for i in 0..4 {
+            let input = core::array::from_fn(|j| BabyBear::from_canonical_u32(i * 16 + j as u32));
+            record
+                .poseidon2_events
+                .push(Poseidon2Event::dummy_from_input(input));
+        }
+        assert_eq!(record.validate(), Ok(()));
+
+        let mut input_mismatch = record.clone();
+        input_mismatch.poseidon2_events[2].input_records[5].value = Block::from(BabyBear::zero());
+        assert_eq!(
+            input_mismatch.validate(),
+            Err(RecordError::InputMismatch { event: 2, lane: 5 })
+        );
+
+        let mut output_mismatch = record.clone();
+        output_mismatch.poseidon2_events[1].result_records[3].value = Block::from(BabyBear::zero());
+        assert_eq!(
+            output_mismatch.validate(),
+            Err(RecordError::OutputMismatch { event: 1, lane: 3 })
+        );
+
+        let mut wrong_permutation = record.clone();
+        let event = &mut wrong_permutation.poseidon2_events[3];
+        event.result_array[0] += BabyBear::one();
+        event.result_records[0].value = Block::from(event.result_array[0]);
+        assert_eq!(
+            wrong_permutation.validate(),
+            Err(RecordError::WrongPermutation { event: 3 })
+        );
+
+        let mut duplicate_write = record;
+        duplicate_write.poseidon2_events[0].result_records[7].addr =
+            BabyBear::from_canonical_u32(2);
+        assert_eq!(
+            duplicate_write.validate(),
+            Err(RecordError::DuplicateWriteAddress { event: 0, addr: 2 })
+        );
+    }
+}
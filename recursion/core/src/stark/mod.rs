@@ -53,7 +53,9 @@ impl<F: PrimeField32 + BinomiallyExtendable<D>, const DEGREE: usize> RecursionAi
             .chain(once(RecursionAir::MemoryFinalize(MemoryGlobalChip {
                 kind: MemoryChipKind::Finalize,
             })))
-            .chain(once(RecursionAir::Poseidon2(Poseidon2WideChip::<DEGREE>)))
+            .chain(once(RecursionAir::Poseidon2(
+                Poseidon2WideChip::<DEGREE>::default(),
+            )))
             .chain(once(RecursionAir::FriFold(FriFoldChip {})))
             .chain(once(RecursionAir::RangeCheck(RangeCheckChip::default())))
             .collect()
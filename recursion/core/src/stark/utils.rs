@@ -1,9 +1,21 @@
 use crate::stark::RecursionAirWideDeg3;
+use p3_air::{Air, AirBuilder, AirBuilderWithPublicValues};
 use p3_baby_bear::BabyBear;
-use sp1_core::stark::StarkGenericConfig;
+use p3_field::{Field, PrimeField32};
+use p3_matrix::dense::{RowMajorMatrix, RowMajorMatrixView};
+use p3_matrix::stack::VerticalPair;
+use p3_matrix::Matrix;
+use p3_uni_stark::{get_symbolic_constraints, Entry, SymbolicAirBuilder, SymbolicExpression};
+use sp1_core::air::EmptyMessageBuilder;
+use sp1_core::lookup::InteractionKind;
+use sp1_core::stark::{Chip, StarkGenericConfig};
 use sp1_core::utils;
 use sp1_core::utils::BabyBearPoseidon2;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
 
 use crate::runtime::ExecutionRecord;
 use crate::runtime::RecursionProgram;
@@ -17,3 +29,383 @@ pub fn debug_constraints(program: RecursionProgram<BabyBear>, record: ExecutionR
     let mut challenger = machine.config().challenger();
     machine.debug_constraints(&pk, record, &mut challenger);
 }
+
+/// The location of the first constraint that does not hold on a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConstraintFailure {
+    /// The row on which the constraint failed.
+    pub row: usize,
+    /// The index of the failing constraint, in the order `eval` asserts them.
+    pub constraint: usize,
+}
+
+/// A builder that evaluates the constraints of an AIR on a single row and records which of them
+/// do not hold, instead of panicking on the first failure like the debug builders do.
+pub struct RowCheckBuilder<'a, F: Field> {
+    main: VerticalPair<RowMajorMatrixView<'a, F>, RowMajorMatrixView<'a, F>>,
+    public_values: &'a [F],
+    is_first_row: F,
+    is_last_row: F,
+    is_transition: F,
+    num_constraints: usize,
+    failures: Vec<usize>,
+    values: Vec<F>,
+}
+
+impl<'a, F: Field> AirBuilder for RowCheckBuilder<'a, F> {
+    type F = F;
+    type Expr = F;
+    type Var = F;
+    type M = VerticalPair<RowMajorMatrixView<'a, F>, RowMajorMatrixView<'a, F>>;
+
+    fn main(&self) -> Self::M {
+        self.main
+    }
+
+    fn is_first_row(&self) -> Self::Expr {
+        self.is_first_row
+    }
+
+    fn is_last_row(&self) -> Self::Expr {
+        self.is_last_row
+    }
+
+    fn is_transition_window(&self, size: usize) -> Self::Expr {
+        if size == 2 {
+            self.is_transition
+        } else {
+            panic!("only supports a window size of 2")
+        }
+    }
+
+    fn assert_zero<I: Into<Self::Expr>>(&mut self, x: I) {
+        let value = x.into();
+        if value != F::zero() {
+            self.failures.push(self.num_constraints);
+        }
+        self.values.push(value);
+        self.num_constraints += 1;
+    }
+}
+
+impl<'a, F: Field> AirBuilderWithPublicValues for RowCheckBuilder<'a, F> {
+    type PublicVar = F;
+
+    fn public_values(&self) -> &[Self::PublicVar] {
+        self.public_values
+    }
+}
+
+impl<'a, F: Field> EmptyMessageBuilder for RowCheckBuilder<'a, F> {}
+
+/// Evaluates the constraints of `air` on row `row` of a trace of height `height`, whose cells
+/// are `local`, followed by the row `next`.
+fn eval_row<'a, F, A>(
+    air: &A,
+    local: &'a [F],
+    next: &'a [F],
+    row: usize,
+    height: usize,
+    public_values: &'a [F],
+) -> RowCheckBuilder<'a, F>
+where
+    F: Field,
+    A: for<'b> Air<RowCheckBuilder<'b, F>>,
+{
+    let mut builder = RowCheckBuilder {
+        main: VerticalPair::new(
+            RowMajorMatrixView::new_row(local),
+            RowMajorMatrixView::new_row(next),
+        ),
+        public_values,
+        is_first_row: if row == 0 { F::one() } else { F::zero() },
+        is_last_row: if row == height - 1 {
+            F::one()
+        } else {
+            F::zero()
+        },
+        is_transition: if row == height - 1 {
+            F::zero()
+        } else {
+            F::one()
+        },
+        num_constraints: 0,
+        failures: Vec::new(),
+        values: Vec::new(),
+    };
+    air.eval(&mut builder);
+    builder
+}
+
+/// Evaluates the constraints of `air` on row `row` of `trace` and returns the indices of the
+/// constraints that do not hold.
+///
+/// Interactions are ignored, so this only checks the constraints local to the chip.
+pub fn check_row<F, A>(
+    air: &A,
+    trace: &RowMajorMatrix<F>,
+    row: usize,
+    public_values: &[F],
+) -> Vec<usize>
+where
+    F: Field,
+    A: for<'a> Air<RowCheckBuilder<'a, F>>,
+{
+    let height = trace.height();
+    let local = trace.row_slice(row);
+    let local = &(*local);
+    let next = trace.row_slice((row + 1) % height);
+    let next = &(*next);
+    eval_row(air, local, next, row, height, public_values).failures
+}
+
+/// The cell of a trace that is most likely responsible for a constraint failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailureCause {
+    /// The first row on which a constraint fails.
+    pub row: usize,
+    /// The column of the row that every failing constraint depends on, if there is one.
+    pub column: Option<usize>,
+    /// The indices of the constraints that fail on the row.
+    pub constraints: Vec<usize>,
+}
+
+/// Reduces a trace on which the constraints of `air` fail to the row and column responsible, to
+/// turn a failing proof into a small reproducer.
+///
+/// The row is the first one on which a constraint fails. A column of that row is a candidate if
+/// changing its value changes every failing constraint, and the candidate changing the fewest
+/// constraints is picked, since it explains the failure most specifically. When several
+/// candidates change as few constraints, the first of them is picked. Only the cells of the row
+/// itself are considered, not the ones of the next row.
+///
+/// Returns `None` if the constraints hold on every row.
+pub fn find_failure_cause<F, A>(
+    air: &A,
+    trace: &RowMajorMatrix<F>,
+    public_values: &[F],
+) -> Option<FailureCause>
+where
+    F: Field,
+    A: for<'a> Air<RowCheckBuilder<'a, F>>,
+{
+    let height = trace.height();
+    let row = (0..height).find(|&row| !check_row(air, trace, row, public_values).is_empty())?;
+
+    let mut local = trace.row_slice(row).to_vec();
+    let next = trace.row_slice((row + 1) % height).to_vec();
+    let (constraints, values) = {
+        let builder = eval_row(air, &local, &next, row, height, public_values);
+        (builder.failures, builder.values)
+    };
+
+    let mut column = None;
+    let mut fewest_changed = usize::MAX;
+    for c in 0..local.len() {
+        let original = local[c];
+        local[c] += F::one();
+        let perturbed = eval_row(air, &local, &next, row, height, public_values).values;
+        local[c] = original;
+
+        let changed = values
+            .iter()
+            .zip(perturbed.iter())
+            .map(|(value, perturbed)| value != perturbed)
+            .collect::<Vec<_>>();
+        let num_changed = changed.iter().filter(|&&changed| changed).count();
+        if constraints.iter().all(|&k| changed[k]) && num_changed < fewest_changed {
+            column = Some(c);
+            fewest_changed = num_changed;
+        }
+    }
+
+    Some(FailureCause {
+        row,
+        column,
+        constraints,
+    })
+}
+
+/// Checks the constraints of `air` on every row of `trace`, returning the first failure.
+pub fn check_constraints<F, A>(
+    air: &A,
+    trace: &RowMajorMatrix<F>,
+    public_values: &[F],
+) -> Result<(), ConstraintFailure>
+where
+    F: Field,
+    A: for<'a> Air<RowCheckBuilder<'a, F>>,
+{
+    for row in 0..trace.height() {
+        if let Some(&constraint) = check_row(air, trace, row, public_values).first() {
+            return Err(ConstraintFailure { row, constraint });
+        }
+    }
+    Ok(())
+}
+
+/// Accumulates the net multiplicity of every interaction of `kind` in `trace` into `balance`,
+/// counting sends positively and receives negatively.
+///
+/// After accumulating the traces of all the chips taking part in a lookup, the lookup is balanced
+/// exactly when every entry of `balance` is zero.
+pub fn accumulate_interactions<F: PrimeField32, A>(
+    chip: &Chip<F, A>,
+    trace: &RowMajorMatrix<F>,
+    kind: InteractionKind,
+    balance: &mut BTreeMap<Vec<u32>, F>,
+) {
+    accumulate_interactions_with_preprocessed(chip, None, trace, kind, balance);
+}
+
+/// Accumulates the interactions of a chip like [`accumulate_interactions`], reading the
+/// preprocessed columns of its interactions from `preprocessed`.
+pub fn accumulate_interactions_with_preprocessed<F: PrimeField32, A>(
+    chip: &Chip<F, A>,
+    preprocessed: Option<&RowMajorMatrix<F>>,
+    trace: &RowMajorMatrix<F>,
+    kind: InteractionKind,
+    balance: &mut BTreeMap<Vec<u32>, F>,
+) {
+    let interactions = chip
+        .sends()
+        .iter()
+        .map(|interaction| (interaction, true))
+        .chain(
+            chip.receives()
+                .iter()
+                .map(|interaction| (interaction, false)),
+        )
+        .filter(|(interaction, _)| interaction.kind == kind)
+        .collect::<Vec<_>>();
+
+    for row in 0..trace.height() {
+        let main = trace.row_slice(row);
+        let main = &(*main);
+        let preprocessed = preprocessed.map(|preprocessed| preprocessed.row_slice(row));
+        let preprocessed = preprocessed.as_deref().unwrap_or(&[]);
+        for (interaction, is_send) in interactions.iter() {
+            let multiplicity: F = interaction.multiplicity.apply::<F, F>(preprocessed, main);
+            if multiplicity.is_zero() {
+                continue;
+            }
+            let key = interaction
+                .values
+                .iter()
+                .map(|value| value.apply::<F, F>(preprocessed, main).as_canonical_u32())
+                .collect();
+            let entry = balance.entry(key).or_insert(F::zero());
+            if *is_send {
+                *entry += multiplicity;
+            } else {
+                *entry -= multiplicity;
+            }
+        }
+    }
+}
+
+/// Renders a symbolic constraint as an S-expression over the trace columns.
+///
+/// Variables are written as `(main <offset> <column>)`, `(preprocessed <offset> <column>)` and
+/// `(public <index>)`, constants as `(const <value>)` and the row selectors as `is_first_row`,
+/// `is_last_row` and `is_transition`.
+pub fn symbolic_to_sexpr<F: Field>(expression: &SymbolicExpression<F>) -> String {
+    match expression {
+        SymbolicExpression::Variable(v) => match v.entry {
+            Entry::Main { offset } => format!("(main {} {})", offset, v.index),
+            Entry::Preprocessed { offset } => format!("(preprocessed {} {})", offset, v.index),
+            Entry::Public => format!("(public {})", v.index),
+            _ => format!("(var {:?} {})", v.entry, v.index),
+        },
+        SymbolicExpression::IsFirstRow => "is_first_row".to_string(),
+        SymbolicExpression::IsLastRow => "is_last_row".to_string(),
+        SymbolicExpression::IsTransition => "is_transition".to_string(),
+        SymbolicExpression::Constant(c) => format!("(const {})", c),
+        SymbolicExpression::Add { x, y, .. } => {
+            format!("(add {} {})", symbolic_to_sexpr(x), symbolic_to_sexpr(y))
+        }
+        SymbolicExpression::Sub { x, y, .. } => {
+            format!("(sub {} {})", symbolic_to_sexpr(x), symbolic_to_sexpr(y))
+        }
+        SymbolicExpression::Neg { x, .. } => format!("(neg {})", symbolic_to_sexpr(x)),
+        SymbolicExpression::Mul { x, y, .. } => {
+            format!("(mul {} {})", symbolic_to_sexpr(x), symbolic_to_sexpr(y))
+        }
+    }
+}
+
+/// Returns the main trace columns that `expression` reads, at any row offset.
+pub fn main_columns<F: Field>(expression: &SymbolicExpression<F>) -> BTreeSet<usize> {
+    let mut columns = BTreeSet::new();
+    collect_main_columns(expression, &mut columns);
+    columns
+}
+
+fn collect_main_columns<F: Field>(
+    expression: &SymbolicExpression<F>,
+    columns: &mut BTreeSet<usize>,
+) {
+    match expression {
+        SymbolicExpression::Variable(v) => {
+            if let Entry::Main { .. } = v.entry {
+                columns.insert(v.index);
+            }
+        }
+        SymbolicExpression::IsFirstRow
+        | SymbolicExpression::IsLastRow
+        | SymbolicExpression::IsTransition
+        | SymbolicExpression::Constant(_) => {}
+        SymbolicExpression::Add { x, y, .. }
+        | SymbolicExpression::Sub { x, y, .. }
+        | SymbolicExpression::Mul { x, y, .. } => {
+            collect_main_columns(x, columns);
+            collect_main_columns(y, columns);
+        }
+        SymbolicExpression::Neg { x, .. } => collect_main_columns(x, columns),
+    }
+}
+
+/// Returns the constraints of `air` as S-expressions, in the order `eval` asserts them.
+///
+/// Each expression must evaluate to zero on every row of a valid trace. Interactions are not
+/// constraints of the AIR itself and are not included.
+pub fn dump_constraints<F, A>(air: &A, num_public_values: usize) -> Vec<String>
+where
+    F: Field,
+    A: Air<SymbolicAirBuilder<F>>,
+{
+    get_symbolic_constraints(air, 0, num_public_values)
+        .iter()
+        .map(symbolic_to_sexpr)
+        .collect()
+}
+
+/// Asserts that `air` asserts at most `budget` constraints, to catch an `eval` whose number of
+/// constraints blows up, such as one asserting a constraint for every pair of lanes.
+pub fn assert_constraint_budget<F, A>(air: &A, num_public_values: usize, budget: usize)
+where
+    F: Field,
+    A: Air<SymbolicAirBuilder<F>>,
+{
+    let num_constraints = get_symbolic_constraints(air, 0, num_public_values).len();
+    assert!(
+        num_constraints <= budget,
+        "{} constraints exceed the constraint budget of {}",
+        num_constraints,
+        budget
+    );
+}
+
+/// Writes the constraints of `air` to the file at `path`, one S-expression per line, so that
+/// external tools can audit the constraint system.
+pub fn write_constraints<F, A>(air: &A, num_public_values: usize, path: &Path) -> io::Result<()>
+where
+    F: Field,
+    A: Air<SymbolicAirBuilder<F>>,
+{
+    let mut file = File::create(path)?;
+    for constraint in dump_constraints(air, num_public_values) {
+        writeln!(file, "{}", constraint)?;
+    }
+    Ok(())
+}